@@ -2,6 +2,8 @@
 use coolc::parser::*;
 use coolc::cool::beam::*;
 use coolc::utils::*;
+use coolc::utils::span::SourceMap;
+use coolc::utils::diagnostic::Emitter;
 use argparse::{ArgumentParser, Store, StoreTrue};
 
 
@@ -13,6 +15,8 @@ fn main() {
     let mut input_model = String::new();
     let mut output_model = String::new();
     let mut output_file = String::new();
+    let mut source_file = String::new();
+    let mut json = false;
 
     {
         let mut ap = ArgumentParser::new();
@@ -27,6 +31,10 @@ fn main() {
             .add_option(&["-s", "--save"], Store, "Output graph model file");
         ap.refer(&mut output_file)
             .add_option(&["-o", "--output"], Store, "Output file");
+        ap.refer(&mut source_file)
+            .add_option(&["-x", "--source"], Store, "Source file the input was parsed from, to show errors in");
+        ap.refer(&mut json)
+            .add_option(&["--json"], StoreTrue, "Print errors as json lines");
         ap.refer(&mut input_file)
             .add_argument("input file", Store, "Source file to process");
         ap.parse_args_or_exit();
@@ -39,6 +47,14 @@ fn main() {
 
     let node: Node = serde_yaml::from_str(&read_file(input_file.as_str()).unwrap()).expect("Deserialize error");
 
+    let file = CoolFile::parse(&node);
+    println!("{:?}", file);
 
-    println!("{:?}", CoolFile::parse(&node));
+    let mut program = CoolProgram::default();
+    if let Err(e) = program.add_file(file) {
+        // without the source, only positions are shown
+        let src = if source_file.is_empty() { String::new() } else { read_file(source_file.as_str()).expect("Cannot open source file") };
+        Emitter::new(SourceMap::new(if source_file.is_empty() { input_file.as_str() } else { source_file.as_str() }, src), json).emit(&e.diagnostic());
+        std::process::exit(1);
+    }
 }
@@ -1,5 +1,8 @@
 
 use coolc::utils::*;
+use coolc::utils::model::{self, ModelKind};
+use coolc::utils::span::SourceMap;
+use coolc::utils::diagnostic::Emitter;
 use coolc::lexer;
 
 use argparse::{ArgumentParser, Store, StoreTrue};
@@ -22,9 +25,14 @@ fn main() {
     let mut debug = false;
     let mut verbose = false;
     let mut external = false;
+    let mut check = false;
+    let mut json = false;
+    let mut compare = String::new();
     let mut config = get_env_var("LEXER_CONFIG", "examples/cool/cool.lex");
     let mut input_model = get_env_var("LEXER_MODEL", "");
     let mut output_model = String::new();
+    let mut output_module = String::new();
+    let mut output_dot = String::new();
     let mut source = String::new();
     let mut output_file = String::new();
 
@@ -37,12 +45,22 @@ fn main() {
             .add_option(&["-v", "--verbose"], StoreTrue, "Show more info");
         ap.refer(&mut external)
             .add_option(&["-e", "--external"], StoreTrue, "Using external regex");
+        ap.refer(&mut check)
+            .add_option(&["--check"], StoreTrue, "Report overlapping, shadowed and empty rules");
+        ap.refer(&mut json)
+            .add_option(&["--json"], StoreTrue, "Print only the errors, as json lines");
+        ap.refer(&mut compare)
+            .add_option(&["--compare"], Store, "Lexer specification file to compare the rules with");
         ap.refer(&mut config)
             .add_option(&["-c", "--config"], Store, "Lexer specfication file");
         ap.refer(&mut input_model)
-            .add_option(&["-l", "--load"], Store, "Input graph model file");
+            .add_option(&["-l", "--load"], Store, "Input graph model file, yaml or binary");
         ap.refer(&mut output_model)
-            .add_option(&["-s", "--save"], Store, "Output graph model file");
+            .add_option(&["-s", "--save"], Store, "Output graph model file, binary if it ends in .bin");
+        ap.refer(&mut output_module)
+            .add_option(&["-g", "--generate"], Store, "Output rust lexer module");
+        ap.refer(&mut output_dot)
+            .add_option(&["--dot"], Store, "Output graphviz file of the rule automata");
         ap.refer(&mut output_file)
             .add_option(&["-o", "--output"], Store, "Output file");
         ap.refer(&mut source)
@@ -52,24 +70,82 @@ fn main() {
 
 
     let rules = if input_model.is_empty() { 
-        if external {
-            lexer::read_config_external(config.as_str()).expect(&format!("Cannot open file: {:} as LEXER_CONFIG", config))
-        } else {
-            lexer::read_config(config.as_str()).expect(&format!("Cannot open file: {:} as LEXER_CONFIG", config)) 
-        } 
-    } else { serde_yaml::from_str(&read_file(input_model.as_str()).expect(&format!("Cannot open file: {:} as LEXER_MODEL", input_model))).expect("Deserialize error") };
-    if !output_model.is_empty() { write_file(output_model.as_str(), serde_yaml::to_string(&rules).expect("Serialize error")).unwrap(); }
-    let tokens = lexer::tokenize(read_file(source.as_str()).expect("Cannot open source file").as_str(), &rules);
-
-    println!("#name \"{:}\"", source);
-
-    if let Ok(tokens) = tokens {
+        let (rules, warnings) = config_or_exit(&config, if external { lexer::read_config_external(&config) } else { lexer::read_config(&config) }, json);
+        for w in &warnings { eprintln!("{:}:{:}", config, w); }
+        rules
+    } else { model::load(&input_model, ModelKind::Lexer, None).unwrap_or_else(|e| panic!("{:}: {:} (as LEXER_MODEL)", input_model, e)) };
+    if !output_model.is_empty() {
+        // a model saved again from a model keeps no hash
+        let hash = if input_model.is_empty() { model::spec_hash(&[&read_file(&config).unwrap()]) } else { 0 };
+        model::save(&output_model, &rules, ModelKind::Lexer, hash).unwrap_or_else(|e| panic!("{:}: {:}", output_model, e));
+    }
+    if !output_module.is_empty() { write_file(output_module.as_str(), lexer::codegen::generate(&rules)).unwrap(); }
+    if !output_dot.is_empty() {
+        let clusters: String = rules.iter().enumerate().map(|(i, rule)| rule.expr.to_dot_cluster(&rule.name, &format!("r{:}_", i))).collect();
+        write_file(output_dot.as_str(), format!("digraph \"{:}\" {{\n\trankdir=LR;\n{:}}}\n", config, clusters)).unwrap();
+    }
+    if check {
+        let warnings = lexer::analysis::check(&rules);
+        for w in &warnings {
+            println!("warning: {:}", w.message(&rules));
+        }
+        println!("{:} rules, {:} warnings", rules.len(), warnings.len());
+    }
+    if !compare.is_empty() {
+        let (others, _) = config_or_exit(&compare, lexer::read_config(&compare), json);
+        // rules are paired by name and modes, in order
+        let mut paired = vec![false; others.len()];
+        let mut differ = 0;
+        for rule in &rules {
+            let other = (0..others.len()).find(|i| !paired[*i] && others[*i].name == rule.name && others[*i].modes == rule.modes);
+            let other = if let Some(i) = other { paired[i] = true; &others[i] } else {
+                println!("[{:}] is only in {:}", rule.name, config);
+                differ += 1;
+                continue;
+            };
+            if rule.eof != other.eof {
+                println!("[{:}] matches the end of input in one file only", rule.name);
+                differ += 1;
+            } else if let Some(w) = if rule.eof { None } else { lexer::dfa::difference_witness(&rule.expr, &other.expr) } {
+                println!("[{:}] differs on {:?}", rule.name, w);
+                differ += 1;
+            } else if rule.keywords != other.keywords {
+                println!("[{:}] has other keywords", rule.name);
+                differ += 1;
+            }
+        }
+        for (i, other) in others.iter().enumerate() {
+            if paired[i] { continue; }
+            println!("[{:}] is only in {:}", other.name, compare);
+            differ += 1;
+        }
+        println!("{:} rules, {:} differences", rules.len(), differ);
+    }
+    if source.is_empty() { return; }
+    let map = SourceMap::new(source.as_str(), read_file(source.as_str()).expect("Cannot open source file"));
+    let (tokens, errors) = lexer::tokenize_recover(map.source(), &rules);
+    let mut emitter = Emitter::new(map, json);
+
+    if !json { println!("#name \"{:}\"", source); }
+
+    {
         // println!("{:?}", tokens);
         if !output_file.is_empty() {
             write_file(output_file.as_str(), serde_yaml::to_string(&tokens).unwrap()).unwrap();
         }
+        if json {
+            for e in &errors { emitter.emit(&e.diagnostic()); }
+            if !errors.is_empty() { std::process::exit(1); }
+            return;
+        }
 
+        let failed = !errors.is_empty();
+        let mut errors = errors.iter().peekable();
         for token in &tokens {
+            while let Some(e) = errors.next_if(|e| e.start < token.span_.start) {
+                if verbose { emitter.emit(&e.diagnostic()); }
+                else { println!("#{:} {:} \"{:}\"", e.line, "ERROR", e.text); }
+            }
             if verbose {
                 println!("{:}", token);
                 continue;
@@ -78,13 +154,19 @@ fn main() {
                 "OBJECTID" | "TYPEID" | "INT_CONST" | "STR_CONST" | "BOOL_CONST" => println!("#{:} {:} {:}", token.line_, token.type_, token.value_),
                 "DARROW" | "ASSIGN" | "CLASS" | "ELSE" | "FI" | "IF" | "IN" | "INHERITS" | "ISVOID" | "LET" | "LOOP" | "POOL" | "THEN" | "WHILE" | "CASE" | "ESAC" | "NEW" | "OF" | "NOT" => println!("#{:} {:}", token.line_, token.type_),
                 "OP_LE" => println!("#{:} {:}", token.line_, "LE"),
-                "ERROR" if token.value_.starts_with("(*") => println!("#{:} {:} \"{:}\"", token.line_, token.type_, "EOF in comment"),
-                "ERROR" => println!("#{:} {:} \"{:}\"", token.line_, token.type_, token.value_),
+                "EOF_IN_COMMENT" => println!("#{:} {:} \"{:}\"", token.line_, "ERROR", "EOF in comment"),
+                "EOF_IN_STRING" => println!("#{:} {:} \"{:}\"", token.line_, "ERROR", "EOF in string constant"),
+                "UNTERMINATED_STRING" => println!("#{:} {:} \"{:}\"", token.line_, "ERROR", "Unterminated string constant"),
                 // "COMMENTBLOCK" | "WHITESPACE" => (),
                 _ => println!("#{:} '{:}'", token.line_, token.value_)
             }
             
         }
+        for e in errors {
+            if verbose { emitter.emit(&e.diagnostic()); }
+            else { println!("#{:} {:} \"{:}\"", e.line, "ERROR", e.text); }
+        }
+        if failed { std::process::exit(1); }
     }
 //     let ddfa = dfa::construct_dfa(&rexpr, &charmap);
 //     println!("{:?}", ddfa);
@@ -95,4 +177,14 @@ fn main() {
 
 
 
-}
\ No newline at end of file
+}
+
+// what a config read gives, else its error shown in the config and the exit
+fn config_or_exit<T>(path: &str, read: Result<T, lexer::LexError>, json: bool) -> T {
+    read.unwrap_or_else(|e| {
+        let mut emitter = Emitter::new(SourceMap::new(path, read_file(path).unwrap_or_default()), json);
+        let d = e.diagnostic();
+        emitter.emit(&if d.span().is_none() { d.note(format!("reading {:}", path)) } else { d });
+        std::process::exit(1)
+    })
+}
@@ -2,6 +2,9 @@
 extern crate coolc;
 
 use coolc::utils::*;
+use coolc::utils::model::{self, ModelKind, ModelCache};
+use coolc::utils::span::SourceMap;
+use coolc::utils::diagnostic::{Diagnostic, DiagnosticFormat, Emitter};
 use coolc::lexer;
 
 use coolc::parser::*;
@@ -11,6 +14,8 @@ use argparse::{ArgumentParser, Store, StoreTrue, StoreConst};
 
 use std::fs::File;
 use std::io::{BufWriter, Write};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
 
 
 macro_rules! DEBUG {
@@ -24,7 +29,7 @@ macro_rules! VERBOSE {
 
 #[derive(Debug, Clone, PartialEq, Copy)]
 enum SupportedParsers {
-    LL, LR, RD, LALR, GLR
+    LL, LR, RD, Packrat, LALR, LR1, IELR, GLR, Earley
 }
 
 fn main() {
@@ -33,6 +38,7 @@ fn main() {
     let mut debug = false;
     let mut verbose = false;
     let mut test = false;
+    let mut json = false;
     let mut do_lexer = false;
     let mut lexer_grammar_config = get_env_var("PARSER_GRAMMAR_LEXER_CONFIG", "examples/ebnf/ebnf.lex");
     let mut lexer_input_config = get_env_var("PARSER_LEXER_CONFIG", "examples/cool/cool.lex");
@@ -43,7 +49,12 @@ fn main() {
     let mut lexer_input_tokens = get_env_var("PARSER_TOKENS", "");
     let mut input_model = get_env_var("PARSER_LRTABLE", "");
     let mut output_model = String::new();
+    let mut output_dot = String::new();
+    let mut output_report = String::new();
+    let mut strict = false;
     let mut output_file = String::new();
+    let mut cache_dir = get_env_var("PARSER_CACHE", ".coolc-cache");
+    let mut no_cache = false;
 
     {
         let mut ap = ArgumentParser::new();
@@ -54,14 +65,20 @@ fn main() {
             .add_option(&["-v", "--verbose"], StoreTrue, "Show more info");
         ap.refer(&mut test)
             .add_option(&["--test"], StoreTrue, "Test");
+        ap.refer(&mut json)
+            .add_option(&["--json"], StoreTrue, "Print errors as json lines");
         ap.refer(&mut do_lexer)
             .add_option(&["--lexer"], StoreTrue, "Output lexical result");
         ap.refer(&mut use_parser)
             .add_option(&["--rd"], StoreConst(Some(SupportedParsers::RD)), "Using RD Parser")
+            .add_option(&["--packrat", "--peg"], StoreConst(Some(SupportedParsers::Packrat)), "Using packrat RD Parser, a PEG with ordered choice, & and ! predicates and left recursion")
             .add_option(&["--ll"], StoreConst(Some(SupportedParsers::LL)), "Using LL(1) Parser")
             .add_option(&["--lr"], StoreConst(Some(SupportedParsers::LALR)), "Using LALR(1) Parser")
+            .add_option(&["--lr1"], StoreConst(Some(SupportedParsers::LR1)), "Using canonical LR(1) Parser")
+            .add_option(&["--ielr"], StoreConst(Some(SupportedParsers::IELR)), "Using minimal LR(1) Parser, LALR(1) states split only where they would conflict")
             .add_option(&["--lr0"], StoreConst(Some(SupportedParsers::LR)), "Using LR(0) Parser")
-            .add_option(&["--glr"], StoreConst(Some(SupportedParsers::GLR)), "Using GLR(1) Parser");
+            .add_option(&["--glr"], StoreConst(Some(SupportedParsers::GLR)), "Using GLR(1) Parser")
+            .add_option(&["--earley"], StoreConst(Some(SupportedParsers::Earley)), "Using Earley Parser, for any context-free grammar as written");
         ap.refer(&mut lexer_grammar_config)
             .add_option(&["-e", "--ebnfconfig"], Store, "EBNF lexer specfication file");
         ap.refer(&mut lexer_input_config)
@@ -69,9 +86,15 @@ fn main() {
         ap.refer(&mut parser_config)
             .add_option(&["-c", "--config"], Store, "Syntax specfication file");
         ap.refer(&mut input_model)
-            .add_option(&["-l", "--load"], Store, "Input graph model file");
+            .add_option(&["-l", "--load"], Store, "Input graph model file, yaml or binary");
         ap.refer(&mut output_model)
-            .add_option(&["-s", "--save"], Store, "Output graph model file");
+            .add_option(&["-s", "--save"], Store, "Output graph model file, binary if it ends in .bin");
+        ap.refer(&mut output_dot)
+            .add_option(&["--dot"], Store, "Output graphviz file of the LR automaton");
+        ap.refer(&mut output_report)
+            .add_option(&["--report"], Store, "Output bison-style .output report of the LR automaton and its conflicts");
+        ap.refer(&mut strict)
+            .add_option(&["--strict"], StoreTrue, "Fail on LR conflicts not resolved by declared precedence");
         ap.refer(&mut lexer_input_model)
             .add_option(&["--loadlexer"], Store, "Input lexer model file");
         ap.refer(&mut lexer_output_model)
@@ -80,6 +103,10 @@ fn main() {
             .add_option(&["--loadtokens"], Store, "Input tokens from lexer");
         ap.refer(&mut output_file)
             .add_option(&["-o", "--output"], Store, "Output file");
+        ap.refer(&mut cache_dir)
+            .add_option(&["--cache"], Store, "Directory of the built lexer models and LR tables");
+        ap.refer(&mut no_cache)
+            .add_option(&["--nocache"], StoreTrue, "Build every model, without reading or writing the cache");
         ap.refer(&mut input_file)
             .add_argument("input file", Store, "Source file to process");
         ap.parse_args_or_exit();
@@ -91,12 +118,18 @@ fn main() {
     }
 
     /* Initilize Parser */
-    let rules = lexer::read_config(&lexer_grammar_config).expect(&format!("Cannot open file: {:} as PARSER_GRAMMAR_LEXER_CONFIG", lexer_grammar_config));
-    let tokens: Vec<Token> = lexer::tokenize(read_file(&parser_config).expect(&format!("Cannot open file: {:} as PARSER_CONFIG", parser_config)).as_str(), &rules).unwrap()
-        .into_iter()
-        .map(|mut t| {if t.type_.as_str()=="Token" { t.value_ = t.value_[1..t.value_.len()-1].to_string() } t})
-        .map(|mut t| {if t.type_.as_str()=="SpecialSequence" { t.value_ = t.value_[1..t.value_.len()-1].to_string() } t})
-        .collect();
+    // models are cached under the hash of their specs, lexer warnings only show when the rules are built
+    let cache = if no_cache { None } else { Some(ModelCache::new(&cache_dir)) };
+    let lexer_hash = |path: &str| model::spec_hash(&[&read_file(path).unwrap_or_default()]);
+
+    let rules = cached(&cache, ModelKind::Lexer, lexer_hash(&lexer_grammar_config), || {
+        read_lexer_config(&lexer_grammar_config, "PARSER_GRAMMAR_LEXER_CONFIG", json)
+    });
+    let parser_spec = read_file(&parser_config).expect(&format!("Cannot open file: {:} as PARSER_CONFIG", parser_config));
+    let tokens: Vec<Token> = lexer::tokenize(parser_spec.as_str(), &rules).unwrap_or_else(|e| panic!("{:}:{:}", parser_config, e));
+    // hash of the specs of the LR models built by a construction, the lexer reading the grammar is one
+    let grammar_lexer_hash = lexer_hash(&lexer_grammar_config).to_string();
+    let model_hash = |construction: &str| model::spec_hash(&[&grammar_lexer_hash, &parser_spec, construction]);
 
     let mut grammar = Grammar::parse(&tokens).unwrap();
 
@@ -107,11 +140,24 @@ fn main() {
     /* Initilize Lexer */
     /* Get Tokens */
 
+    // the source, to show errors in, empty if the tokens are read from a file
+    let source = SourceMap::new(input_file.as_str(), if lexer_input_tokens.is_empty() { read_file(&input_file).expect("Cannot open source file") } else { String::new() });
+    let mut emitter = Emitter::new(source, json);
+
     let input_tokens: Vec<Token> = if lexer_input_tokens.is_empty() {
-        let input_lexer_rules = if lexer_input_model.is_empty() { lexer::read_config(lexer_input_config.as_str()).expect(&format!("Cannot open file: {:} as PARSER_LEXER_CONFIG", lexer_input_config)) } 
-            else { serde_yaml::from_str(&read_file(lexer_input_model.as_str()).expect(&format!("Cannot open file: {:} as PARSER_LEXER_CONFIG", lexer_input_model))).expect("Deserialize error") };
-        if !lexer_output_model.is_empty() { write_file(lexer_output_model.as_str(), serde_yaml::to_string(&input_lexer_rules).expect("Serialize error")).unwrap(); }
-        lexer::tokenize(read_file(&input_file).expect("Cannot open source file").as_str(), &input_lexer_rules).unwrap()
+        let input_lexer_rules = if lexer_input_model.is_empty() {
+            cached(&cache, ModelKind::Lexer, lexer_hash(&lexer_input_config), || {
+                read_lexer_config(&lexer_input_config, "PARSER_LEXER_CONFIG", json)
+            })
+        } else { model::load(&lexer_input_model, ModelKind::Lexer, None).unwrap_or_else(|e| panic!("{:}: {:} (as PARSER_LEXMODEL)", lexer_input_model, e)) };
+        if !lexer_output_model.is_empty() {
+            let hash = if lexer_input_model.is_empty() { lexer_hash(&lexer_input_config) } else { 0 };
+            model::save(&lexer_output_model, &input_lexer_rules, ModelKind::Lexer, hash).unwrap_or_else(|e| panic!("{:}: {:}", lexer_output_model, e));
+        }
+        match lexer::tokenize(emitter.map.source(), &input_lexer_rules) {
+            Ok(tokens) => tokens,
+            Err(e) => abort(&mut emitter, vec![e.diagnostic()]),
+        }
     } else {
         serde_yaml::from_str(&read_file(lexer_input_tokens.as_str()).expect(&format!("Cannot open file: {:} as PARSER_TOKENS", lexer_input_tokens))).expect("Deserialize error")
     };
@@ -125,6 +171,9 @@ fn main() {
     if DEBUG!() { println!("\n{:}", grammar.productions.iter().map(|x| x.dump()).collect::<Vec<String>>().join("\n")); }
     grammar = transform::convert_to_formal_grammar(grammar);
     if DEBUG!() { println!("\n{:}", grammar.productions.iter().map(|x| x.dump()).collect::<Vec<String>>().join("\n")); }
+    if grammar.has_predicates() && use_parser != Some(SupportedParsers::RD) && use_parser != Some(SupportedParsers::Packrat) {
+        abort(&mut emitter, vec![Diagnostic::error(format!("{:}: & and ! predicates need a recursive descent parser (--rd or --packrat)", parser_config))]);
+    }
     // grammar = transform::elimate_undirect_left_recursion(grammar);
     // if DEBUG!() { println!("\n{:}", grammar.productions.iter().map(|x| x.dump()).collect::<Vec<String>>().join("\n")); }
     // grammar = transform::elimate_left_recursion(grammar);
@@ -140,11 +189,8 @@ fn main() {
             let n = match llparser::parse(&input_tokens, &grammar) {
                 Ok(n) => n,
                 Err(errs) => {
-                    for err in errs {
-                        println!("Line {:} : {:}", input_tokens[err.index].line_, err.msg);
-                    }
-                    println!("Compiling aborted");
-                    return;
+                    let errs = errs.into_iter().map(|err| parse_error(&emitter.map, &input_tokens, &err, &grammar)).collect();
+                    abort(&mut emitter, errs);
                 },
             };
             transform::retrieve_unwrap(n)
@@ -152,27 +198,102 @@ fn main() {
         Some(SupportedParsers::RD) => {
             grammar = transform::elimate_left_recursion(grammar);
             if DEBUG!() { println!("\n{:}", grammar.productions.iter().map(|x| x.dump()).collect::<Vec<String>>().join("\n")); }
-            (&grammar.productions[0] as &rdparser::Parser).parse(&input_tokens, &grammar).unwrap()
+            match (&grammar.productions[0] as &dyn rdparser::Parser).parse(&input_tokens, &grammar) {
+                Ok(n) => n,
+                Err(err) => {
+                    let err = parse_error(&emitter.map, &input_tokens, &err, &grammar);
+                    abort(&mut emitter, vec![err]);
+                },
+            }
+        },
+        Some(SupportedParsers::Packrat) => {
+            match rdparser::Packrat::new(&input_tokens, &grammar).parse() {
+                Ok(n) => n,
+                Err(err) => {
+                    let err = parse_error(&emitter.map, &input_tokens, &err, &grammar);
+                    abort(&mut emitter, vec![err]);
+                },
+            }
         },
-        Some(SupportedParsers::LALR) => {
+        Some(kind @ SupportedParsers::LALR) | Some(kind @ SupportedParsers::LR1) | Some(kind @ SupportedParsers::IELR) => {
             // let graph = if input_model.is_empty() { lrparser::construct_lalr_1(&grammar) } else { serde_yaml::from_str(&read_file(input_model.as_str()).unwrap()).expect("Deserialize error") };
-            let table = if input_model.is_empty() { lrparser::construct_table(&lrparser::construct_lalr_1(&grammar)).unwrap() } else { serde_yaml::from_str(&read_file(input_model.as_str()).expect(&format!("Cannot open file: {:} as PARSER_LRTABLE", input_model))).expect("Deserialize error") };
-            if !output_model.is_empty() { write_file(output_model.as_str(), serde_yaml::to_string(&table).expect("Serialize error")).unwrap(); }
-            let n = lrparser::parse_with_table(&input_tokens, &table).unwrap();
+            let (construction, merge) = match kind {
+                SupportedParsers::LR1 => ("lr1", lrparser::LRMerge::Canonical),
+                SupportedParsers::IELR => ("ielr", lrparser::LRMerge::Minimal),
+                _ => ("lalr", lrparser::LRMerge::LALR),
+            };
+            let table = if input_model.is_empty() && output_dot.is_empty() && output_report.is_empty() && !strict {
+                cached(&cache, ModelKind::LRTable, model_hash(construction), || lrparser::construct_table(&lrparser::construct_lr_1_merging(&grammar, merge)).unwrap())
+            } else if input_model.is_empty() {
+                let graph = lrparser::construct_lr_1_merging(&grammar, merge);
+                if !output_dot.is_empty() { write_file(output_dot.as_str(), graph.to_dot(&parser_config)).unwrap(); }
+                let (table, conflicts) = lrparser::construct_table_with_conflicts(&graph);
+                if !output_report.is_empty() { write_file(output_report.as_str(), lrparser::output_report(&graph, &table, &conflicts)).unwrap(); }
+                if strict && conflicts.iter().any(|x| !x.resolved) {
+                    let errs = conflicts.iter().filter(|x| !x.resolved).map(conflict_error).collect();
+                    abort(&mut emitter, errs);
+                }
+                table
+            } else {
+                if !output_dot.is_empty() || !output_report.is_empty() { println!("No LR automaton to export from a loaded table"); }
+                model::load(&input_model, ModelKind::LRTable, Some(model_hash(construction))).unwrap_or_else(|e| panic!("{:}: {:} (as PARSER_LRTABLE)", input_model, e))
+            };
+            if !output_model.is_empty() { model::save(&output_model, &table, ModelKind::LRTable, model_hash(construction)).unwrap_or_else(|e| panic!("{:}: {:}", output_model, e)); }
+            let n = match lrparser::parse_with_table(&input_tokens, &table) {
+                Ok(n) => n,
+                Err(errs) => {
+                    let errs = errs.into_iter().map(|err| parse_error(&emitter.map, &input_tokens, &err, &grammar)).collect();
+                    abort(&mut emitter, errs);
+                },
+            };
             transform::retrieve_unwrap(n)
         },
         Some(SupportedParsers::GLR) => {
-            let graph = if input_model.is_empty() { lrparser::construct_lalr_1(&grammar) } else { serde_yaml::from_str(&read_file(input_model.as_str()).expect(&format!("Cannot open file: {:} as PARSER_LRTABLE", input_model))).expect("Deserialize error") };
+            let graph = if input_model.is_empty() { cached(&cache, ModelKind::LRAutomaton, model_hash("lalr"), || lrparser::construct_lalr_1(&grammar)) } else { model::load(&input_model, ModelKind::LRAutomaton, Some(model_hash("lalr"))).unwrap_or_else(|e| panic!("{:}: {:} (as PARSER_LRTABLE)", input_model, e)) };
             // let table = if input_model.is_empty() { lrparser::construct_table(&lrparser::construct_lr_0(&grammar)).unwrap() } else { serde_yaml::from_str(&read_file(input_model.as_str()).unwrap()).expect("Deserialize error") };
-            if !output_model.is_empty() { write_file(output_model.as_str(), serde_yaml::to_string(&graph).expect("Serialize error")).unwrap(); }
-            let n = glrparser::parse_with_graph(&input_tokens, &graph).unwrap();
+            if !output_model.is_empty() { model::save(&output_model, &graph, ModelKind::LRAutomaton, model_hash("lalr")).unwrap_or_else(|e| panic!("{:}: {:}", output_model, e)); }
+            if !output_dot.is_empty() { write_file(output_dot.as_str(), graph.to_dot(&parser_config)).unwrap(); }
+            let forest = match glrparser::parse_forest(&input_tokens, &graph) {
+                Ok(forest) => forest,
+                Err(err) => {
+                    let err = parse_error(&emitter.map, &input_tokens, &err, &grammar);
+                    abort(&mut emitter, vec![err]);
+                },
+            };
+            if VERBOSE!() { println!("{:} derivations in a forest of {:} nodes", forest.count(), forest.nodes.len()); }
+            let n = match forest.disambiguate() {
+                Some(n) => n,
+                None => abort(&mut emitter, vec![Diagnostic::error("No tree without a cycle in the parse forest")]),
+            };
+            transform::retrieve_unwrap(n)
+        },
+        Some(SupportedParsers::Earley) => {
+            let forest = match earleyparser::parse_forest(&input_tokens, &grammar) {
+                Ok(forest) => forest,
+                Err(err) => {
+                    let err = parse_error(&emitter.map, &input_tokens, &err, &grammar);
+                    abort(&mut emitter, vec![err]);
+                },
+            };
+            if VERBOSE!() { println!("{:} derivations in a forest of {:} nodes", forest.count(), forest.nodes.len()); }
+            let n = match forest.disambiguate() {
+                Some(n) => n,
+                None => abort(&mut emitter, vec![Diagnostic::error("No tree without a cycle in the parse forest")]),
+            };
             transform::retrieve_unwrap(n)
         },
         Some(SupportedParsers::LR) => {
-            let graph = if input_model.is_empty() { lrparser::construct_lr_0(&grammar) } else { serde_yaml::from_str(&read_file(input_model.as_str()).expect(&format!("Cannot open file: {:} as PARSER_LRTABLE", input_model))).expect("Deserialize error") };
+            let graph = if input_model.is_empty() { cached(&cache, ModelKind::LRAutomaton, model_hash("lr0"), || lrparser::construct_lr_0(&grammar)) } else { model::load(&input_model, ModelKind::LRAutomaton, Some(model_hash("lr0"))).unwrap_or_else(|e| panic!("{:}: {:} (as PARSER_LRTABLE)", input_model, e)) };
             // let table = if input_model.is_empty() { lrparser::construct_table(&lrparser::construct_lr_0(&grammar)).unwrap() } else { serde_yaml::from_str(&read_file(input_model.as_str()).unwrap()).expect("Deserialize error") };
-            if !output_model.is_empty() { write_file(output_model.as_str(), serde_yaml::to_string(&graph).expect("Serialize error")).unwrap(); }
-            let n = lrparser::parse_with_graph(&input_tokens, &graph).unwrap();
+            if !output_model.is_empty() { model::save(&output_model, &graph, ModelKind::LRAutomaton, model_hash("lr0")).unwrap_or_else(|e| panic!("{:}: {:}", output_model, e)); }
+            if !output_dot.is_empty() { write_file(output_dot.as_str(), graph.to_dot(&parser_config)).unwrap(); }
+            let n = match lrparser::parse_with_graph(&input_tokens, &graph) {
+                Ok(n) => n,
+                Err(errs) => {
+                    let errs = errs.into_iter().map(|err| parse_error(&emitter.map, &input_tokens, &err, &grammar)).collect();
+                    abort(&mut emitter, errs);
+                },
+            };
             transform::retrieve_unwrap(n)
         },
         _ => panic!("Parser not specfied")
@@ -194,7 +315,7 @@ fn main() {
     }
 
     if test {
-        let ret = functor::REParser::parse(&ret);
+        let ret = functor::REParser::parse(&ret).map_err(|e| e.to_string());
         write_file(output_file.as_str(), serde_yaml::to_string(&ret).unwrap()).unwrap();
 
     }
@@ -202,6 +323,58 @@ fn main() {
 
 }
 
+/*
+ * A parse error at the token index of the input, past the last token it is
+ * at the end of the source.
+ */
+fn parse_error(source: &SourceMap, tokens: &[Token], err: &ParseError, grammar: &Grammar) -> Diagnostic {
+    let len = source.source().len();
+    let span = match tokens.get(err.index) {
+        Some(token) => token.span_,
+        None if len > 0 => source.span(len - 1, len),
+        None => tokens.last().map(|t| t.span_).unwrap_or_default(),
+    };
+    let d = Diagnostic::error(err.describe(grammar)).primary(span, "");
+    if let Some(fix) = err.describe_fix(grammar) { d.note(fix) } else { d }
+}
+
+// an LR conflict of the grammar, it has no place in the source
+fn conflict_error(conflict: &lrparser::Conflict) -> Diagnostic {
+    let mut d = Diagnostic::error(format!("{:} conflict in state {:} on {:}", conflict.kind(), conflict.state, conflict.lookahead))
+        .note(format!("actions {:}", conflict.actions.iter().map(|x| match x {
+            lrparser::LRAction::Shift(next) => format!("shift to state {:}", next),
+            lrparser::LRAction::Reduce(rule) => format!("reduce {:} -> {:}", rule.name, rule.expr),
+        }).collect::<Vec<_>>().join(", ")));
+    if !conflict.prefix.is_empty() {
+        d = d.note(format!("after {:}", conflict.prefix.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(" ")));
+    }
+    conflict.items.iter().fold(d, |d, item| d.note(format!("item {:}", item.dump())))
+}
+
+// show the diagnostics and exit with a failure status
+fn abort(emitter: &mut Emitter, diagnostics: Vec<Diagnostic>) -> ! {
+    for d in &diagnostics {
+        emitter.emit(d);
+    }
+    if emitter.format == DiagnosticFormat::Human { println!("Compiling aborted"); }
+    std::process::exit(1)
+}
+
+// the rules of a lexer config, its warnings shown on stderr, an error shown in the config aborts
+fn read_lexer_config(path: &str, role: &str, json: bool) -> Vec<lexer::RegularRule> {
+    let (rules, warnings) = lexer::read_config(path).unwrap_or_else(|e| {
+        let mut emitter = Emitter::new(SourceMap::new(path, read_file(path).unwrap_or_default()), json);
+        abort(&mut emitter, vec![e.diagnostic().note(format!("reading {:} as {:}", path, role))])
+    });
+    for w in &warnings { eprintln!("{:}:{:}", path, w); }
+    rules
+}
+
+// the model from the cache if any, else built
+fn cached<T: Serialize + DeserializeOwned, F: FnOnce() -> T>(cache: &Option<ModelCache>, kind: ModelKind, hash: u64, build: F) -> T {
+    if let Some(cache) = cache { cache.get_or_build(kind, hash, build) } else { build() }
+}
+
 fn print_syntax_tree(node: &Node, indent: &mut usize, tokens: &[Token]) {
     match &node.value {
         NodeType::Terminal(t) => {
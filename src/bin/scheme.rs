@@ -6,6 +6,9 @@
 
 use argparse::{ArgumentParser, Store, StoreTrue};
 use coolc::utils::*;
+use coolc::utils::model::{self, ModelKind};
+use coolc::utils::span::SourceMap;
+use coolc::utils::diagnostic::Emitter;
 use coolc::scheme;
 use coolc::lexer::Token;
 use coolc::lexer;
@@ -13,7 +16,7 @@ use coolc::lexer;
 use coolc::scheme::beam::*;
 use std::io::*;
 
-fn repl(rules: &Vec<lexer::RegularRule>, env: self::scheme::env::Env, test: bool) {
+fn repl(rules: &Vec<lexer::RegularRule>, env: self::scheme::env::Env, test: bool, json: bool) {
     // if !test {
     // let code = vec![
     //     "(define (exit))",
@@ -38,6 +41,10 @@ fn repl(rules: &Vec<lexer::RegularRule>, env: self::scheme::env::Env, test: bool
     // }
     // }
 
+    // one lexer for the whole session, so line numbers go on between inputs
+    let mut lexer = lexer::stream::Lexer::new(rules);
+    // and spans point into everything read so far
+    let mut session = String::new();
     loop {
         let mut input = String::new();
 
@@ -50,14 +57,23 @@ fn repl(rules: &Vec<lexer::RegularRule>, env: self::scheme::env::Env, test: bool
         }
         // if input.trim().is_empty() { return; }
 
-        let tokens: Vec<Token> = lexer::tokenize(input.as_str(), &rules).unwrap();
+        lexer.feed(input.as_str());
+        session.push_str(&input);
+        let (tokens, errors): (Vec<_>, Vec<_>) = lexer.by_ref().partition(|t| t.is_ok());
+        if let Some(Err(e)) = errors.into_iter().next() {
+            Emitter::new(SourceMap::new("<stdin>", session.as_str()), json).emit(&e.diagnostic());
+            continue;
+        }
+        let tokens: Vec<Token> = tokens.into_iter().map(|t| t.unwrap()).collect();
 
         // println!("\t{:?}", tokens);
 
-        let program = if let Ok(p) = self::scheme::parser::parse(&tokens) { p }
-        else {
-            println!("Error: parsing");
-            continue;
+        let program = match self::scheme::parser::parse(&tokens) {
+            Ok(p) => p,
+            Err(e) => {
+                Emitter::new(SourceMap::new("<stdin>", session.as_str()), json).emit(&e.diagnostic());
+                continue;
+            },
         };
 
         // println!("\t{:?}", program.borrow());
@@ -74,7 +90,7 @@ fn repl(rules: &Vec<lexer::RegularRule>, env: self::scheme::env::Env, test: bool
                 println!("=> {:?}", value.borrow());
                 drop(value)
             },
-            Err(e) => println!("Error: {:?}", e)
+            Err(e) => Emitter::new(SourceMap::new("<stdin>", session.as_str()), json).emit(&e.diagnostic()),
         }
         
     }
@@ -86,7 +102,9 @@ fn main() {
     let mut verbose = false;
     let mut repl = false;
     let mut test = false;
+    let mut json = false;
     let mut lexer_input_model = get_env_var("LEXER_MODEL", "examples/scheme/scheme.lexmodel");
+    let mut lexer_config = get_env_var("LEXER_CONFIG", "examples/scheme/scheme.lex");
     let mut lexer_input_tokens = get_env_var("PARSER_TOKENS", "");
     let mut input_file = String::new();
     let mut output_file = String::new();
@@ -102,6 +120,10 @@ fn main() {
             .add_option(&["-r", "--repl"], StoreTrue, "Show more info");
         ap.refer(&mut test)
             .add_option(&["--test"], StoreTrue, "Test");
+        ap.refer(&mut json)
+            .add_option(&["--json"], StoreTrue, "Print errors as json lines");
+        ap.refer(&mut lexer_config)
+            .add_option(&["-x", "--lexerconfig"], Store, "Lexer specfication file the model is checked against");
         ap.refer(&mut lexer_input_model)
             .add_option(&["--loadlexer"], Store, "Input lexer model file");
         ap.refer(&mut lexer_input_tokens)
@@ -120,19 +142,30 @@ fn main() {
 
     let env = self::scheme::env::Environment::new();
 
-    let rules: Vec<lexer::RegularRule> = serde_yaml::from_str(&read_file(lexer_input_model.as_str()).expect(&format!("Cannot open file: {:} as LEXER_MODEL", lexer_input_model))).expect("Deserialize error");
+    // a model built from another spec than the one at hand is stale
+    let hash = read_file(&lexer_config).ok().map(|spec| model::spec_hash(&[&spec]));
+    let rules: Vec<lexer::RegularRule> = model::load(&lexer_input_model, ModelKind::Lexer, hash).unwrap_or_else(|e| panic!("{:}: {:} (as LEXER_MODEL)", lexer_input_model, e));
 
 
     if !input_file.is_empty() {
-        let tokens: Vec<Token> = lexer::tokenize(read_file(input_file.as_str()).expect("Cannot open source file").as_str(), &rules).unwrap();
+        let map = SourceMap::new(input_file.as_str(), read_file(input_file.as_str()).expect("Cannot open source file"));
+        let mut emitter = Emitter::new(map, json);
+        let tokens: Vec<Token> = match lexer::tokenize(emitter.map.source(), &rules) {
+            Ok(tokens) => tokens,
+            Err(e) => {
+                emitter.emit(&e.diagnostic());
+                std::process::exit(1);
+            },
+        };
 
         let program = self::scheme::parser::parse(&tokens).unwrap();
         if debug { println!("{:?}", program); }
 
         let ret = self::scheme::engine::eval(program, env.clone());
+        if let Err(e) = &ret { emitter.emit(&e.diagnostic()); }
         println!("\nAnswer: {:?}", ret.map(|x| x.borrow().clone()));
     }
     if repl {
-        self::repl(&rules, env, test);
+        self::repl(&rules, env, test, json);
     }
 }
@@ -3,6 +3,8 @@ use std::fmt;
 use std::error::Error;
 use crate::parser::*;
 use crate::lexer::Token;
+use crate::utils::span::Span;
+use crate::utils::diagnostic::Diagnostic;
 
 // macro_rules! check_default {
 // 	($id: ident, $expr: expr, $default: expr) => {
@@ -375,28 +377,17 @@ impl CoolProgram {
 	pub fn add_file(&mut self, f: CoolFile) -> Result<(), CoolCompileError> {
 		for cls in f.classes_ {
 			let name = cls.name_.clone();
+			let pos = cls.pos_;
 			if let Some(cls) = self.classes_.insert(name, cls) {
-				return Err(CoolCompileError::new(format!("Multiply definition for class {:}", cls.name_)))
+				return Err(CoolCompileError::new(format!("Multiply definition for class {:}", cls.name_)).at(pos).related(cls.pos_, "first defined here"))
 			}
 		}
 		Ok(())
 	}
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Default)]
-pub struct CoolPosition {
-	pub lines: usize,
-	pub chars: usize,
-}
-
-impl<'a> From<&'a Node> for CoolPosition {
-	fn from(node: &'a Node) -> Self {
-		CoolPosition {
-			lines: node.index,
-			chars: 0,
-		}
-	}
-}
+// where the source of an ast node is, empty when the node has no located token
+pub type CoolPosition = Span;
 
 pub type CoolClassInheritMap = HashMap<CoolTypename, Vec<CoolTypename>>;
 
@@ -533,22 +524,44 @@ impl CoolEnvironment {
 
 #[derive(Debug)]
 pub struct CoolCompileError {
-	msg: String,
-	index: usize
+	pub msg: String,
+	// the ast node at fault, if it is known and located
+	pub pos: Option<CoolPosition>,
+	// other nodes involved, with what they are
+	pub related: Vec<(CoolPosition, String)>,
 }
 
 impl CoolCompileError {
 	pub fn new<S: Into<String>>(s: S) -> Self {
 		CoolCompileError {
 			msg: s.into(), 
-			index: 0
+			pos: None,
+			related: Vec::new(),
 		}
 	}
+
+	pub fn related<S: Into<String>>(mut self, pos: CoolPosition, what: S) -> Self {
+		self.related.push((pos, what.into()));
+		self
+	}
+
+	pub fn diagnostic(&self) -> Diagnostic {
+		let d = Diagnostic::error(self.msg.clone()).primary(self.pos.unwrap_or_default(), "");
+		self.related.iter().fold(d, |d, (pos, what)| d.secondary(*pos, what.clone()))
+	}
+
+	pub fn at(mut self, pos: CoolPosition) -> Self {
+		if !pos.is_empty() { self.pos = Some(pos); }
+		self
+	}
 }
 
 impl fmt::Display for CoolCompileError {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-		write!(f, "{:?}", self)
+		match &self.pos {
+			Some(pos) => write!(f, "{:}: {:}", pos, self.msg),
+			None => write!(f, "{:}", self.msg),
+		}
 	}
 }
 
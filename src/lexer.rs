@@ -3,20 +3,62 @@ pub mod re;
 pub mod nfa;
 pub mod dfa;
 pub mod graph;
+pub mod scanner;
+pub mod stream;
+pub mod codegen;
+pub mod dot;
+pub mod regex;
+pub mod analysis;
 
 
-use std::collections::HashMap;
+use std::collections::{HashMap, BTreeMap};
 use self::re::*;
 use std::fmt;
 use std::io;
+use std::hash;
 use crate::utils::*;
+use crate::utils::span::Span;
+use crate::utils::diagnostic::{Diagnostic, Severity};
 use crate::parser::functor::REParser;
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
 pub struct Token {
     pub type_: String,
     pub value_: String,
+    // line where the token ends
     pub line_: usize,
+    // where the token is in source, span_.line is the line where it starts
+    #[serde(default)]
+    pub span_: Span,
+    // value computed by the value actions of the rule, value_ stays the matched text
+    #[serde(default)]
+    pub literal_: Option<TokenValue>,
+}
+
+impl Token {
+    // the string value of the token if the rule computed one, else the matched text
+    pub fn text(&self) -> &str {
+        if let Some(TokenValue::Str(s)) = &self.literal_ { s } else { &self.value_ }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum TokenValue {
+    Str(String),
+    Int(i64),
+    Float(f64),
+}
+
+impl Eq for TokenValue {}
+
+impl hash::Hash for TokenValue {
+    fn hash<H: hash::Hasher>(&self, state: &mut H) {
+        match self {
+            TokenValue::Str(s) => s.hash(state),
+            TokenValue::Int(x) => x.hash(state),
+            TokenValue::Float(x) => x.to_bits().hash(state),
+        }
+    }
 }
 
 impl fmt::Display for Token {
@@ -25,224 +67,488 @@ impl fmt::Display for Token {
     }
 }
 
+pub const INITIAL_MODE: &str = "INITIAL";
+pub const ANY_MODE: &str = "*";
+pub const EOF_RULE: &str = "<<EOF>>";
+
+/*
+ * Start condition actions, written after the rule name in config:
+ *     <COMMENT>COMMENT_END:pop:more
+ * push(M) / pop  enter / leave mode M on a stack of modes
+ * begin(M)       replace the current mode by M
+ * more           keep the text and prepend it to the next token
+ * Value actions, applied in order to the text of the token:
+ * strip          drop the first and the last char, e.g. quotes
+ * unescape       replace backslash sequences, \n \t \r \b \f \0, by their char,
+ *                and any other escaped char by itself
+ * lower          lowercase
+ * int / float    parse the text as a number, only as the last value action
+ */
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LexAction {
+    Push(String), Pop, Begin(String), More,
+    Strip, Unescape, Lower, Int, Float
+}
+
+fn unescape(s: &str) -> String {
+    let mut ret = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            ret.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => ret.push('\n'),
+            Some('t') => ret.push('\t'),
+            Some('r') => ret.push('\r'),
+            Some('b') => ret.push('\u{8}'),
+            Some('f') => ret.push('\u{c}'),
+            Some('0') => ret.push('\0'),
+            Some(c) => ret.push(c),
+            None => ret.push('\\'),
+        }
+    }
+    ret
+}
+
+/*
+ * Keywords of identifier rules, written as a section of the rules:
+ *     %keywords:nocase OBJECTID TYPEID
+ *     CLASS           class
+ *     %
+ * A token of one of the rules whose text is in the table gets the keyword
+ * as its type. With nocase the text is looked up in lowercase.
+ */
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeywordTable {
+    pub nocase: bool,
+    // word -> token type, lowercase words if nocase
+    pub words: BTreeMap<String, String>,
+}
+
+impl KeywordTable {
+    pub fn lookup(&self, text: &str) -> Option<&str> {
+        if self.nocase { self.words.get(&text.to_lowercase()) } else { self.words.get(text) }.map(|s| s.as_str())
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RegularRule {
     pub name: String,
     pub expr: StateTransferGraph,
     pub greedy: bool,
     pub ignore: bool,
+    // modes the rule is active in, none means INITIAL only
+    #[serde(default)]
+    pub modes: Vec<String>,
+    #[serde(default)]
+    pub actions: Vec<LexAction>,
+    // matches the end of input instead of expr
+    #[serde(default)]
+    pub eof: bool,
+    #[serde(default)]
+    pub keywords: Option<KeywordTable>,
 }
 
-
-pub fn tokenize(src: &str, rules: &Vec<RegularRule>) -> Result<Vec<Token>, ()> {
-    
-    #[derive(Debug)]
-    struct MatchProcess {
-        rule: usize,
-        pos: Option<usize>,
-        valid: bool,
+impl RegularRule {
+    pub fn active_in(&self, mode: &str) -> bool {
+        if self.modes.is_empty() { mode == INITIAL_MODE } else { self.modes.iter().any(|m| m == mode || m == ANY_MODE) }
     }
 
-    let src = src.to_string() + "\0";
+    // type of a token matching text, a keyword or the rule name
+    pub fn token_type(&self, text: &str) -> &str {
+        self.keywords.as_ref().and_then(|k| k.lookup(text)).unwrap_or(&self.name)
+    }
 
-    let mut line = 1;
-    let mut tokens = Vec::new();
-    let mut buffer = String::new();
+    /*
+     * The value of a token matching text, None if the rule has no value action.
+     */
+    pub fn literal(&self, text: &str) -> Result<Option<TokenValue>, String> {
+        let mut value = None;
+        for action in &self.actions {
+            let s = if let Some(TokenValue::Str(s)) = &value { s.as_str() } else { text };
+            value = Some(match action {
+                LexAction::Strip => {
+                    let mut chars = s.chars();
+                    chars.next();
+                    chars.next_back();
+                    TokenValue::Str(chars.as_str().to_string())
+                },
+                LexAction::Unescape => TokenValue::Str(unescape(s)),
+                LexAction::Lower => TokenValue::Str(s.to_lowercase()),
+                LexAction::Int => TokenValue::Int(s.parse().map_err(|_| format!("Invalid integer {:?}", s))?),
+                LexAction::Float => TokenValue::Float(s.parse().map_err(|_| format!("Invalid float {:?}", s))?),
+                _ => continue
+            });
+        }
+        Ok(value)
+    }
 
-    let mut finish = true;
+    /*
+     * Rule head syntax: [-][<MODE,...>]NAME[?][:action]...
+     */
+    fn parse_head(head: &str) -> Result<Self, String> {
+        let mut parts = head.split(':');
+        let mut ty = parts.next().unwrap().to_string();
+
+        let actions = parts.map(|a| {
+            let arg = |a: &str| a[a.find('(').unwrap() + 1..a.len() - 1].to_string();
+            Ok(match a {
+                "pop" => LexAction::Pop,
+                "more" => LexAction::More,
+                "strip" => LexAction::Strip,
+                "unescape" => LexAction::Unescape,
+                "lower" => LexAction::Lower,
+                "int" => LexAction::Int,
+                "float" => LexAction::Float,
+                _ if a.starts_with("push(") && a.ends_with(')') => LexAction::Push(arg(a)),
+                _ if a.starts_with("begin(") && a.ends_with(')') => LexAction::Begin(arg(a)),
+                _ => return Err(format!("Unknown lexer action [{:}]", a))
+            })
+        }).collect::<Result<Vec<LexAction>, _>>()?;
+        let values: Vec<&LexAction> = actions.iter().filter(|a| match a {
+            LexAction::Strip | LexAction::Unescape | LexAction::Lower | LexAction::Int | LexAction::Float => true,
+            _ => false
+        }).collect();
+        if values.iter().rev().skip(1).any(|a| **a == LexAction::Int || **a == LexAction::Float) {
+            return Err(String::from("A number action must be the last value action"));
+        }
 
-    let reset = || -> Vec<MatchProcess> { (0..rules.len()).map(|i| MatchProcess {
-        rule: i,
-        pos: None,
-        valid: true
-    }).collect()};
-    let mut last_matched: Vec<MatchProcess> = reset();
+        let greedy = if ty.ends_with('?') {
+            ty = ty[0..ty.len()-1].to_string();
+            false } else { true };
 
-    let mut i = 0;
-    while i < src.len() {
-        let c = src.get(i..i+1).unwrap();
+        let ignore = if ty.starts_with('-') {
+            ty = ty[1..ty.len()].to_string();
+            true } else { false };
 
-        buffer.push_str(c);
-        // println!("{:?}", buffer);
-        let matched: Vec<MatchProcess> = last_matched.iter().map(|MatchProcess {rule, pos, valid}| {
-            if !valid {
-                return MatchProcess {
-                    rule: *rule,
-                    pos: *pos,
-                    valid: *valid
-                };
-            }
-            if !rules[*rule].greedy && !(pos == &None) {
-                return MatchProcess {
-                    rule: *rule,
-                    pos: *pos,
-                    valid: false
-                };
-            }
-            let ret = dfa::match_dfa(&rules[*rule].expr, buffer.chars());
-            match ret {
-                dfa::MatchResult::Err => MatchProcess {
-                    rule: *rule,
-                    pos: *pos,
-                    valid: false
-                },
-                dfa::MatchResult::Unfinished => MatchProcess {
-                    rule: *rule,
-                    pos: *pos,
-                    valid: *valid
-                },
-                dfa::MatchResult::Ok => MatchProcess {
-                    rule: *rule,
-                    pos: Some(i),
-                    valid: *valid
-                }
-            }
-        }).collect();
-        // println!("cur: {:?}", matched);
-        // println!("last: {:?}", last_matched);
-
-        finish =
-        if let Some(_) = matched.iter().find(|MatchProcess {rule, pos, valid}| *valid) {
-            if c == "\n" { line += 1; }
-            i += 1;
-            false
-        } else if let Some(MatchProcess {rule, pos, ..}) = last_matched.iter().filter(|MatchProcess {pos, ..}|  !(pos == &None)).max_by(|MatchProcess {rule: rx, pos: px, ..}, MatchProcess {rule: ry, pos: py, ..}| px.unwrap().cmp(&py.unwrap()).then(ry.cmp(rx))) {
-            // buffer.pop();
-            // println!("{:?} {:?} {:?}", buffer.len(), i, pos);
-            let token = Token {
-                type_: rules[*rule].name.clone(),
-                value_: buffer[0..(buffer.len() + pos.unwrap() - i)].to_string(),
-                line_: line,
-            };
-            if !rules[*rule].ignore { tokens.push(token); }
-            buffer.clear();
-            i = pos.unwrap() + 1;
-            true
-        } else {
-            if c == "\n" { line += 1; }
-            if finish {
-                i += 1;
-            } else {
-                buffer.pop();
-            }
-            if buffer != "\0" {
-                // println!("{:?}", last_matched);
-                // println!("len: {:}  [{:}]", buffer.len(), buffer);
-                let token = Token {
-                    type_: String::from("ERROR"),
-                    value_: buffer.clone(),
-                    line_: line,
-                };
-                tokens.push(token);
-            }
-            buffer.clear();
-            true
-        };
-        if finish { last_matched = reset(); }
-        else { last_matched = matched; }
+        let modes = if ty.starts_with('<') {
+            let end = ty.find('>').ok_or(String::from("Unclosed mode list"))?;
+            let modes = ty[1..end].split(',').map(|m| m.trim().to_string()).collect();
+            ty = ty[end + 1..].to_string();
+            modes } else { Vec::new() };
+
+        Ok(RegularRule {
+            name: ty,
+            expr: StateTransferGraph::new(),
+            greedy: greedy,
+            ignore: ignore,
+            modes: modes,
+            actions: actions,
+            eof: false,
+            keywords: None,
+        })
     }
+}
 
-    Ok(tokens)
+/*
+ * Error of the lexer, either input that no rule matches or a malformed config.
+ * line / column (from 1) and the byte offsets [start, end) locate text in the
+ * source, or in the config file.
+ */
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct LexError {
+    pub msg: String,
+    // rule still matching when the input failed, or the config rule that is malformed
+    pub rule: Option<String>,
+    pub text: String,
+    pub line: usize,
+    pub column: usize,
+    pub start: usize,
+    pub end: usize,
 }
 
-pub fn read_config(path: &str) -> Result<Vec<RegularRule>, io::Error> {
-    let string = read_file(path)?;
-    let mut rules = Vec::new();
-    let configs: Vec<&str> = string.split('\n').into_iter().collect();
+impl LexError {
+    pub fn span(&self) -> Span {
+        Span::new(self.start, self.end, self.line, self.column)
+    }
 
-    let charmap: Vec<char> = (0..255).map(|x: u8| x as char).collect();
-    // let charmap: Vec<char> = "'| |,|\r|\n|\t|{|}|*|+|/|=|(|)|@|:|.|-|<|>|;|\\|\"|0|1|2|3|4|5|6|7|8|9|a|b|c|d|e|f|g|h|i|j|k|l|m|n|o|p|q|r|s|t|u|v|w|x|y|z|A|B|C|D|E|F|G|H|I|J|K|L|M|N|O|P|Q|R|S|T|U|V|W|X|Y|Z|_".split('|').map(|x| x.chars().next().unwrap()).collect();
-    let mut alias: HashMap<String, RegularExpression> = HashMap::new();
-    let mut flag = false;
+    pub fn diagnostic(&self) -> Diagnostic {
+        let d = Diagnostic::error(self.msg.clone()).primary(self.span(), "");
+        let d = if let Some(rule) = &self.rule { d.note(format!("in rule [{:}]", rule)) } else { d };
+        if self.span().is_empty() && !self.text.is_empty() { d.note(format!("at {:?}", self.text)) } else { d }
+    }
 
-    for config in configs {
-        // println!("Parsing: {:?}", config);
-        if config.trim().is_empty() || config.starts_with('#') { continue; }
-        if config.starts_with('%') {
-            flag = true;
-            continue;
+    // the error without its position
+    pub fn message(&self) -> String {
+        let mut s = self.msg.clone();
+        if let Some(rule) = &self.rule { s += &format!(" in rule [{:}]", rule); }
+        if !self.text.is_empty() { s += &format!(": {:?}", self.text); }
+        s
+    }
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{:}:{:}: {:}", self.line, self.column, self.message())
+    }
+}
+
+impl From<io::Error> for LexError {
+    fn from(e: io::Error) -> Self {
+        LexError {
+            msg: e.to_string(),
+            ..Default::default()
         }
-        let mut ty = config.split_whitespace().next().unwrap().to_string();
-        
-        let greedy = if ty.ends_with('?') {
-            ty = ty[0..ty.len()-1].to_string();
-            false } else { true };
+    }
+}
 
-        let ignore = if ty.starts_with('-') {
-            ty = ty[1..ty.len()].to_string();
-            true } else { false };
+/*
+ * Split a config line into the rule head, the expression and where the
+ * expression begins as (byte offset, column).
+ */
+fn split_config_line(config: &str) -> (&str, &str, usize, usize) {
+    let config = config.trim_end();
+    let head = config.split_whitespace().next().unwrap();
+    let offset = config.find(head).unwrap() + head.len();
+    let offset = offset + (config.len() - offset - config[offset..].trim_start().len());
+    (head, &config[offset..], offset, config[..offset].chars().count() + 1)
+}
 
-        let re = config.split_whitespace().skip(1).collect::<Vec<&str>>().join(" ");
-        let re = RegularExpression::parse(&re, &alias).expect(&format!("Unable to resolve regular rule [{:}]", ty));
+fn config_error<S: Into<String>>(msg: S, rule: &str, text: &str, line: usize, line_start: usize, offset: usize, column: usize) -> LexError {
+    LexError {
+        msg: msg.into(),
+        rule: Some(rule.to_string()),
+        text: text.to_string(),
+        line: line,
+        column: column,
+        start: line_start + offset,
+        end: line_start + offset + text.len(),
+    }
+}
 
-        // let mut re = REParser::parse_from_str(re.as_str()).expect(&format!("Unable to resolve regular rule [{:}]", ty));
-        // re.apply_alias(&alias);
+// keyword section of a config, given to its rules once they are all read
+struct KeywordSection {
+    table: KeywordTable,
+    rules: Vec<String>,
+    config: String,
+    line: usize,
+    line_start: usize,
+}
 
+impl KeywordSection {
+    /*
+     * Section head syntax: %keywords[:nocase] RULE...
+     */
+    fn parse(config: &str, line: usize, line_start: usize) -> Result<Self, LexError> {
+        let config = config.trim_end();
+        let mut parts = config.split_whitespace();
+        let head = parts.next().unwrap();
+        let mut options = head.split(':');
+        if options.next() != Some("%keywords") {
+            return Err(config_error("Unknown section", head, head, line, line_start, 0, 1));
+        }
+        let mut table = KeywordTable::default();
+        for option in options {
+            match option {
+                "nocase" => table.nocase = true,
+                _ => return Err(config_error(format!("Unknown keyword option [{:}]", option), head, head, line, line_start, 0, 1))
+            }
+        }
+        let rules: Vec<String> = parts.map(|r| r.to_string()).collect();
+        if rules.is_empty() {
+            return Err(config_error("Keywords of no rule", head, head, line, line_start, 0, 1));
+        }
+        Ok(KeywordSection { table: table, rules: rules, config: config.to_string(), line: line, line_start: line_start })
+    }
 
-        // println!("{:?}", re);
-        if flag {
-            // println!("{:?}", dfa::construct_dfa(&re, &charmap));
-            rules.push(RegularRule {
-                name: ty,
-                expr: dfa::construct_dfa(&re, &charmap),
-                greedy: greedy,
-                ignore: ignore,
-            });
-        } else {
-            alias.insert(ty, re);
+    // NAME word...
+    fn read(&mut self, config: &str, line: usize, line_start: usize) -> Result<(), LexError> {
+        let (name, words, offset, column) = split_config_line(config);
+        if words.is_empty() {
+            return Err(config_error("Keyword without word", name, name, line, line_start, 0, 1));
+        }
+        for word in words.split_whitespace() {
+            let key = if self.table.nocase { word.to_lowercase() } else { word.to_string() };
+            if let Some(other) = self.table.words.insert(key, name.to_string()) {
+                return Err(config_error(format!("Word {:?} is already keyword [{:}]", word, other), name, words, line, line_start, offset, column));
+            }
+        }
+        Ok(())
+    }
+
+    fn attach(self, rules: &mut [RegularRule]) -> Result<(), LexError> {
+        for name in &self.rules {
+            let offset = self.config.find(name.as_str()).unwrap();
+            let error = |msg: &str| config_error(msg, name, name, self.line, self.line_start, offset, self.config[..offset].chars().count() + 1);
+            let mut found = false;
+            for rule in rules.iter_mut().filter(|r| r.name == *name && !r.eof) {
+                if rule.keywords.is_some() { return Err(error("Rule already has keywords")); }
+                rule.keywords = Some(self.table.clone());
+                found = true;
+            }
+            if !found { return Err(error("Keywords of an unknown rule")); }
         }
+        Ok(())
     }
-    Ok(rules)
 }
 
 
-pub fn read_config_external(path: &str) -> Result<Vec<RegularRule>, io::Error> {
+pub fn tokenize(src: &str, rules: &Vec<RegularRule>) -> Result<Vec<Token>, LexError> {
+    scanner::Scanner::new(rules).tokenize(src)
+}
+
+/*
+ * Keep on after unmatched input, skipping it, and report every error.
+ */
+pub fn tokenize_recover(src: &str, rules: &Vec<RegularRule>) -> (Vec<Token>, Vec<LexError>) {
+    scanner::Scanner::new(rules).tokenize_recover(src)
+}
+
+/*
+ * What analysis::check finds, at the name of the rule in its config. Rules
+ * overlapping an earlier one are only notes: a keyword is most often written
+ * before the identifiers it is taken out of.
+ */
+fn rule_warnings(rules: &[RegularRule], spans: &[Span]) -> Vec<Diagnostic> {
+    analysis::check(rules).into_iter()
+        .map(|w| {
+            let severity = if let analysis::RuleWarningKind::Ambiguous(_) = w.kind { Severity::Note } else { Severity::Warning };
+            Diagnostic::new(severity, w.message(rules)).primary(spans[w.rule], "")
+        })
+        .collect()
+}
+
+// the rules of a config and the warnings about them, for the caller to show
+pub fn read_config(path: &str) -> Result<(Vec<RegularRule>, Vec<Diagnostic>), LexError> {
+    let (rules, spans) = read_rules(path, |re, alias| RegularExpression::parse(re, alias))?;
+    let warnings = rule_warnings(&rules, &spans);
+    Ok((rules, warnings))
+}
+
+// the expressions read by the regex parser generated from examples/re
+pub fn read_config_external(path: &str) -> Result<(Vec<RegularRule>, Vec<Diagnostic>), LexError> {
+    let (rules, spans) = read_rules(path, |re, alias| {
+        let mut re = REParser::parse_from_str(re)?;
+        re.apply_alias(alias)?;
+        Ok(re)
+    })?;
+    let warnings = rule_warnings(&rules, &spans);
+    Ok((rules, warnings))
+}
+
+/*
+ * The rules of a config and the span of the name of each, the expressions
+ * read by parse with the aliases defined so far.
+ */
+fn read_rules<F>(path: &str, parse: F) -> Result<(Vec<RegularRule>, Vec<Span>), LexError>
+    where F: Fn(&str, &HashMap<String, RegularExpression>) -> Result<RegularExpression, ReError> {
     let string = read_file(path)?;
     let mut rules = Vec::new();
     let configs: Vec<&str> = string.split('\n').into_iter().collect();
 
-    let charmap: Vec<char> = (0..255).map(|x: u8| x as char).collect();
-    // let charmap: Vec<char> = "'| |,|\r|\n|\t|{|}|*|+|/|=|(|)|@|:|.|-|<|>|;|\\|\"|0|1|2|3|4|5|6|7|8|9|a|b|c|d|e|f|g|h|i|j|k|l|m|n|o|p|q|r|s|t|u|v|w|x|y|z|A|B|C|D|E|F|G|H|I|J|K|L|M|N|O|P|Q|R|S|T|U|V|W|X|Y|Z|_".split('|').map(|x| x.chars().next().unwrap()).collect();
     let mut alias: HashMap<String, RegularExpression> = HashMap::new();
     let mut flag = false;
-
-    for config in configs {
-        // println!("Parsing: {:?}", config);
+    // where each rule is named in the config
+    let mut spans = Vec::new();
+    let mut section: Option<KeywordSection> = None;
+    let mut sections = Vec::new();
+
+    let mut line_start = 0;
+    for (line, config) in configs.into_iter().enumerate() {
+        let (line, start) = (line + 1, line_start);
+        line_start += config.len() + 1;
         if config.trim().is_empty() || config.starts_with('#') { continue; }
         if config.starts_with('%') {
+            sections.extend(section.take());
+            if config.len() > 1 && !config[1..].trim().is_empty() { section = Some(KeywordSection::parse(config, line, start)?); }
             flag = true;
             continue;
         }
-        let mut ty = config.split_whitespace().next().unwrap().to_string();
-        
-        let greedy = if ty.ends_with('?') {
-            ty = ty[0..ty.len()-1].to_string();
-            false } else { true };
-
-        let ignore = if ty.starts_with('-') {
-            ty = ty[1..ty.len()].to_string();
-            true } else { false };
-
-        let re = config.split_whitespace().skip(1).collect::<Vec<&str>>().join(" ");
-        // let re = RegularExpression::parse(&re, &alias).expect(&format!("Unable to resolve regular rule [{:}]", ty));
-
-        let mut re = REParser::parse_from_str(re.as_str()).expect(&format!("Unable to resolve regular rule [{:}]", ty));
-        re.apply_alias(&alias);
+        if let Some(section) = &mut section {
+            section.read(config, line, start)?;
+            continue;
+        }
+        let (head, re, offset, column) = split_config_line(config);
+        let mut rule = RegularRule::parse_head(head).map_err(|msg| config_error(msg, head, head, line, start, 0, 1))?;
 
+        if flag && re == EOF_RULE {
+            rule.eof = true;
+            rules.push(rule);
+            spans.push(Span::new(start, start + head.len(), line, 1));
+            continue;
+        }
+        let re = parse(re, &alias).map_err(|e| config_error(e.msg, &rule.name, re, line, start, offset, column + e.pos))?;
 
-        // println!("{:?}", re);
         if flag {
-            // println!("{:?}", dfa::construct_dfa(&re, &charmap));
-            rules.push(RegularRule {
-                name: ty,
-                expr: dfa::construct_dfa(&re, &charmap),
-                greedy: greedy,
-                ignore: ignore,
-            });
+            rule.expr = dfa::minimize_dfa(&dfa::construct_dfa(&re));
+            rules.push(rule);
+            spans.push(Span::new(start, start + head.len(), line, 1));
         } else {
-            alias.insert(ty, re);
+            alias.insert(rule.name, re);
         }
     }
-    Ok(rules)
+    for s in sections.into_iter().chain(section) {
+        s.attach(&mut rules)?;
+    }
+    Ok((rules, spans))
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the rules of a config written to a file of its own
+    fn config(name: &str, src: &str) -> (Vec<RegularRule>, Vec<Diagnostic>) {
+        let path = std::env::temp_dir().join(format!("coolc-{:}-{:}.lex", name, std::process::id()));
+        std::fs::write(&path, src).unwrap();
+        let ret = read_config(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        ret
+    }
+
+    const VALUES: &str = "%\nSTR:strip:unescape \"[^\"]*\"\nINT:int [0-9][0-9]*\nFLOAT:float [0-9][0-9]*\\.[0-9][0-9]*\nID:lower [a-zA-Z][a-zA-Z]*\n-WS [\\ ][\\ ]*\n";
+
+    #[test]
+    fn warnings_returned() {
+        let (rules, warnings) = config("warnings", "%\nA a\nB a\nC [a-z][a-z]*\n");
+        assert_eq!(rules.len(), 3);
+        let warnings: Vec<String> = warnings.iter().map(|x| x.to_string()).collect();
+        assert_eq!(warnings, vec![
+            "3:1: warning: Rule [B] is shadowed by [A] in mode INITIAL",
+            "4:1: note: Rules [A] and [C] both match in mode INITIAL, [A] wins on \"a\"",
+            "4:1: note: Rules [B] and [C] both match in mode INITIAL, [B] wins on \"a\"",
+        ]);
+    }
+
+    #[test]
+    fn same_rules_both_readers() {
+        let (internal, _) = read_config("examples/cool/cool.lex").unwrap();
+        let (external, _) = read_config_external("examples/cool/cool.lex").unwrap();
+        assert_eq!(internal.len(), external.len());
+        for (a, b) in internal.iter().zip(&external) {
+            assert_eq!(a.name, b.name);
+            assert!(dfa::equivalent(&a.expr, &b.expr), "rule [{:}]", a.name);
+        }
+    }
+
+    #[test]
+    fn value_actions() {
+        let (rules, _) = config("values", VALUES);
+        let tokens = tokenize("\"a\\tb\" 42 1.5 HeLLo", &rules).unwrap();
+        let literals: Vec<Option<TokenValue>> = tokens.into_iter().map(|x| x.literal_).collect();
+        assert_eq!(literals, vec![
+            Some(TokenValue::Str(String::from("a\tb"))),
+            Some(TokenValue::Int(42)),
+            Some(TokenValue::Float(1.5)),
+            Some(TokenValue::Str(String::from("hello"))),
+        ]);
+    }
+
+    #[test]
+    fn invalid_int_reported() {
+        let (rules, _) = config("int", VALUES);
+        let e = tokenize("1 99999999999999999999", &rules).unwrap_err();
+        assert_eq!((e.rule.as_ref().map(|x| x.as_str()), e.text.as_str(), e.column), (Some("INT"), "99999999999999999999", 3));
+        assert!(e.msg.starts_with("Invalid integer"), "{:}", e.msg);
+    }
+
+    #[test]
+    fn keywords_nocase() {
+        let (rules, _) = read_config("examples/cool/cool.lex").unwrap();
+        let types: Vec<String> = tokenize("ClAsS classy Class CLASS", &rules).unwrap().into_iter().map(|x| x.type_).collect();
+        assert_eq!(types, vec!["CLASS", "OBJECTID", "CLASS", "CLASS"]);
+    }
+}
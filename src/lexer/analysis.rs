@@ -0,0 +1,155 @@
+use super::re::*;
+use super::{RegularRule, KeywordTable};
+use super::scanner::mode_names;
+use std::collections::{HashMap, VecDeque};
+
+/*
+ * Static checks of lexer rules, run on the rule dfas of each mode.
+ * A rule matching the empty string never produces a token, a rule whose every
+ * match is also matched by earlier rules of the mode never wins (ties go to
+ * the earliest rule), and two rules sharing some match are ambiguous, the
+ * earlier one winning silently. A keyword that none of the rules of its
+ * table matches is never looked up.
+ */
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuleWarningKind {
+	EmptyMatch,
+	// by the earlier rules sharing some match with it
+	Shadowed(Vec<usize>),
+	// with an earlier rule, which wins on the witness
+	Ambiguous(usize),
+	// keyword type, and the rules holding its table
+	UnmatchedKeyword(String, Vec<usize>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuleWarning {
+	pub kind: RuleWarningKind,
+	// index of the rule in the rule list
+	pub rule: usize,
+	pub mode: String,
+	// shortest input showing the problem
+	pub witness: Option<String>,
+}
+
+impl RuleWarning {
+	pub fn message(&self, rules: &[RegularRule]) -> String {
+		let names = |x: &[usize]| x.iter().map(|i| format!("[{:}]", rules[*i].name)).collect::<Vec<String>>().join(", ");
+		let msg = match &self.kind {
+			RuleWarningKind::EmptyMatch => format!("Rule [{:}] matches the empty string", rules[self.rule].name),
+			RuleWarningKind::Shadowed(by) => format!("Rule [{:}] is shadowed by {:} in mode {:}", rules[self.rule].name, names(by), self.mode),
+			RuleWarningKind::UnmatchedKeyword(keyword, by) => format!("Keyword [{:}] is matched by none of {:}", keyword, names(by)),
+			RuleWarningKind::Ambiguous(other) => format!("Rules [{:}] and [{:}] both match in mode {:}, [{:}] wins", rules[*other].name, rules[self.rule].name, self.mode, rules[*other].name),
+		};
+		if let Some(w) = &self.witness { format!("{:} on {:?}", msg, w) } else { msg }
+	}
+}
+
+// a readable char of the class, printable ascii if possible
+fn sample(class: &CharRange) -> char {
+	let printable = CharRange::new(' ', '~');
+	if class.end < printable.start || class.start > printable.end { class.start } else if class.start < printable.start { printable.start } else { class.start }
+}
+
+// whether dfa matches word, in any case if nocase
+fn matches_word(dfa: &StateTransferGraph, word: &str, nocase: bool) -> bool {
+	let mut states = vec![dfa.start];
+	for c in word.chars() {
+		let variants: Vec<char> = if nocase { c.to_lowercase().chain(c.to_uppercase()).collect() } else { vec![c] };
+		let mut next: Vec<usize> = states.iter().flat_map(|s| variants.iter().filter_map(move |v| dfa.get_transition_by_char(*s, *v))).collect();
+		next.sort();
+		next.dedup();
+		states = next;
+	}
+	states.iter().any(|s| dfa.ends.contains(s))
+}
+
+/*
+ * Shortest non-empty input on which the accepting dfas satisfy accept, running
+ * all the dfas side by side. The first `alive` dfas must keep matching.
+ */
+pub fn search(dfas: &[&StateTransferGraph], alive: usize, accept: &dyn Fn(&[bool]) -> bool) -> Option<String> {
+	let classes = split_ranges(dfas.iter().flat_map(|d| d.edges.iter().map(|e| &e.cost)));
+	let start: Vec<Option<usize>> = dfas.iter().map(|d| Some(d.start)).collect();
+	// (tuple, parent, char)
+	let mut visited: Vec<(Vec<Option<usize>>, usize, char)> = vec![(start.clone(), 0, '\0')];
+	let mut index: HashMap<Vec<Option<usize>>, usize> = HashMap::new();
+	index.insert(start, 0);
+	let mut queue = VecDeque::new();
+	queue.push_back(0);
+
+	while let Some(i) = queue.pop_front() {
+		for class in &classes {
+			let c = sample(class);
+			let next: Vec<Option<usize>> = visited[i].0.iter().zip(dfas).map(|(s, d)| d.get_transition_by_char((*s)?, c)).collect();
+			if next[..alive].iter().any(|s| s.is_none()) || index.contains_key(&next) { continue; }
+			let accepts: Vec<bool> = next.iter().zip(dfas).map(|(s, d)| s.map_or(false, |s| d.ends.contains(&s))).collect();
+			index.insert(next.clone(), visited.len());
+			visited.push((next, i, c));
+			if accept(&accepts) {
+				let mut witness = Vec::new();
+				let mut j = visited.len() - 1;
+				while j != 0 {
+					witness.push(visited[j].2);
+					j = visited[j].1;
+				}
+				return Some(witness.into_iter().rev().collect());
+			}
+			queue.push_back(visited.len() - 1);
+		}
+	}
+	None
+}
+
+pub fn check(rules: &[RegularRule]) -> Vec<RuleWarning> {
+	let mut warnings = Vec::new();
+	let modes = mode_names(rules);
+	let active = |mode: &str| -> Vec<usize> { (0..rules.len()).filter(|i| !rules[*i].eof && rules[*i].active_in(mode)).collect() };
+
+	for (i, rule) in rules.iter().enumerate() {
+		if !rule.eof && rule.expr.ends.contains(&rule.expr.start) {
+			warnings.push(RuleWarning { kind: RuleWarningKind::EmptyMatch, rule: i, mode: String::new(), witness: None });
+		}
+	}
+
+	let mut tables: Vec<(&KeywordTable, Vec<usize>)> = Vec::new();
+	for (i, rule) in rules.iter().enumerate() {
+		if let Some(k) = &rule.keywords {
+			if let Some(t) = tables.iter_mut().find(|t| t.0 == k) { t.1.push(i); } else { tables.push((k, vec![i])); }
+		}
+	}
+	for (table, holders) in tables {
+		for (word, keyword) in &table.words {
+			if holders.iter().any(|i| matches_word(&rules[*i].expr, word, table.nocase)) { continue; }
+			warnings.push(RuleWarning { kind: RuleWarningKind::UnmatchedKeyword(keyword.clone(), holders.clone()), rule: holders[0], mode: String::new(), witness: Some(word.clone()) });
+		}
+	}
+
+	// pairs already reported as ambiguous
+	let mut reported = Vec::new();
+	for mode in &modes {
+		let active = active(mode);
+		for (k, b) in active.iter().enumerate() {
+			let earlier = &active[..k];
+			let overlapping: Vec<usize> = earlier.iter().cloned().filter(|a| {
+				search(&[&rules[*b].expr, &rules[*a].expr], 2, &|x| x[0] && x[1]).is_some()
+			}).collect();
+			if overlapping.is_empty() { continue; }
+
+			let mut dfas = vec![&rules[*b].expr];
+			dfas.extend(overlapping.iter().map(|a| &rules[*a].expr));
+			if search(&dfas, 1, &|x| x[0] && !x[1..].iter().any(|y| *y)).is_none() {
+				warnings.push(RuleWarning { kind: RuleWarningKind::Shadowed(overlapping), rule: *b, mode: mode.clone(), witness: None });
+				continue;
+			}
+			for a in overlapping {
+				if reported.contains(&(a, *b)) { continue; }
+				reported.push((a, *b));
+				let witness = search(&[&rules[*b].expr, &rules[a].expr], 2, &|x| x[0] && x[1]);
+				warnings.push(RuleWarning { kind: RuleWarningKind::Ambiguous(a), rule: *b, mode: mode.clone(), witness: witness });
+			}
+		}
+	}
+	warnings
+}
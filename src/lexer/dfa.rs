@@ -1,13 +1,14 @@
 use super::re::*;
+use super::analysis::search;
 use std::collections::HashMap;
 use crate::utils::*;
 
 trait FollowPosImpl {
-	fn downward(&self, followpos: &mut Vec<Vec<bool>>, posmap: &mut Vec<SingleToken>) -> (bool, VertexSet, VertexSet);
+	fn downward(&self, followpos: &mut Vec<Vec<bool>>, posmap: &mut Vec<Vec<CharRange>>) -> (bool, VertexSet, VertexSet);
 }
 
 impl FollowPosImpl for RegularExpression {
-	fn downward(&self, followpos: &mut Vec<Vec<bool>>, posmap: &mut Vec<SingleToken>) -> (bool, VertexSet, VertexSet) {
+	fn downward(&self, followpos: &mut Vec<Vec<bool>>, posmap: &mut Vec<Vec<CharRange>>) -> (bool, VertexSet, VertexSet) {
 		match self {
 			RegularExpression::Epsilon => {
 				// push_sets!(followpos);
@@ -20,9 +21,18 @@ impl FollowPosImpl for RegularExpression {
 				let mut last = new_set!(followpos.len());
 				last.push(true);
 				push_sets!(followpos);
-				posmap.push(*id);
+				posmap.push(vec![CharRange::single(*id)]);
 				(false, first, last)                
 			},
+			RegularExpression::Set { ranges } => {
+				let mut first = new_set!(followpos.len());
+				first.push(true);
+				let mut last = new_set!(followpos.len());
+				last.push(true);
+				push_sets!(followpos);
+				posmap.push(ranges.clone());
+				(false, first, last)
+			},
 			RegularExpression::Union { ref operands } => {
 				let mut arr = Vec::<(bool, VertexSet, VertexSet)>::new();
 				for child in operands {
@@ -99,6 +109,31 @@ impl FollowPosImpl for RegularExpression {
 
 				(true, cf, cl)
 			},
+			RegularExpression::OneOrMore { ref operand } => {
+				let (cn, cf, cl) = operand.downward(followpos, posmap);
+
+				// same as iteration, but nullable only if the operand is
+				for i in 0..cl.len() {
+					if !cl[i] { continue; }
+					for j in 0..cf.len() {
+						if !cf[j] { continue; }
+						followpos[i][j] = true;
+					}
+				}
+
+				(cn, cf, cl)
+			},
+			RegularExpression::Optional { ref operand } => {
+				let (_, cf, cl) = operand.downward(followpos, posmap);
+				(true, cf, cl)
+			},
+			RegularExpression::Repetition { .. } => {
+				// every copy of the operand needs positions of its own
+				self.desugar().downward(followpos, posmap)
+			},
+			RegularExpression::Match { ref operand } => {
+				operand.downward(followpos, posmap)
+			},
 			_ => panic!()
 		}
 	}
@@ -106,14 +141,13 @@ impl FollowPosImpl for RegularExpression {
 
 
 
-pub fn construct_dfa(re: &RegularExpression, charmap: &[SingleToken]) -> StateTransferGraph {
+pub fn construct_dfa(re: &RegularExpression) -> StateTransferGraph {
 	let mut graph = StateTransferGraph::new();
 	let mut followpos = Vec::<Vec<bool>>::new();
-	let mut posmap = Vec::<SingleToken>::new();
-	let re = RegularExpression::Concatenation { operands: vec![re.clone(), RegularExpression::Atomic { id: '$' } ] };
-	// push_sets!(followpos);
+	let mut posmap = Vec::<Vec<CharRange>>::new();
+	// an empty set marks the end position, it never matches any char
+	let re = RegularExpression::Concatenation { operands: vec![re.clone(), RegularExpression::Set { ranges: Vec::new() } ] };
 	let (_, first, _) = re.downward(&mut followpos, &mut posmap);
-	// println!("{:?}", followpos);
 
 	let mut states: HashMap<VertexSet, usize> = HashMap::new();	
 	let mut stack: Vec<VertexSet> = Vec::new();
@@ -127,10 +161,11 @@ pub fn construct_dfa(re: &RegularExpression, charmap: &[SingleToken]) -> StateTr
 	while let Some(ref s) = stack.pop() {
 		let state = *states.get(s).unwrap();
 
-		for token in charmap {
+		let tokens = split_ranges((0..s.len()).filter(|i| s[*i]).flat_map(|i| posmap[i].iter()));
+		for token in tokens {
 			let mut ahead = new_set!(followpos.len());
 			for i in 0..s.len() {
-				if !s[i] || &posmap[i] != token { continue; }
+				if !s[i] || !posmap[i].iter().any(|r| r.covers(&token)) { continue; }
 				cup_set!(ahead, followpos[i]);
 			}
 
@@ -147,19 +182,18 @@ pub fn construct_dfa(re: &RegularExpression, charmap: &[SingleToken]) -> StateTr
 				stack.push(ahead);
 				ind
 			};
-			graph.add_transfer(state, state_rhs, *token);
+			graph.add_transfer(state, state_rhs, token);
 		}
 	}
+	graph.coalesce_edges();
 	graph
 }
 
 
-pub fn nfa_to_dfa<D: Default, T: PartialEq + Default + Clone>(nfa_graph: &StateTransferGraph<D, T>, charmap: &[T]) -> StateTransferGraph<D, T> {
-	// let mut map: HashMap<VertexSet, bool> = HashMap::new();
+pub fn nfa_to_dfa<D: Default>(nfa_graph: &StateTransferGraph<D, Option<CharRange>>) -> StateTransferGraph<D, CharRange> {
 	let mut states: HashMap<VertexSet, usize> = HashMap::new();	
 	let mut stack: Vec<VertexSet> = Vec::new();
 	let mut graph = StateTransferGraph::new();
-	// let charmap = vec!['a', 'b'];
 
 	let epsilon_closure = |s: &VertexSet| -> VertexSet {
 		let mut ret = s.clone();
@@ -167,11 +201,10 @@ pub fn nfa_to_dfa<D: Default, T: PartialEq + Default + Clone>(nfa_graph: &StateT
 
 		while flag {
 			flag = false;
-			// println!("loop here");
 			for i in 0..s.len() {
 				if !ret[i] { continue; }
 				for e in &nfa_graph.vertices[i].out_edges {
-					if nfa_graph.edges[*e].cost == T::default() {
+					if nfa_graph.edges[*e].cost == None {
 						let v = nfa_graph.edges[*e].out_vertex;
 						if !ret[v] {
 							ret[v] = true;
@@ -185,16 +218,18 @@ pub fn nfa_to_dfa<D: Default, T: PartialEq + Default + Clone>(nfa_graph: &StateT
 	};
 
 
-	let move_step = |s: &VertexSet, token: &T| -> VertexSet {
+	let move_step = |s: &VertexSet, token: &CharRange| -> VertexSet {
 		let mut ret = VertexSet::with_capacity(s.len());
 		ret.resize(s.len(), false);
 		for i in 0..s.len() {
 			if !s[i] { continue; }
 			for e in &nfa_graph.vertices[i].out_edges {
-				if &nfa_graph.edges[*e].cost == token {
-					let v = nfa_graph.edges[*e].out_vertex;
-					ret[v] = true;
-				}		
+				if let Some(ref r) = nfa_graph.edges[*e].cost {
+					if r.covers(token) {
+						let v = nfa_graph.edges[*e].out_vertex;
+						ret[v] = true;
+					}
+				}
 			}
 		}
 		ret
@@ -212,16 +247,15 @@ pub fn nfa_to_dfa<D: Default, T: PartialEq + Default + Clone>(nfa_graph: &StateT
 	stack.push(start);
 
 	while let Some(ref s) = stack.pop() {
-		// if map.contains_key(s) { continue; }
-
 		let state = *states.get(s).unwrap();
-		// states.insert(s.clone(), state);
-		for token in charmap {
-			let ahead = move_step(s, token);
+		let tokens = split_ranges((0..s.len())
+			.filter(|i| s[*i])
+			.flat_map(|i| nfa_graph.vertices[i].out_edges.iter())
+			.filter_map(|e| nfa_graph.edges[*e].cost.as_ref()));
+		for token in tokens {
+			let ahead = move_step(s, &token);
 			let ahead = epsilon_closure(&ahead);
 
-			// println!("ahead {:?} {:?} \n {:?} \n ", token, s, ahead);
-
 			if !ahead.contains(&true) { continue; }
 
 			let state_option = states.remove(&ahead);
@@ -235,85 +269,241 @@ pub fn nfa_to_dfa<D: Default, T: PartialEq + Default + Clone>(nfa_graph: &StateT
 				stack.push(ahead);
 				ind
 			};
-			graph.add_transfer(state, state_rhs, token.clone());
+			graph.add_transfer(state, state_rhs, token);
 		}
 	}
-
+	graph.coalesce_edges();
 	graph
 }
 
 
 
-pub fn minimize_dfa<D: Default, T: PartialEq + Default + Clone>(ori: &StateTransferGraph<D, T>, charmap: &[T]) -> StateTransferGraph<D, T> {
-	let mut graph = StateTransferGraph::new();
-	let mut group = Vec::<usize>::new();
-	let mut groups: usize = 1;
+/*
+ * Hopcroft's algorithm on the char classes of the edges, with a dead state
+ * completing the dfa. Blocks are split by the predecessors of a splitter
+ * block on one class, a split block not yet waiting as a splitter only puts
+ * its smaller half on the worklist, hence O(n log n) per class.
+ * The splitter is taken as it was popped for every class: it may split
+ * itself on the way, and its half left off the worklist still has to split
+ * the blocks on the next classes.
+ * States equivalent to the dead state are dropped, the others are numbered by
+ * their first state in ori so the start state stays first.
+ */
+pub fn minimize_dfa<D: Default>(ori: &StateTransferGraph<D, CharRange>) -> StateTransferGraph<D, CharRange> {
+	let classes = split_ranges(ori.edges.iter().map(|e| &e.cost));
+	let dead = ori.vertices.len();
+	let states = dead + 1;
+
+	// predecessors of each state on each class: class -> state -> states
+	let mut inverse = vec![vec![Vec::new(); states]; classes.len()];
+	for (c, class) in classes.iter().enumerate() {
+		for s in 0..states {
+			let t = if s == dead { dead } else { ori.get_transition_by_char(s, class.start).unwrap_or(dead) };
+			inverse[c][t].push(s);
+		}
+	}
 
-	group.resize(ori.vertices.len(), 0);
-	for end in &ori.ends {
-		group[*end] = 1;
+	let (ends, others): (Vec<usize>, Vec<usize>) = (0..states).partition(|s| ori.ends.contains(s));
+	let mut blocks: Vec<Vec<usize>> = vec![ends, others].into_iter().filter(|b| !b.is_empty()).collect();
+	let mut block_of = vec![0; states];
+	// index of each state in its block
+	let mut position = vec![0; states];
+	for (i, b) in blocks.iter().enumerate() {
+		for (j, s) in b.iter().enumerate() {
+			block_of[*s] = i;
+			position[*s] = j;
+		}
 	}
-	// println!("group: {:?}", group);
-	let mut i = 0;
-	while i <= groups {
-		for token in charmap {
-			let mut set = Vec::new();
-			for j in 0..group.len() {
-				if group[j] != i { continue; }
-				set.push(ori.get_transition(j, token.clone()));
+	let mut waiting = vec![true; blocks.len()];
+	let mut work: Vec<usize> = (0..blocks.len()).collect();
+
+	while let Some(splitter) = work.pop() {
+		waiting[splitter] = false;
+		let members = blocks[splitter].clone();
+		for c in 0..classes.len() {
+			// predecessors on c of the splitter by block, a state has a single one on c
+			let mut touched: Vec<usize> = Vec::new();
+			let mut moved: HashMap<usize, Vec<usize>> = HashMap::new();
+			for t in &members {
+				for s in &inverse[c][*t] {
+					let y = block_of[*s];
+					if !moved.contains_key(&y) { touched.push(y); }
+					moved.entry(y).or_insert_with(Vec::new).push(*s);
+				}
 			}
-			// println!("[{:} -> {:}] : {:?}", i, token, set);
-			let mut k = 0;
-			let new_group = groups + 1;
-			for j in 0..group.len() {
-				if group[j] != i { continue; }
-				if set[k] != set[0] {
-					groups = new_group;
-					group[j] = groups;	
+			for y in touched {
+				let split = moved.remove(&y).unwrap();
+				if split.len() == blocks[y].len() { continue; }
+				let z = blocks.len();
+				for s in &split {
+					// swap remove s from y
+					let last = *blocks[y].last().unwrap();
+					blocks[y][position[*s]] = last;
+					position[last] = position[*s];
+					blocks[y].pop();
+					block_of[*s] = z;
+				}
+				for (j, s) in split.iter().enumerate() {
+					position[*s] = j;
+				}
+				blocks.push(split);
+				waiting.push(false);
+				let smaller = if waiting[y] || blocks[z].len() <= blocks[y].len() { z } else { y };
+				if !waiting[smaller] {
+					waiting[smaller] = true;
+					work.push(smaller);
 				}
-				k += 1;
 			}
 		}
-		i += 1;
 	}
-	// println!("GROUP: {:?}", group);
-	for _ in 0..groups+1 {
-		graph.add_state();
+
+	let mut graph = StateTransferGraph::new();
+	let mut number: Vec<Option<usize>> = vec![None; blocks.len()];
+	for s in 0..dead {
+		let b = block_of[s];
+		if b == block_of[dead] || number[b].is_some() { continue; }
+		number[b] = Some(graph.add_state());
 	}
-	for i in 0..groups+1 {
-		let states: Vec<usize> = group.iter().enumerate().filter_map(move |(state, g)| if *g==i { Some(state) } else { None }).collect();
-		for token in charmap {
-			if group[ori.start] == i { graph.mark_as_start(i); }
-			if ori.ends.iter().any(|x| group[*x] == i) { graph.mark_as_end(i); }
-			for state in &states {
-				let trans = ori.get_transition(*state, token.clone());
-				if let Some(rhs) = trans {
-					graph.add_transfer(i, group[rhs], token.clone());
-					break;
-				}
+	if let Some(start) = number[block_of[ori.start]] {
+		graph.mark_as_start(start);
+	} else {
+		// nothing is accepted
+		let start = graph.add_state();
+		graph.mark_as_start(start);
+		return graph;
+	}
+	let mut done = vec![false; blocks.len()];
+	for s in 0..dead {
+		let from = if let Some(x) = number[block_of[s]] { x } else { continue };
+		// the first state of each block stands for it
+		if done[block_of[s]] { continue; }
+		done[block_of[s]] = true;
+		if ori.ends.contains(&s) { graph.mark_as_end(from); }
+		for e in &ori.vertices[s].out_edges {
+			let edge = &ori.edges[*e];
+			if let Some(to) = number[block_of[edge.out_vertex]] {
+				graph.add_transfer(from, to, edge.cost);
 			}
 		}
 	}
+	graph.coalesce_edges();
 	graph
 }
 
+/*
+ * Shortest input accepted by exactly one of a and b, None when they accept
+ * the same language.
+ */
+pub fn difference_witness(a: &StateTransferGraph, b: &StateTransferGraph) -> Option<String> {
+	if a.ends.contains(&a.start) != b.ends.contains(&b.start) { return Some(String::new()); }
+	search(&[a, b], 0, &|x| x[0] != x[1])
+}
+
+pub fn equivalent(a: &StateTransferGraph, b: &StateTransferGraph) -> bool {
+	difference_witness(a, b).is_none()
+}
+
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum MatchResult {
 	Ok, Unfinished, Err,
 }
 
-pub fn match_dfa<D: Default, T: PartialEq, I: IntoIterator<Item=T>>(graph: &StateTransferGraph<D, T>, token: I) -> MatchResult {
+pub fn match_dfa<D: Default, I: IntoIterator<Item=SingleToken>>(graph: &StateTransferGraph<D, CharRange>, token: I) -> MatchResult {
 	let mut state = graph.start;
 	for c in token {
-		let mut flag = false;
-		for e in &graph.vertices[state].out_edges {
-			if graph.edges[*e].cost == c {
-				state = graph.edges[*e].out_vertex;
-				flag = true;
-				break;
+		if let Some(next) = graph.get_transition_by_char(state, c) {
+			state = next;
+		} else { return MatchResult::Err; }
+	}
+	if graph.ends.contains(&state) { MatchResult::Ok } else { MatchResult::Unfinished }
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn dfa(src: &str) -> StateTransferGraph {
+		construct_dfa(&RegularExpression::parse(src, None).unwrap())
+	}
+
+	fn accepts(graph: &StateTransferGraph, s: &str) -> bool {
+		match_dfa(graph, s.chars()) == MatchResult::Ok
+	}
+
+	// states of the minimal dfa by Moore's refinement, without the dead state
+	fn moore_states(ori: &StateTransferGraph) -> usize {
+		let classes = split_ranges(ori.edges.iter().map(|e| &e.cost));
+		let dead = ori.vertices.len();
+		let next = |s: usize, c: &CharRange| if s == dead { dead } else { ori.get_transition_by_char(s, c.start).unwrap_or(dead) };
+		let mut block: Vec<usize> = (0..dead + 1).map(|s| if ori.ends.contains(&s) { 1 } else { 0 }).collect();
+		loop {
+			let mut signatures: HashMap<Vec<usize>, usize> = HashMap::new();
+			let refined: Vec<usize> = (0..dead + 1).map(|s| {
+				let signature: Vec<usize> = Some(block[s]).into_iter().chain(classes.iter().map(|c| block[next(s, c)])).collect();
+				let n = signatures.len();
+				*signatures.entry(signature).or_insert(n)
+			}).collect();
+			let done = signatures.len() == block.iter().collect::<std::collections::HashSet<_>>().len();
+			block = refined;
+			if done { break; }
+		}
+		(0..dead).map(|s| block[s]).filter(|b| *b != block[dead]).collect::<std::collections::HashSet<_>>().len()
+	}
+
+	fn check(src: &str) {
+		let ori = dfa(src);
+		let min = minimize_dfa(&ori);
+		assert_eq!(difference_witness(&ori, &min), None, "{:}", src);
+		if !ori.ends.is_empty() { assert_eq!(min.vertices.len(), moore_states(&ori), "{:}", src); }
+	}
+
+	#[test]
+	fn splitter_splitting_itself() {
+		let src = "((a)?)?(b)?a[ab]([bc]|([bc]c(b)*)*)";
+		let ori = dfa(src);
+		let min = minimize_dfa(&ori);
+		assert!(accepts(&ori, "aabcc"));
+		assert!(accepts(&min, "aabcc"));
+		check(src);
+	}
+
+	#[test]
+	fn known_minimal() {
+		// the textbook dfa of (a|b)*abb has 4 states
+		let min = minimize_dfa(&dfa("(a|b)*abb"));
+		assert_eq!(min.vertices.len(), 4);
+		assert!(accepts(&min, "babb"));
+		assert!(!accepts(&min, "abba"));
+		assert_eq!(minimize_dfa(&dfa("a*")).vertices.len(), 1);
+	}
+
+	#[test]
+	fn equivalence() {
+		assert!(equivalent(&dfa("(a|b)*"), &dfa("(a*b*)*")));
+		assert_eq!(difference_witness(&dfa("a(b|c)"), &dfa("ab")), Some(String::from("ac")));
+		assert_eq!(difference_witness(&dfa("a*"), &dfa("aa*")), Some(String::new()));
+	}
+
+	// random regular expressions over a, b and c against Moore's minimization
+	#[test]
+	fn random_against_moore() {
+		let mut seed: u64 = 0x2545f4914f6cdd1d;
+		let mut rand = move |n: u64| { seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407); (seed >> 33) % n };
+		fn gen(rand: &mut dyn FnMut(u64) -> u64, depth: usize) -> String {
+			let atoms = ["a", "b", "c", "[ab]", "[bc]"];
+			if depth == 0 { return atoms[rand(atoms.len() as u64) as usize].to_string(); }
+			match rand(6) {
+				0 => format!("{:}{:}", gen(rand, depth - 1), gen(rand, depth - 1)),
+				1 => format!("({:}|{:})", gen(rand, depth - 1), gen(rand, depth - 1)),
+				2 => format!("({:})*", gen(rand, depth - 1)),
+				3 => format!("({:})?", gen(rand, depth - 1)),
+				4 => format!("{:}{:}{:}", gen(rand, depth - 1), gen(rand, depth - 1), gen(rand, depth - 1)),
+				_ => atoms[rand(atoms.len() as u64) as usize].to_string(),
 			}
 		}
-		if !flag { return MatchResult::Err; }
+		for _ in 0..3000 {
+			let depth = rand(4) as usize + 1;
+			check(&gen(&mut rand, depth));
+		}
 	}
-	if graph.ends.contains(&state) { MatchResult::Ok } else { MatchResult::Unfinished }
-}
\ No newline at end of file
+}
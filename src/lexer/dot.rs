@@ -0,0 +1,106 @@
+use super::re::*;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/*
+ * Graphviz export of state transfer graphs.
+ * Vertices and edges describe themselves through DotVertex and DotEdge, all
+ * the edges between the same pair of states are drawn as a single arrow.
+ * Labels are plain text, a multi-line label is drawn left-justified.
+ */
+
+pub trait DotVertex {
+	fn dot_label(&self, index: usize) -> String;
+
+	fn dot_shape() -> &'static str {
+		"circle"
+	}
+}
+
+pub trait DotEdge: Sized {
+	// label of the arrow standing for all the costs between two states
+	fn dot_label(costs: &[&Self]) -> String;
+}
+
+impl DotVertex for () {
+	fn dot_label(&self, index: usize) -> String {
+		index.to_string()
+	}
+}
+
+impl DotEdge for CharRange {
+	fn dot_label(costs: &[&Self]) -> String {
+		normalize_ranges(costs.iter().map(|x| **x).collect()).iter().map(|x| x.to_string()).collect::<Vec<String>>().join(", ")
+	}
+}
+
+impl DotEdge for Option<CharRange> {
+	fn dot_label(costs: &[&Self]) -> String {
+		let ranges: Vec<&CharRange> = costs.iter().filter_map(|x| x.as_ref()).collect();
+		let mut label = if ranges.is_empty() { String::new() } else { CharRange::dot_label(&ranges) };
+		if ranges.len() < costs.len() {
+			if !label.is_empty() { label.push_str(", "); }
+			label.push_str("ε");
+		}
+		label
+	}
+}
+
+fn escape(label: &str) -> String {
+	let escaped = label.replace('\\', "\\\\").replace('"', "\\\"");
+	if escaped.contains('\n') {
+		escaped.lines().map(|l| format!("{:}\\l", l)).collect()
+	} else { escaped }
+}
+
+impl<D: Default + DotVertex, T: PartialEq + DotEdge> StateTransferGraph<D, T> {
+	pub fn to_dot(&self, name: &str) -> String {
+		let mut out = String::new();
+		writeln!(out, "digraph \"{:}\" {{", escape(name)).unwrap();
+		writeln!(out, "\trankdir=LR;").unwrap();
+		self.dot_statements("", "\t", &mut out);
+		writeln!(out, "}}").unwrap();
+		out
+	}
+
+	/*
+	 * The graph as a cluster to put inside a digraph, the ids of its states
+	 * start with prefix so that several graphs can share the digraph.
+	 */
+	pub fn to_dot_cluster(&self, name: &str, prefix: &str) -> String {
+		let mut out = String::new();
+		writeln!(out, "\tsubgraph \"cluster_{:}\" {{", escape(prefix)).unwrap();
+		writeln!(out, "\t\tlabel=\"{:}\";", escape(name)).unwrap();
+		self.dot_statements(prefix, "\t\t", &mut out);
+		writeln!(out, "\t}}").unwrap();
+		out
+	}
+
+	fn dot_statements(&self, prefix: &str, indent: &str, out: &mut String) {
+		let id = |i: usize| format!("\"{:}{:}\"", escape(prefix), i);
+		writeln!(out, "{:}node [shape={:}];", indent, D::dot_shape()).unwrap();
+		if !self.vertices.is_empty() {
+			writeln!(out, "{:}\"{:}start\" [shape=point];", indent, escape(prefix)).unwrap();
+			writeln!(out, "{:}\"{:}start\" -> {:};", indent, escape(prefix), id(self.start)).unwrap();
+		}
+		for (i, v) in self.vertices.iter().enumerate() {
+			let peripheries = if self.ends.contains(&i) { ", peripheries=2" } else { "" };
+			writeln!(out, "{:}{:} [label=\"{:}\"{:}];", indent, id(i), escape(&v.data.dot_label(i)), peripheries).unwrap();
+		}
+
+		let mut arrows: Vec<((usize, usize), Vec<&T>)> = Vec::new();
+		let mut index: HashMap<(usize, usize), usize> = HashMap::new();
+		for e in &self.edges {
+			let key = (e.in_vertex, e.out_vertex);
+			if let Some(i) = index.get(&key) {
+				arrows[*i].1.push(&e.cost);
+			} else {
+				index.insert(key, arrows.len());
+				arrows.push((key, vec![&e.cost]));
+			}
+		}
+		for ((in_, out_), costs) in arrows {
+			writeln!(out, "{:}{:} -> {:} [label=\"{:}\"];", indent, id(in_), id(out_), escape(&T::dot_label(&costs))).unwrap();
+		}
+	}
+}
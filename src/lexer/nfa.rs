@@ -1,8 +1,13 @@
 use super::re::*;
-use super::re::EPSILON_SINGLETOKEN;
 use super::utils::*;
 
-pub fn construct_nfa(re: &RegularExpression) -> StateTransferGraph {
+/*
+ * Edges of the nfa are labelled with a char range, or None for an epsilon move
+ */
+pub type NfaGraph = StateTransferGraph<(), Option<CharRange>>;
+const EPSILON: Option<CharRange> = None;
+
+pub fn construct_nfa(re: &RegularExpression) -> NfaGraph {
 	match re {
 		&RegularExpression::Union {
 			ref operands
@@ -12,12 +17,12 @@ pub fn construct_nfa(re: &RegularExpression) -> StateTransferGraph {
 			graph.mark_as_start(state_start);
 			let mut states = Vec::new();
 			for ref o in operands {
-				let state_mid = graph.add_state_after(state_start, EPSILON_SINGLETOKEN);
+				let state_mid = graph.add_state_after(state_start, EPSILON);
 				states.push(graph.extend(state_mid, construct_nfa(o)));
 			}
 			let state_end = graph.add_state();
 			for s in states {
-				graph.add_transfer(s, state_end, EPSILON_SINGLETOKEN);
+				graph.add_transfer(s, state_end, EPSILON);
 			}
 			graph.mark_as_end(state_end);
 			graph
@@ -40,11 +45,11 @@ pub fn construct_nfa(re: &RegularExpression) -> StateTransferGraph {
 			let mut graph = StateTransferGraph::new();
 			let state_start = graph.add_state();
 			graph.mark_as_start(state_start);
-			let state_start_inner = graph.add_state_after(state_start, EPSILON_SINGLETOKEN);
+			let state_start_inner = graph.add_state_after(state_start, EPSILON);
 			let state_end_inner = graph.extend(state_start_inner, construct_nfa(operand));
-			let state_end = graph.add_state_after(state_end_inner, EPSILON_SINGLETOKEN);
-			graph.add_transfer(state_end_inner, state_start_inner, EPSILON_SINGLETOKEN);
-			graph.add_transfer(state_start, state_end, EPSILON_SINGLETOKEN);
+			let state_end = graph.add_state_after(state_end_inner, EPSILON);
+			graph.add_transfer(state_end_inner, state_start_inner, EPSILON);
+			graph.add_transfer(state_start, state_end, EPSILON);
 			graph.mark_as_end(state_end);
 			graph
 		},
@@ -61,10 +66,33 @@ pub fn construct_nfa(re: &RegularExpression) -> StateTransferGraph {
 			let mut graph = StateTransferGraph::new();
 			let state = graph.add_state();
 			graph.mark_as_start(state);
-			let state = graph.add_state_after(state, id.clone());
+			let state = graph.add_state_after(state, Some(CharRange::single(*id)));
 			graph.mark_as_end(state);
 			graph
 		},
+		&RegularExpression::Set {
+			ref ranges
+		} => {
+			let mut graph = StateTransferGraph::new();
+			let state_start = graph.add_state();
+			graph.mark_as_start(state_start);
+			let state_end = graph.add_state();
+			for r in ranges {
+				graph.add_transfer(state_start, state_end, Some(*r));
+			}
+			graph.mark_as_end(state_end);
+			graph
+		},
+		&RegularExpression::Match {
+			ref operand
+		} => {
+			construct_nfa(operand)
+		},
+		&RegularExpression::Optional { .. } |
+		&RegularExpression::OneOrMore { .. } |
+		&RegularExpression::Repetition { .. } => {
+			construct_nfa(&re.desugar())
+		},
 		_ => {
 			panic!()
 		}
@@ -77,7 +105,7 @@ pub enum MatchResult {
 	Unfinished, Err,
 }
 
-pub fn match_nfa<D: Default, T: PartialEq + Default + Clone, I: IntoIterator<Item=T>>(graph: &StateTransferGraph<D, T>, token: I) -> MatchResult {
+pub fn match_nfa<D: Default, I: IntoIterator<Item=SingleToken>>(graph: &StateTransferGraph<D, Option<CharRange>>, token: I) -> MatchResult {
 
 	let epsilon_closure = |s: &VertexSet| -> VertexSet {
 		let mut ret = s.clone();
@@ -89,7 +117,7 @@ pub fn match_nfa<D: Default, T: PartialEq + Default + Clone, I: IntoIterator<Ite
 			for i in 0..s.len() {
 				if !ret[i] { continue; }
 				for e in &graph.vertices[i].out_edges {
-					if graph.edges[*e].cost == T::default() {
+					if graph.edges[*e].cost == EPSILON {
 						let v = graph.edges[*e].out_vertex;
 						if !ret[v] {
 							ret[v] = true;
@@ -103,13 +131,13 @@ pub fn match_nfa<D: Default, T: PartialEq + Default + Clone, I: IntoIterator<Ite
 	};
 
 
-	let move_step = |s: &VertexSet, token: &T| -> VertexSet {
+	let move_step = |s: &VertexSet, token: &SingleToken| -> VertexSet {
 		let mut ret = VertexSet::with_capacity(s.len());
 		ret.resize(s.len(), false);
 		for i in 0..s.len() {
 			if !s[i] { continue; }
 			for e in &graph.vertices[i].out_edges {
-				if &graph.edges[*e].cost == token {
+				if graph.edges[*e].cost.map_or(false, |r| r.contains(*token)) {
 					let v = graph.edges[*e].out_vertex;
 					ret[v] = true;
 				}		
@@ -124,7 +152,7 @@ pub fn match_nfa<D: Default, T: PartialEq + Default + Clone, I: IntoIterator<Ite
 	let mut state = epsilon_closure(&state);
 
 	for c in token {
-		state = move_step(&state, &c);
+		state = epsilon_closure(&move_step(&state, &c));
 		if state.iter().all(|x| !x) {
 			return MatchResult::Err;
 		}
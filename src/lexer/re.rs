@@ -4,6 +4,156 @@ use std::fmt;
 
 pub type SingleToken = char;
 pub const EPSILON_SINGLETOKEN: SingleToken = '\0';
+pub const MAX_SINGLETOKEN: SingleToken = '\u{10FFFF}';
+
+/*
+ * An inclusive interval of unicode scalar values, used as the label of lexer edges
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct CharRange {
+	pub start: SingleToken,
+	pub end: SingleToken,
+}
+
+impl CharRange {
+	pub fn new(start: SingleToken, end: SingleToken) -> Self {
+		CharRange { start: start, end: end }
+	}
+
+	pub fn single(c: SingleToken) -> Self {
+		CharRange { start: c, end: c }
+	}
+
+	pub fn full() -> Self {
+		CharRange { start: EPSILON_SINGLETOKEN, end: MAX_SINGLETOKEN }
+	}
+
+	pub fn contains(&self, c: SingleToken) -> bool {
+		self.start <= c && c <= self.end
+	}
+
+	pub fn covers(&self, rhs: &CharRange) -> bool {
+		self.start <= rhs.start && rhs.end <= self.end
+	}
+}
+
+impl fmt::Display for CharRange {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		if self.start == self.end {
+			write!(f, "{:?}", self.start)
+		} else {
+			write!(f, "{:?}-{:?}", self.start, self.end)
+		}
+	}
+}
+
+/*
+ * Successor and predecessor of a scalar value, skipping the surrogate block
+ */
+pub fn next_char(c: SingleToken) -> Option<SingleToken> {
+	match c {
+		'\u{D7FF}' => Some('\u{E000}'),
+		MAX_SINGLETOKEN => None,
+		_ => std::char::from_u32(c as u32 + 1)
+	}
+}
+
+pub fn prev_char(c: SingleToken) -> Option<SingleToken> {
+	match c {
+		'\u{E000}' => Some('\u{D7FF}'),
+		EPSILON_SINGLETOKEN => None,
+		_ => std::char::from_u32(c as u32 - 1)
+	}
+}
+
+/*
+ * Sort and merge overlapping or adjacent ranges
+ */
+pub fn normalize_ranges(mut ranges: Vec<CharRange>) -> Vec<CharRange> {
+	ranges.sort();
+	let mut ret: Vec<CharRange> = Vec::new();
+	for r in ranges {
+		if let Some(last) = ret.last_mut() {
+			if next_char(last.end).map_or(true, |c| c >= r.start) {
+				if r.end > last.end { last.end = r.end; }
+				continue;
+			}
+		}
+		ret.push(r);
+	}
+	ret
+}
+
+pub fn complement_ranges(ranges: &[CharRange]) -> Vec<CharRange> {
+	let mut ret = Vec::new();
+	let mut curr = Some(EPSILON_SINGLETOKEN);
+	for r in normalize_ranges(ranges.to_vec()) {
+		if let Some(c) = curr {
+			if c < r.start { ret.push(CharRange::new(c, prev_char(r.start).unwrap())); }
+		}
+		curr = next_char(r.end);
+	}
+	if let Some(c) = curr { ret.push(CharRange::new(c, MAX_SINGLETOKEN)); }
+	ret
+}
+
+pub fn subtract_ranges(lhs: &[CharRange], rhs: &[CharRange]) -> Vec<CharRange> {
+	let neg = complement_ranges(rhs);
+	let mut ret = Vec::new();
+	for l in normalize_ranges(lhs.to_vec()) {
+		for r in &neg {
+			let start = if l.start > r.start { l.start } else { r.start };
+			let end = if l.end < r.end { l.end } else { r.end };
+			if start <= end { ret.push(CharRange::new(start, end)); }
+		}
+	}
+	ret
+}
+
+/*
+ * Split the given (possibly overlapping) ranges into disjoint elementary ranges,
+ * so that every input range is exactly a union of some of the outputs
+ */
+pub fn split_ranges<'a, I: IntoIterator<Item=&'a CharRange>>(ranges: I) -> Vec<CharRange> {
+	let ranges: Vec<CharRange> = ranges.into_iter().cloned().collect();
+	// every boundary is the first char of a new elementary range
+	let mut bounds: Vec<SingleToken> = Vec::new();
+	for r in &ranges {
+		bounds.push(r.start);
+		if let Some(c) = next_char(r.end) { bounds.push(c); }
+	}
+	bounds.sort();
+	bounds.dedup();
+	let mut ret = Vec::new();
+	for (i, start) in bounds.iter().enumerate() {
+		let end = if let Some(next) = bounds.get(i + 1) { prev_char(*next).unwrap() } else { MAX_SINGLETOKEN };
+		let range = CharRange::new(*start, end);
+		if ranges.iter().any(|r| r.covers(&range)) { ret.push(range); }
+	}
+	ret
+}
+
+/*
+ * Error from parsing a regular expression, pos is the offset in chars of the
+ * offending part in the expression
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReError {
+	pub msg: String,
+	pub pos: usize,
+}
+
+impl ReError {
+	pub fn new<S: Into<String>>(msg: S, pos: usize) -> Self {
+		ReError { msg: msg.into(), pos: pos }
+	}
+}
+
+impl fmt::Display for ReError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{:} (at char {:})", self.msg, self.pos)
+	}
+}
 
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub enum RegularExpression {
@@ -11,6 +161,9 @@ pub enum RegularExpression {
 	Atomic {
 		id: SingleToken,
 	},
+	Set {
+		ranges: Vec<CharRange>,
+	},
 	Union {
 		operands: Vec<RegularExpression>,
 	},
@@ -20,6 +173,18 @@ pub enum RegularExpression {
 	Iteration {
 		operand: Box<RegularExpression>,
 	},
+	Optional {
+		operand: Box<RegularExpression>,
+	},
+	OneOrMore {
+		operand: Box<RegularExpression>,
+	},
+	// between min and max (unbounded if None) times
+	Repetition {
+		operand: Box<RegularExpression>,
+		min: usize,
+		max: Option<usize>,
+	},
 	Alias {
 		id: String
 	},
@@ -36,7 +201,7 @@ impl Default for RegularExpression {
 
 impl RegularExpression {
 	
-	pub fn parse<'a, M: Into<Option<&'a HashMap<String, RegularExpression>>>>(src: &str, definitions: M) -> Result<RegularExpression, ()> {
+	pub fn parse<'a, M: Into<Option<&'a HashMap<String, RegularExpression>>>>(src: &str, definitions: M) -> Result<RegularExpression, ReError> {
 		let mut stack: Vec<RegularExpressionNode> = Vec::new();
 		let definitions = definitions.into();
 		let mut buffer = String::new();
@@ -45,11 +210,12 @@ impl RegularExpression {
 		let mut flag_range = false;
 		let mut flag_alias = false;
 
-		let collect_union = |stack: &mut Vec<RegularExpressionNode>| {
+		let collect_union = |stack: &mut Vec<RegularExpressionNode>| -> Result<(), &'static str> {
 			//collect union
 			// println!("{:?}", stack);
 			let mut rset = Vec::new();
 			let mut rnegset = Vec::new();
+			let mut capture = false;
 			while let Some(node) = stack.pop() {
 				match node {
 					RegularExpressionNode::Expression(e) => rset.push(e),
@@ -59,27 +225,32 @@ impl RegularExpression {
 						rset.clear();
 					},
 					RegularExpressionNode::Tuple | RegularExpressionNode::Group => break,
+					RegularExpressionNode::MatchTuple => {
+						capture = true;
+						break;
+					},
 					_ => ()
 				}
 			}
 			rset = rset.into_iter().filter(|x| !rnegset.contains(x)).collect();
-			if rset.len() == 1 {
-				stack.push(RegularExpressionNode::Expression(rset.pop().unwrap()));
+			let re = if rset.len() == 1 {
+				rset.pop().unwrap()
 			} else if rset.len() > 1 {
 				rset.reverse();
-				let re = RegularExpression::Union {	operands: rset };
-				stack.push(RegularExpressionNode::Expression(re));
-			} else { panic!("Empty union") }
+				RegularExpression::Union {	operands: rset }
+			} else { return Err("Empty alternative") };
+			stack.push(RegularExpressionNode::Expression(if capture { RegularExpression::Match { operand: Box::new(re) } } else { re }));
+			Ok(())
 		};
 
-		let collect_concat = |stack: &mut Vec<RegularExpressionNode>| {
+		let collect_concat = |stack: &mut Vec<RegularExpressionNode>| -> Result<(), &'static str> {
 			//collect concat
 			// println!("{:?}", stack);
 			let mut rset = Vec::new();
 			while let Some(node) = stack.pop() {
 				match node {
 					RegularExpressionNode::Expression(e) => rset.push(e),
-					RegularExpressionNode::Tuple | RegularExpressionNode::UnionOp => {
+					RegularExpressionNode::Tuple | RegularExpressionNode::MatchTuple | RegularExpressionNode::UnionOp => {
 						stack.push(node);
 						break;
 					},
@@ -92,11 +263,61 @@ impl RegularExpression {
 				rset.reverse();
 				let re = RegularExpression::Concatenation {	operands: rset };
 				stack.push(RegularExpressionNode::Expression(re));
-			} else { panic!("Empty concat") }
+			} else { return Err("Empty alternative") }
+			Ok(())
 		};
 
-		for token in src.chars() {
+		let collect_group = |stack: &mut Vec<RegularExpressionNode>| -> Result<(), &'static str> {
+			//collect group, everything after '^' is excluded from the group
+			let mut ranges = Vec::new();
+			let mut negranges = Vec::new();
+			let mut negate = false;
+			while let Some(node) = stack.pop() {
+				match node {
+					RegularExpressionNode::Expression(e) => ranges.extend(e.as_ranges().ok_or("Only chars are allowed in a char group")?),
+					RegularExpressionNode::GroupNegate => {
+						negranges = ranges;
+						ranges = Vec::new();
+						negate = true;
+					},
+					RegularExpressionNode::Group => break,
+					_ => ()
+				}
+			}
+			// a leading '^' negates against the whole code-point space
+			if negate && ranges.is_empty() { ranges.push(CharRange::full()); }
+			let ranges = subtract_ranges(&ranges, &negranges);
+			if ranges.is_empty() { return Err("Empty char group"); }
+			stack.push(RegularExpressionNode::Expression(RegularExpression::Set { ranges: ranges }));
+			Ok(())
+		};
+
+		let postfix = |stack: &mut Vec<RegularExpressionNode>, wrap: &dyn Fn(Box<RegularExpression>) -> RegularExpression| -> Result<(), &'static str> {
+			match stack.pop() {
+				Some(RegularExpressionNode::Expression(e)) => {
+					stack.push(RegularExpressionNode::Expression(wrap(Box::new(e))));
+					Ok(())
+				},
+				_ => Err("Nothing to repeat")
+			}
+		};
+
+		// innermost group opened by '(' or '(?'
+		let innermost = |stack: &Vec<RegularExpressionNode>| -> Option<RegularExpressionNode> {
+			stack.iter().rev().find(|n| **n == RegularExpressionNode::Tuple || **n == RegularExpressionNode::MatchTuple).cloned()
+		};
+
+		// position of the alias being read
+		let mut alias_pos = 0;
+		let chars: Vec<char> = src.chars().collect();
+		// the '?' of '(?'
+		let mut flag_skip = false;
+		for (pos, token) in chars.iter().cloned().enumerate() {
+			let next = chars.get(pos + 1).cloned();
 			match token {
+				_ if flag_skip => {
+					flag_skip = false;
+				},
 				'0' if flag_escape => {
 					stack.push(RegularExpressionNode::Expression(RegularExpression::Epsilon));
 					flag_escape = false;
@@ -112,20 +333,30 @@ impl RegularExpression {
 				},
 				_ if flag_range => {
 					if let Some(RegularExpressionNode::Expression(RegularExpression::Atomic{ id: start })) = stack.pop() {
-						let mut curr = start;
-						while curr <= token {
-							stack.push(RegularExpressionNode::Expression(RegularExpression::Atomic{ id: curr }));
-							curr =(curr as u8 + 1) as char;
-						}
+						if start > token { return Err(ReError::new(format!("Invalid char range {:?}-{:?}", start, token), pos)); }
+						stack.push(RegularExpressionNode::Expression(RegularExpression::Set{ ranges: vec![CharRange::new(start, token)] }));
 					}
 					// stack.push(RegularExpressionNode::Expression(RegularExpression::Atomic{ id: token }));
 					// collect_union()
 					flag_range = false;
 					// Range
 				},
+				// postfix operators are plain chars in a char group
+				'}' if flag_alias && !flag_group && buffer == "+" => {
+					postfix(&mut stack, &|e| RegularExpression::OneOrMore{ operand: e }).map_err(|e| ReError::new(e, alias_pos))?;
+					buffer.clear();
+					flag_alias = false;
+				},
+				'}' if flag_alias && !flag_group && is_repetition(&buffer) => {
+					let (min, max) = parse_repetition(&buffer).map_err(|e| ReError::new(e, alias_pos))?;
+					postfix(&mut stack, &|e| RegularExpression::Repetition{ operand: e, min: min, max: max }).map_err(|e| ReError::new(e, alias_pos))?;
+					buffer.clear();
+					flag_alias = false;
+				},
 				'}' if flag_alias => {
 					// stack.push(RegularExpressionNode::Expression(RegularExpression::Alias{ id: buffer.clone() }));
-					stack.push(RegularExpressionNode::Expression(definitions.expect("Expected alias table").get(&buffer).expect(&format!("Expected alias [{:}]", buffer)).clone()));
+					let alias = definitions.and_then(|d| d.get(&buffer)).ok_or(ReError::new(format!("Unknown alias [{:}]", buffer), alias_pos))?;
+					stack.push(RegularExpressionNode::Expression(alias.clone()));
 					buffer.clear();
 					flag_alias = false;
 				},
@@ -134,33 +365,40 @@ impl RegularExpression {
 				}
 				' ' | '\n' | '\t' | '\r' => (),
 				'*' => {
-					let re = stack.pop();
-					if let Some(RegularExpressionNode::Expression(e)) = re {
-						stack.push(RegularExpressionNode::Expression(RegularExpression::Iteration{ operand: Box::new(e) }));
-					} else { panic!("Iteration after operation") }
+					postfix(&mut stack, &|e| RegularExpression::Iteration{ operand: e }).map_err(|e| ReError::new(e, pos))?;
+				},
+				'?' if !flag_group => {
+					postfix(&mut stack, &|e| RegularExpression::Optional{ operand: e }).map_err(|e| ReError::new(e, pos))?;
 				},
 				'|' | '+' => {
-					collect_concat(&mut stack);
+					collect_concat(&mut stack).map_err(|e| ReError::new(e, pos))?;
 					stack.push(RegularExpressionNode::UnionOp);
 				},
+				// a capture group '(? ... )', nothing precedes the '?' to make it optional
+				'(' if !flag_group && next == Some('?') => {
+					stack.push(RegularExpressionNode::MatchTuple);
+					flag_skip = true;
+				},
 				'(' => {
 					// collect_concat(&mut stack);
 					stack.push(RegularExpressionNode::Tuple);
 				},
 				')' => {
-					collect_concat(&mut stack);
-					collect_union(&mut stack);
+					if innermost(&stack).is_none() { return Err(ReError::new("Unmatched ')'", pos)); }
+					collect_concat(&mut stack).map_err(|e| ReError::new(e, pos))?;
+					collect_union(&mut stack).map_err(|e| ReError::new(e, pos))?;
 				},
 				'[' => {
-					if flag_group { return Err(()); }
+					if flag_group { return Err(ReError::new("Nested char group", pos)); }
 					stack.push(RegularExpressionNode::Group);
 					flag_group = true;
 				},
-				']' => {
-					collect_union(&mut stack);
+				']' if flag_group => {
+					collect_group(&mut stack).map_err(|e| ReError::new(e, pos))?;
 					flag_group = false;
 				},
 				'{' => {
+					alias_pos = pos;
 					flag_alias = true;
 				},
 				'-' if flag_group => {
@@ -178,54 +416,141 @@ impl RegularExpression {
 			}
 		}
 
-		collect_concat(&mut stack);
-		collect_union(&mut stack);
+		let end = src.chars().count();
+		if flag_group { return Err(ReError::new("Unclosed char group", end)); }
+		if flag_alias { return Err(ReError::new("Unclosed alias", alias_pos)); }
+		if flag_escape || flag_range { return Err(ReError::new("Unexpected end of expression", end)); }
+		if stack.contains(&RegularExpressionNode::Tuple) { return Err(ReError::new("Unclosed '('", end)); }
+		if stack.contains(&RegularExpressionNode::MatchTuple) { return Err(ReError::new("Unclosed '(?'", end)); }
+		collect_concat(&mut stack).map_err(|e| ReError::new(e, end))?;
+		collect_union(&mut stack).map_err(|e| ReError::new(e, end))?;
 
 		if stack.len() == 1 {
 			if let Some(RegularExpressionNode::Expression(e)) = stack.pop() {
 				return Ok(e)
 			}
 		}
-		Err(())
+		Err(ReError::new("Invalid expression", end))
 	}
 
 
-	pub fn apply_alias(&mut self, alias: &HashMap<String, RegularExpression>) {
+	/*
+	 * The set of chars matched, if this expression matches exactly one char
+	 */
+	pub fn as_ranges(&self) -> Option<Vec<CharRange>> {
+		match self {
+			RegularExpression::Atomic { id } => Some(vec![CharRange::single(*id)]),
+			RegularExpression::Set { ranges } => Some(ranges.clone()),
+			RegularExpression::Union { operands } => {
+				let mut ret = Vec::new();
+				for operand in operands {
+					ret.extend(operand.as_ranges()?);
+				}
+				Some(ret)
+			},
+			_ => None
+		}
+	}
+
+	/*
+	 * The same expression written with union, concatenation and iteration only,
+	 * each copy of a repeated operand gets its own positions
+	 */
+	pub fn desugar(&self) -> RegularExpression {
+		match self {
+			RegularExpression::Union { operands } => RegularExpression::Union { operands: operands.iter().map(|x| x.desugar()).collect() },
+			RegularExpression::Concatenation { operands } => RegularExpression::Concatenation { operands: operands.iter().map(|x| x.desugar()).collect() },
+			RegularExpression::Iteration { operand } => RegularExpression::Iteration { operand: Box::new(operand.desugar()) },
+			RegularExpression::Match { operand } => RegularExpression::Match { operand: Box::new(operand.desugar()) },
+			RegularExpression::Optional { operand } => RegularExpression::Union { operands: vec![operand.desugar(), RegularExpression::Epsilon] },
+			RegularExpression::OneOrMore { operand } => {
+				let operand = operand.desugar();
+				RegularExpression::Concatenation { operands: vec![operand.clone(), RegularExpression::Iteration { operand: Box::new(operand) }] }
+			},
+			RegularExpression::Repetition { operand, min, max } => {
+				let operand = operand.desugar();
+				let mut operands = vec![operand.clone(); *min];
+				match max {
+					Some(max) => for _ in *min..*max {
+						operands.push(RegularExpression::Union { operands: vec![operand.clone(), RegularExpression::Epsilon] });
+					},
+					None => operands.push(RegularExpression::Iteration { operand: Box::new(operand) }),
+				}
+				match operands.len() {
+					0 => RegularExpression::Epsilon,
+					1 => operands.pop().unwrap(),
+					_ => RegularExpression::Concatenation { operands: operands },
+				}
+			},
+			_ => self.clone()
+		}
+	}
+
+	pub fn apply_alias(&mut self, alias: &HashMap<String, RegularExpression>) -> Result<(), ReError> {
 		let aliasid;
 		match self {
 			RegularExpression::Union { ref mut operands } => {
 				for ref mut operand in operands {
-					operand.apply_alias(alias);
+					operand.apply_alias(alias)?;
 				}
-				return;
+				return Ok(());
 			},
 			RegularExpression::Concatenation { ref mut operands } => {
 				for ref mut operand in operands {
-					operand.apply_alias(alias);
+					operand.apply_alias(alias)?;
 				}
-				return;
+				return Ok(());
 			},
-			RegularExpression::Iteration { ref mut operand } => {
-				operand.apply_alias(alias);
-				return;
+			RegularExpression::Iteration { ref mut operand } |
+			RegularExpression::Optional { ref mut operand } |
+			RegularExpression::OneOrMore { ref mut operand } |
+			RegularExpression::Repetition { ref mut operand, .. } => {
+				return operand.apply_alias(alias);
 			},
 			RegularExpression::Match { ref mut operand } => {
-				operand.apply_alias(alias);
-				return;
+				return operand.apply_alias(alias);
 			},
 			RegularExpression::Alias { ref id } => {
 				aliasid = id.clone();
 			},
-			_ => return
+			_ => return Ok(())
 		}
-		*self = alias.get(&aliasid).expect("No alias").clone();
+		*self = alias.get(&aliasid).ok_or(ReError::new(format!("Unknown alias [{:}]", aliasid), 0))?.clone();
+		Ok(())
+	}
+}
+
+/*
+ * The body of a {m}, {m,} or {m,n} repetition, as opposed to an alias name
+ */
+fn is_repetition(body: &str) -> bool {
+	!body.is_empty() && body.chars().all(|c| c.is_ascii_digit() || c == ',')
+}
+
+// desugar copies the operand once per count, the automaton grows as much
+const MAX_REPETITION: usize = 1000;
+
+pub fn parse_repetition(body: &str) -> Result<(usize, Option<usize>), &'static str> {
+	let mut parts = body.splitn(2, ',');
+	let min = parts.next().unwrap_or("").parse::<usize>().map_err(|_| "Invalid repetition")?;
+	let max = match parts.next() {
+		None => Some(min),
+		Some("") => None,
+		Some(max) => Some(max.parse::<usize>().map_err(|_| "Invalid repetition")?),
+	};
+	if let Some(max) = max {
+		if max < min { return Err("Invalid repetition, max is less than min"); }
 	}
+	if min.max(max.unwrap_or(0)) > MAX_REPETITION { return Err("Invalid repetition, counts are at most 1000"); }
+	Ok((min, max))
 }
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum RegularExpressionNode {
 	Expression(RegularExpression),
 	Tuple,
+	// a '(?' group, capturing its match
+	MatchTuple,
 	Group,
 	GroupNegate,
 	UnionOp,
@@ -234,7 +559,7 @@ pub enum RegularExpressionNode {
 
 
 #[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
-pub struct StateTransferGraph<D: Default = (), T: PartialEq = SingleToken> {
+pub struct StateTransferGraph<D: Default = (), T: PartialEq = CharRange> {
 	pub vertices: Vec<Vertex<D>>,
 	pub edges: Vec<Edge<T>>,
 	pub start: usize,
@@ -310,6 +635,47 @@ impl<D: Default, T: PartialEq> StateTransferGraph<D, T> {
 	}
 }
 
+impl<D: Default> StateTransferGraph<D, CharRange> {
+	pub fn get_transition_by_char(&self, state: usize, token: SingleToken) -> Option<usize> {
+		for i in &self.vertices[state].out_edges {
+			if self.edges[*i].cost.contains(token) {
+				return Some(self.edges[*i].out_vertex);
+			}
+		}
+		None
+	}
+
+	/*
+	 * Merge the edges between the same pair of states into as few ranges as possible
+	 */
+	pub fn coalesce_edges(&mut self) {
+		let mut edges: Vec<Edge<CharRange>> = Vec::new();
+		for v in &mut self.vertices {
+			v.in_edges.clear();
+			v.out_edges.clear();
+		}
+		let mut groups: Vec<((usize, usize), Vec<CharRange>)> = Vec::new();
+		let mut index: HashMap<(usize, usize), usize> = HashMap::new();
+		for e in self.edges.drain(..) {
+			let key = (e.in_vertex, e.out_vertex);
+			if let Some(i) = index.get(&key) {
+				groups[*i].1.push(e.cost);
+			} else {
+				index.insert(key, groups.len());
+				groups.push((key, vec![e.cost]));
+			}
+		}
+		for ((in_, out_), ranges) in groups {
+			for r in normalize_ranges(ranges) {
+				edges.push(Edge::new(in_, out_, r));
+			}
+		}
+		for e in edges {
+			self.add_transfer(e.in_vertex, e.out_vertex, e.cost);
+		}
+	}
+}
+
 
 impl<D: Default + fmt::Display, T: Clone + PartialEq + fmt::Display> fmt::Display for StateTransferGraph<D, T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -318,4 +684,63 @@ impl<D: Default + fmt::Display, T: Clone + PartialEq + fmt::Display> fmt::Displa
         writeln!(f, "start: {:?}", self.start);
         writeln!(f, "ends: {:?}", self.ends)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::lexer::dfa;
+	use crate::parser::functor::REParser;
+
+	fn parse(src: &str) -> RegularExpression {
+		RegularExpression::parse(src, None).unwrap()
+	}
+
+	fn atom(c: char) -> RegularExpression {
+		RegularExpression::Atomic { id: c }
+	}
+
+	// the lexer config dialect and re.ebnf read src the same
+	fn same_as_external(src: &str) {
+		let external = REParser::parse_from_str(src).unwrap();
+		assert!(dfa::equivalent(&dfa::construct_dfa(&parse(src)), &dfa::construct_dfa(&external)), "{:}", src);
+	}
+
+	#[test]
+	fn postfix_operators() {
+		assert_eq!(parse("a?"), RegularExpression::Optional { operand: Box::new(atom('a')) });
+		assert_eq!(parse("a{+}"), RegularExpression::OneOrMore { operand: Box::new(atom('a')) });
+		assert_eq!(parse("a{2,3}"), RegularExpression::Repetition { operand: Box::new(atom('a')), min: 2, max: Some(3) });
+		assert_eq!(parse("a{2,}"), RegularExpression::Repetition { operand: Box::new(atom('a')), min: 2, max: None });
+		assert!(RegularExpression::parse("?", None).is_err());
+		for src in &["ab?c", "(ab){+}", "a{1,2}b*"] { same_as_external(src); }
+	}
+
+	#[test]
+	fn repetition_limit() {
+		assert!(RegularExpression::parse("a{1000}", None).is_ok());
+		for src in &["a{1001}", "a{1,5000}", "a{99999999999999999999}"] {
+			assert!(RegularExpression::parse(src, None).is_err(), "{:}", src);
+			assert!(REParser::parse_from_str(src).is_err(), "{:}", src);
+		}
+		assert_eq!(RegularExpression::parse("a{2000,}", None).unwrap_err(), ReError::new("Invalid repetition, counts are at most 1000", 1));
+	}
+
+	#[test]
+	fn postfix_chars_in_char_group() {
+		assert_eq!(parse("[a?]"), RegularExpression::Set { ranges: vec![CharRange::single('?'), CharRange::single('a')] });
+		assert_eq!(parse("[?]"), RegularExpression::Set { ranges: vec![CharRange::single('?')] });
+		same_as_external("[a?]");
+	}
+
+	#[test]
+	fn capture_groups() {
+		let optional = RegularExpression::Optional { operand: Box::new(atom('c')) };
+		let capture = RegularExpression::Match { operand: Box::new(RegularExpression::Union { operands: vec![atom('b'), optional] }) };
+		assert_eq!(parse("a(?b|c?)d"), RegularExpression::Concatenation { operands: vec![atom('a'), capture, atom('d')] });
+		assert_eq!(parse("(?ab)"), RegularExpression::Match { operand: Box::new(RegularExpression::Concatenation { operands: vec![atom('a'), atom('b')] }) });
+		assert!(RegularExpression::parse("(?ab", None).is_err());
+		assert!(RegularExpression::parse("ab)", None).is_err());
+		for src in &["a(?b|c?)d", "(?a(?b)?)c"] { same_as_external(src); }
+	}
+}
@@ -0,0 +1,342 @@
+use super::re::*;
+use super::dot::DotEdge;
+use std::collections::HashSet;
+
+/*
+ * Regex matching in the dialect of the lexer configs, with submatches.
+ * The expression is compiled into a thompson nfa whose epsilon moves are
+ * ordered by priority, and simulated in a pike vm: the threads run in
+ * lockstep over the input, each one carrying the offsets saved so far.
+ * Matches are leftmost, and among them the one a backtracking matcher would
+ * find first: alternatives are tried in order and repetitions are greedy.
+ * Group 0 is the whole match, every Match expression (written '(? ... )')
+ * is a group numbered in the order of its opening.
+ */
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Transfer {
+	Epsilon,
+	Char(CharRange),
+	// record the current offset in a slot, group n has slots 2n and 2n + 1
+	Save(usize),
+}
+
+impl DotEdge for Transfer {
+	fn dot_label(costs: &[&Self]) -> String {
+		let ranges: Vec<&CharRange> = costs.iter().filter_map(|x| if let Transfer::Char(r) = x { Some(r) } else { None }).collect();
+		let mut labels = Vec::new();
+		if !ranges.is_empty() { labels.push(CharRange::dot_label(&ranges)); }
+		for cost in costs {
+			match cost {
+				Transfer::Epsilon => labels.push(String::from("ε")),
+				Transfer::Save(n) => labels.push(format!("save {:}", n)),
+				_ => ()
+			}
+		}
+		labels.join(", ")
+	}
+}
+
+// out edges of a state are in decreasing priority
+pub type CaptureGraph = StateTransferGraph<(), Transfer>;
+
+fn build(re: &RegularExpression, graph: &mut CaptureGraph, from: usize, groups: &mut usize) -> usize {
+	match re {
+		RegularExpression::Epsilon => from,
+		RegularExpression::Atomic { id } => graph.add_state_after(from, Transfer::Char(CharRange::single(*id))),
+		RegularExpression::Set { ranges } => {
+			let to = graph.add_state();
+			for r in ranges {
+				graph.add_transfer(from, to, Transfer::Char(*r));
+			}
+			to
+		},
+		RegularExpression::Concatenation { operands } => {
+			operands.iter().fold(from, |state, o| build(o, graph, state, groups))
+		},
+		RegularExpression::Union { operands } => {
+			let starts: Vec<usize> = operands.iter().map(|_| graph.add_state_after(from, Transfer::Epsilon)).collect();
+			let ends: Vec<usize> = operands.iter().zip(starts).map(|(o, s)| build(o, graph, s, groups)).collect();
+			let to = graph.add_state();
+			for e in ends {
+				graph.add_transfer(e, to, Transfer::Epsilon);
+			}
+			to
+		},
+		RegularExpression::Iteration { operand } => {
+			let head = graph.add_state_after(from, Transfer::Epsilon);
+			let start = graph.add_state_after(head, Transfer::Epsilon);
+			let end = build(operand, graph, start, groups);
+			graph.add_transfer(end, head, Transfer::Epsilon);
+			graph.add_state_after(head, Transfer::Epsilon)
+		},
+		RegularExpression::OneOrMore { operand } => {
+			let start = graph.add_state_after(from, Transfer::Epsilon);
+			let end = build(operand, graph, start, groups);
+			let tail = graph.add_state_after(end, Transfer::Epsilon);
+			graph.add_transfer(tail, start, Transfer::Epsilon);
+			graph.add_state_after(tail, Transfer::Epsilon)
+		},
+		RegularExpression::Optional { operand } => {
+			let start = graph.add_state_after(from, Transfer::Epsilon);
+			let end = build(operand, graph, start, groups);
+			let to = graph.add_state_after(end, Transfer::Epsilon);
+			graph.add_transfer(from, to, Transfer::Epsilon);
+			to
+		},
+		RegularExpression::Repetition { .. } => build(&re.desugar(), graph, from, groups),
+		RegularExpression::Match { operand } => {
+			*groups += 1;
+			let group = *groups;
+			let start = graph.add_state_after(from, Transfer::Save(group * 2));
+			let end = build(operand, graph, start, groups);
+			graph.add_state_after(end, Transfer::Save(group * 2 + 1))
+		},
+		RegularExpression::Alias { id } => panic!("Unresolved alias [{:}]", id),
+	}
+}
+
+pub fn construct_capture_nfa(re: &RegularExpression) -> (CaptureGraph, usize) {
+	let mut graph = CaptureGraph::new();
+	let mut groups = 0;
+	let start = graph.add_state();
+	graph.mark_as_start(start);
+	let state = graph.add_state_after(start, Transfer::Save(0));
+	let state = build(re, &mut graph, state, &mut groups);
+	let end = graph.add_state_after(state, Transfer::Save(1));
+	graph.mark_as_end(end);
+	(graph, groups + 1)
+}
+
+type Slots = Vec<Option<usize>>;
+
+#[derive(Debug, Clone)]
+pub struct Regex {
+	graph: CaptureGraph,
+	groups: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Match<'t> {
+	text: &'t str,
+	start: usize,
+	end: usize,
+}
+
+impl<'t> Match<'t> {
+	pub fn start(&self) -> usize {
+		self.start
+	}
+
+	pub fn end(&self) -> usize {
+		self.end
+	}
+
+	pub fn as_str(&self) -> &'t str {
+		&self.text[self.start..self.end]
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Captures<'t> {
+	text: &'t str,
+	slots: Slots,
+}
+
+impl<'t> Captures<'t> {
+	// None if the group took no part in the match
+	pub fn get(&self, group: usize) -> Option<Match<'t>> {
+		match (self.slots.get(group * 2)?, self.slots.get(group * 2 + 1)?) {
+			(Some(start), Some(end)) => Some(Match { text: self.text, start: *start, end: *end }),
+			_ => None
+		}
+	}
+
+	pub fn len(&self) -> usize {
+		self.slots.len() / 2
+	}
+}
+
+pub struct Matches<'r, 't> {
+	regex: &'r Regex,
+	text: &'t str,
+	offset: usize,
+	last_end: Option<usize>,
+}
+
+impl<'r, 't> Iterator for Matches<'r, 't> {
+	type Item = Match<'t>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		while self.offset <= self.text.len() {
+			let m = self.regex.find_at(self.text, self.offset)?;
+			if m.start == m.end {
+				// step over an empty match, and never report one right after the previous match
+				self.offset = m.end + self.text[m.end..].chars().next().map_or(1, |c| c.len_utf8());
+				if self.last_end == Some(m.end) { continue; }
+			} else {
+				self.offset = m.end;
+			}
+			self.last_end = Some(m.end);
+			return Some(m);
+		}
+		None
+	}
+}
+
+impl Regex {
+	pub fn new(src: &str) -> Result<Regex, ReError> {
+		Ok(Regex::from_expression(&RegularExpression::parse(src, None)?))
+	}
+
+	// aliases must have been resolved
+	pub fn from_expression(re: &RegularExpression) -> Regex {
+		let (graph, groups) = construct_capture_nfa(re);
+		Regex { graph: graph, groups: groups }
+	}
+
+	pub fn graph(&self) -> &CaptureGraph {
+		&self.graph
+	}
+
+	// number of groups, counting the whole match
+	pub fn captures_len(&self) -> usize {
+		self.groups
+	}
+
+	pub fn is_match(&self, text: &str) -> bool {
+		self.find(text).is_some()
+	}
+
+	pub fn find<'t>(&self, text: &'t str) -> Option<Match<'t>> {
+		self.find_at(text, 0)
+	}
+
+	pub fn find_at<'t>(&self, text: &'t str, start: usize) -> Option<Match<'t>> {
+		let slots = self.run(text, start)?;
+		Some(Match { text: text, start: slots[0]?, end: slots[1]? })
+	}
+
+	pub fn captures<'t>(&self, text: &'t str) -> Option<Captures<'t>> {
+		self.captures_at(text, 0)
+	}
+
+	pub fn captures_at<'t>(&self, text: &'t str, start: usize) -> Option<Captures<'t>> {
+		Some(Captures { text: text, slots: self.run(text, start)? })
+	}
+
+	pub fn find_iter<'r, 't>(&'r self, text: &'t str) -> Matches<'r, 't> {
+		Matches { regex: self, text: text, offset: 0, last_end: None }
+	}
+
+	/*
+	 * Follow the epsilon moves from state in priority order, adding every
+	 * state with a char edge or accepting to the thread list.
+	 * A state already reached at this offset is held by a thread of higher
+	 * priority, so it is not added twice.
+	 */
+	fn add_thread(&self, threads: &mut Vec<(usize, Slots)>, seen: &mut HashSet<usize>, state: usize, slots: Slots, offset: usize) {
+		if !seen.insert(state) { return; }
+		let vertex = &self.graph.vertices[state];
+		if self.graph.ends.contains(&state) || vertex.out_edges.iter().any(|e| if let Transfer::Char(_) = self.graph.edges[*e].cost { true } else { false }) {
+			threads.push((state, slots.clone()));
+		}
+		for e in &vertex.out_edges {
+			let edge = &self.graph.edges[*e];
+			match edge.cost {
+				Transfer::Epsilon => self.add_thread(threads, seen, edge.out_vertex, slots.clone(), offset),
+				Transfer::Save(n) => {
+					let mut slots = slots.clone();
+					slots[n] = Some(offset);
+					self.add_thread(threads, seen, edge.out_vertex, slots, offset);
+				},
+				Transfer::Char(_) => (),
+			}
+		}
+	}
+
+	// slots of the leftmost-first match starting from offset start
+	fn run(&self, text: &str, start: usize) -> Option<Slots> {
+		if start > text.len() { return None; }
+		let mut threads: Vec<(usize, Slots)> = Vec::new();
+		// states reached at the current offset
+		let mut seen = HashSet::new();
+		let mut matched: Option<Slots> = None;
+		let offsets = text[start..].char_indices().map(|(i, c)| (start + i, Some(c))).chain(Some((text.len(), None)));
+
+		for (offset, c) in offsets {
+			// a new match may only begin while none was found
+			if matched.is_none() {
+				self.add_thread(&mut threads, &mut seen, self.graph.start, vec![None; self.groups * 2], offset);
+			}
+			if threads.is_empty() { break; }
+
+			let mut next = Vec::new();
+			let mut next_seen = HashSet::new();
+			let next_offset = offset + c.map_or(0, |c| c.len_utf8());
+			for (state, slots) in threads.drain(..) {
+				if self.graph.ends.contains(&state) {
+					// threads after this one have lower priority
+					matched = Some(slots);
+					break;
+				}
+				let c = if let Some(c) = c { c } else { continue };
+				for e in &self.graph.vertices[state].out_edges {
+					let edge = &self.graph.edges[*e];
+					if let Transfer::Char(r) = edge.cost {
+						if r.contains(c) {
+							self.add_thread(&mut next, &mut next_seen, edge.out_vertex, slots.clone(), next_offset);
+						}
+					}
+				}
+			}
+			threads = next;
+			seen = next_seen;
+		}
+		matched
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// the text of each group of the first match, None if it took no part
+	fn groups(re: &str, text: &str) -> Vec<Option<String>> {
+		let caps = Regex::new(re).unwrap().captures(text).expect(re);
+		(0..caps.len()).map(|i| caps.get(i).map(|m| m.as_str().to_string())).collect()
+	}
+
+	fn some(groups: &[&str]) -> Vec<Option<String>> {
+		groups.iter().map(|x| Some(x.to_string())).collect()
+	}
+
+	#[test]
+	fn groups_numbered_by_opening() {
+		assert_eq!(Regex::new("(?a(?b))c").unwrap().captures_len(), 3);
+		assert_eq!(groups("(?a(?b))c", "xabc"), some(&["abc", "ab", "b"]));
+	}
+
+	#[test]
+	fn leftmost_first() {
+		// alternatives in order, as a backtracking matcher tries them
+		assert_eq!(groups("(?a|ab)(?c|bcd)", "abcd"), some(&["abcd", "a", "bcd"]));
+		// repetitions are greedy
+		assert_eq!(groups("(?a*)(?a*)", "aaa"), some(&["aaa", "aaa", ""]));
+	}
+
+	#[test]
+	fn unmatched_and_repeated_groups() {
+		assert_eq!(groups("(?a)|(?b)", "b"), vec![Some("b".to_string()), None, Some("b".to_string())]);
+		// a group in a repetition keeps its last iteration
+		assert_eq!(groups("(?[ab])*", "ab"), some(&["ab", "b"]));
+	}
+
+	#[test]
+	fn find_iter() {
+		let re = Regex::new("a*").unwrap();
+		let found: Vec<(usize, usize)> = re.find_iter("baaa").map(|m| (m.start(), m.end())).collect();
+		assert_eq!(found, vec![(0, 0), (1, 4)]);
+		assert!(Regex::new("[0-9][0-9]*").unwrap().find("abc").is_none());
+	}
+}
@@ -0,0 +1,420 @@
+use super::re::*;
+use super::{RegularRule, Token, LexAction, LexError, INITIAL_MODE};
+use std::collections::HashMap;
+use std::cmp::Ordering;
+use crate::utils::span::Span;
+
+/*
+ * A single automaton merging the dfa of every rule active in one mode.
+ * Each state of the automaton is a tuple of the states of all rule dfas, so one
+ * transition advances every rule at once. The accepting rule of a state is the
+ * earliest rule (in config order) whose own dfa accepts there.
+ */
+
+#[derive(Debug, Clone, Default)]
+pub struct Automaton {
+	// index in the rule list of each merged rule
+	rules: Vec<usize>,
+	classes: Vec<CharRange>,
+	transitions: Vec<Vec<Option<usize>>>,
+	accepts: Vec<Option<usize>>,
+	// earliest rule still matching in each state
+	lives: Vec<Option<usize>>,
+	start: usize,
+}
+
+/*
+ * One automaton per start condition, plus the stack of modes driven by the
+ * rule actions while tokenizing.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct Scanner {
+	pub rules: Vec<RegularRule>,
+	modes: HashMap<String, Automaton>,
+	// rule matching the end of input in each mode
+	eofs: HashMap<String, usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScanResult {
+	// (rule, length in bytes)
+	Matched(usize, usize),
+	// (length in bytes of the input consumed before failing, rule still matching then)
+	Failed(usize, Option<usize>),
+}
+
+impl Automaton {
+	pub fn new(all: &[RegularRule], indices: Vec<usize>) -> Self {
+		let rules: Vec<&RegularRule> = indices.iter().map(|i| &all[*i]).collect();
+		// disjoint char classes, every edge of every rule is a union of some of them
+		let classes = split_ranges(rules.iter().flat_map(|r| r.expr.edges.iter().map(|e| &e.cost)));
+
+		// dense transition table of every rule: rule -> state -> class -> state
+		let tables: Vec<Vec<Vec<Option<usize>>>> = rules.iter().map(|r| {
+			r.expr.vertices.iter().map(|v| {
+				let mut row = vec![None; classes.len()];
+				for e in &v.out_edges {
+					let edge = &r.expr.edges[*e];
+					let first = classes.binary_search_by(|x| x.start.cmp(&edge.cost.start)).unwrap();
+					for c in first..classes.len() {
+						if !edge.cost.covers(&classes[c]) { break; }
+						if row[c].is_none() { row[c] = Some(edge.out_vertex); }
+					}
+				}
+				row
+			}).collect()
+		}).collect();
+
+		let accepting = |tuple: &Vec<Option<usize>>| -> Option<usize> {
+			tuple.iter().enumerate().find(|(i, s)| if let Some(s) = s { rules[*i].expr.ends.contains(s) } else { false }).map(|(i, _)| i)
+		};
+		let living = |tuple: &Vec<Option<usize>>| -> Option<usize> {
+			tuple.iter().position(|s| s.is_some())
+		};
+
+		let mut automaton = Automaton {
+			classes: classes,
+			..Default::default()
+		};
+
+		let mut states: HashMap<Vec<Option<usize>>, usize> = HashMap::new();
+		let mut stack: Vec<Vec<Option<usize>>> = Vec::new();
+
+		let start: Vec<Option<usize>> = rules.iter().map(|r| Some(r.expr.start)).collect();
+		automaton.accepts.push(accepting(&start));
+		automaton.lives.push(living(&start));
+		automaton.transitions.push(Vec::new());
+		states.insert(start.clone(), 0);
+		stack.push(start);
+
+		while let Some(tuple) = stack.pop() {
+			let state = *states.get(&tuple).unwrap();
+			let mut row = vec![None; automaton.classes.len()];
+			for c in 0..automaton.classes.len() {
+				let ahead: Vec<Option<usize>> = tuple.iter().enumerate().map(|(i, s)| {
+					let s = (*s)?;
+					// a non-greedy rule stops at its first match
+					if !rules[i].greedy && rules[i].expr.ends.contains(&s) { return None; }
+					tables[i][s][c]
+				}).collect();
+				if ahead.iter().all(|s| s.is_none()) { continue; }
+
+				row[c] = Some(if let Some(ind) = states.get(&ahead) { *ind } else {
+					let ind = automaton.accepts.len();
+					automaton.accepts.push(accepting(&ahead));
+					automaton.lives.push(living(&ahead));
+					automaton.transitions.push(Vec::new());
+					states.insert(ahead.clone(), ind);
+					stack.push(ahead);
+					ind
+				});
+			}
+			automaton.transitions[state] = row;
+		}
+		automaton.rules = indices;
+		automaton
+	}
+
+	pub fn len(&self) -> usize {
+		self.transitions.len()
+	}
+
+	pub fn start(&self) -> usize {
+		self.start
+	}
+
+	pub fn classes(&self) -> &[CharRange] {
+		&self.classes
+	}
+
+	// next state of state on every char class
+	pub fn row(&self, state: usize) -> &[Option<usize>] {
+		&self.transitions[state]
+	}
+
+	pub fn step(&self, state: usize, c: SingleToken) -> Option<usize> {
+		let class = self.classes.binary_search_by(|x| if x.end < c { Ordering::Less } else if x.start > c { Ordering::Greater } else { Ordering::Equal }).ok()?;
+		self.transitions[state][class]
+	}
+
+	// index in the rule list of the rule accepted at state
+	pub fn accept(&self, state: usize) -> Option<usize> {
+		self.accepts[state].map(|r| self.rules[r])
+	}
+
+	pub fn living(&self, state: usize) -> Option<usize> {
+		self.lives[state].map(|r| self.rules[r])
+	}
+
+	/*
+	 * Longest match from the beginning of src, ties go to the earliest rule.
+	 */
+	pub fn scan(&self, src: &str) -> ScanResult {
+		self.scan_partial(src).0
+	}
+
+	/*
+	 * Same as scan, also telling if the automaton was still running at the end
+	 * of src, in which case more input could give a longer match.
+	 */
+	pub fn scan_partial(&self, src: &str) -> (ScanResult, bool) {
+		let mut state = self.start;
+		let mut last = None;
+		let mut consumed = 0;
+		let mut running = true;
+		for (i, c) in src.char_indices() {
+			if let Some(next) = self.step(state, c) {
+				state = next;
+				consumed = i + c.len_utf8();
+				if let Some(rule) = self.accept(state) {
+					last = Some((rule, consumed));
+				}
+			} else {
+				running = false;
+				break;
+			}
+		}
+		// a state without any transition can not go further either
+		let running = running && self.transitions[state].iter().any(|t| t.is_some());
+		if let Some((rule, len)) = last { (ScanResult::Matched(rule, len), running) } else { (ScanResult::Failed(consumed, if consumed > 0 { self.living(state) } else { None }), running) }
+	}
+}
+
+/*
+ * Where the scanner is in the input: the next char, the modes entered and
+ * the text kept by more. Offsets count from the beginning of the whole input.
+ */
+#[derive(Debug, Clone)]
+pub struct ScanState {
+	pub offset: usize,
+	pub line: usize,
+	pub column: usize,
+	stack: Vec<String>,
+	// (start, line, column, rule) of the text kept by more
+	pending: Option<(usize, usize, usize, usize)>,
+	finished: bool,
+}
+
+impl Default for ScanState {
+	fn default() -> Self {
+		ScanState {
+			offset: 0,
+			line: 1,
+			column: 1,
+			stack: vec![INITIAL_MODE.to_string()],
+			pending: None,
+			finished: false,
+		}
+	}
+}
+
+impl ScanState {
+	pub fn mode(&self) -> &str {
+		self.stack.last().unwrap()
+	}
+
+	// first offset the scanner may still need
+	pub fn keep_from(&self) -> usize {
+		if let Some((start, ..)) = self.pending { start } else { self.offset }
+	}
+
+	fn advance(&mut self, text: &str) {
+		self.offset += text.len();
+		if let Some(i) = text.rfind('\n') {
+			self.line += text.matches('\n').count();
+			self.column = text[i + 1..].chars().count() + 1;
+		} else {
+			self.column += text.chars().count();
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+	Token(Token),
+	Error(LexError),
+	// ignored or kept by more
+	Skipped,
+	// the input so far is not enough to decide
+	NeedInput,
+	End,
+}
+
+// every mode named by the rules, INITIAL first
+pub fn mode_names(rules: &[RegularRule]) -> Vec<String> {
+	let mut names = vec![INITIAL_MODE.to_string()];
+	for rule in rules {
+		for m in rule.modes.iter().chain(rule.actions.iter().filter_map(|a| match a {
+			LexAction::Push(m) | LexAction::Begin(m) => Some(m),
+			_ => None
+		})) {
+			if !names.contains(m) { names.push(m.clone()); }
+		}
+	}
+	names
+}
+
+impl Scanner {
+	pub fn new(rules: &[RegularRule]) -> Self {
+		let names = mode_names(rules);
+		let mut scanner = Scanner {
+			rules: rules.to_vec(),
+			..Default::default()
+		};
+		for mode in names {
+			let active: Vec<usize> = (0..rules.len()).filter(|i| rules[*i].active_in(&mode)).collect();
+			if let Some(i) = active.iter().find(|i| rules[**i].eof) {
+				scanner.eofs.insert(mode.clone(), *i);
+			}
+			let active = active.into_iter().filter(|i| !rules[*i].eof).collect();
+			scanner.modes.insert(mode, Automaton::new(rules, active));
+		}
+		scanner
+	}
+
+	pub fn automaton(&self, mode: &str) -> Option<&Automaton> {
+		self.modes.get(mode)
+	}
+
+	// every mode, INITIAL first and the others by name
+	pub fn modes(&self) -> Vec<&str> {
+		let mut names: Vec<&str> = self.modes.keys().map(|m| m.as_str()).filter(|m| *m != INITIAL_MODE).collect();
+		names.sort();
+		names.insert(0, INITIAL_MODE);
+		names
+	}
+
+	// index in the rule list of the rule matching the end of input in mode
+	pub fn eof(&self, mode: &str) -> Option<usize> {
+		self.eofs.get(mode).cloned()
+	}
+
+	pub fn tokenize(&self, src: &str) -> Result<Vec<Token>, LexError> {
+		let (tokens, mut errors) = self.run(src, false);
+		if errors.is_empty() { Ok(tokens) } else { Err(errors.remove(0)) }
+	}
+
+	pub fn tokenize_recover(&self, src: &str) -> (Vec<Token>, Vec<LexError>) {
+		self.run(src, true)
+	}
+
+	fn run(&self, src: &str, recover: bool) -> (Vec<Token>, Vec<LexError>) {
+		let mut state = ScanState::default();
+		let mut tokens = Vec::new();
+		let mut errors = Vec::new();
+		loop {
+			match self.next_step(&mut state, src, 0, true) {
+				Step::Token(t) => tokens.push(t),
+				Step::Error(e) => {
+					errors.push(e);
+					if !recover { break; }
+				},
+				Step::Skipped => (),
+				Step::NeedInput | Step::End => break,
+			}
+		}
+		(tokens, errors)
+	}
+
+	/*
+	 * Scan one token from buffer, which holds the input from offset base.
+	 * Unless at_end, a match running up to the end of buffer is left for
+	 * later, as more input may extend it.
+	 * Unmatched input is skipped as far as the scanner went (at least one
+	 * char) and reported, scanning goes on in the same mode after it.
+	 */
+	pub fn next_step(&self, state: &mut ScanState, buffer: &str, base: usize, at_end: bool) -> Step {
+		let rest = &buffer[state.offset - base..];
+		let (start, line, column) = if let Some((start, line, column, _)) = state.pending { (start, line, column) } else {
+			(state.offset, state.line, state.column)
+		};
+		let value = |end: usize| buffer[start - base..end - base].to_string();
+
+		if rest.is_empty() {
+			if !at_end { return Step::NeedInput; }
+			if state.finished { return Step::End; }
+			// end of input, in the mode left by the last token
+			state.finished = true;
+			if let Some(rule) = self.eofs.get(state.mode()).map(|r| &self.rules[*r]) {
+				state.pending = None;
+				return if rule.ignore { Step::Skipped } else { make_token(rule, value(state.offset), state, start, line, column) };
+			} else if let Some((_, _, _, rule)) = state.pending.take() {
+				return Step::Error(LexError {
+					msg: String::from("Unexpected end of input"),
+					rule: Some(self.rules[rule].name.clone()),
+					text: value(state.offset),
+					line: line,
+					column: column,
+					start: start,
+					end: state.offset,
+				});
+			}
+			return Step::End;
+		}
+
+		let mode = state.mode().to_string();
+		let (result, running) = self.modes[&mode].scan_partial(rest);
+		if running && !at_end { return Step::NeedInput; }
+		let (rule, len) = match result {
+			ScanResult::Matched(rule, len) => (Ok(rule), len),
+			ScanResult::Failed(0, _) => (Err(None), rest.chars().next().unwrap().len_utf8()),
+			ScanResult::Failed(len, rule) => (Err(rule), len),
+		};
+		state.advance(&rest[..len]);
+
+		let (ind, rule) = match rule {
+			Ok(ind) => (ind, &self.rules[ind]),
+			Err(rule) => {
+				state.pending = None;
+				return Step::Error(LexError {
+					msg: if mode == INITIAL_MODE { String::from("Unmatched input") } else { format!("Unmatched input in mode {:}", mode) },
+					rule: rule.map(|r| self.rules[r].name.clone()),
+					text: value(state.offset),
+					line: line,
+					column: column,
+					start: start,
+					end: state.offset,
+				});
+			}
+		};
+		let mut more = false;
+		for action in &rule.actions {
+			match action {
+				LexAction::Push(m) => state.stack.push(m.clone()),
+				LexAction::Pop => if state.stack.len() > 1 { state.stack.pop(); },
+				LexAction::Begin(m) => *state.stack.last_mut().unwrap() = m.clone(),
+				LexAction::More => more = true,
+				_ => ()
+			}
+		}
+		if more {
+			state.pending = Some((start, line, column, ind));
+			return Step::Skipped;
+		}
+		state.pending = None;
+		if rule.ignore { return Step::Skipped; }
+		make_token(rule, value(state.offset), state, start, line, column)
+	}
+}
+
+// token of rule ending at the current position of state, or the error of its value actions
+fn make_token(rule: &RegularRule, text: String, state: &ScanState, start: usize, line: usize, column: usize) -> Step {
+	match rule.literal(&text) {
+		Ok(literal) => Step::Token(Token {
+			type_: rule.token_type(&text).to_string(),
+			value_: text,
+			line_: state.line,
+			span_: Span::new(start, state.offset, line, column),
+			literal_: literal,
+		}),
+		Err(msg) => Step::Error(LexError {
+			msg: msg,
+			rule: Some(rule.name.clone()),
+			text: text,
+			line: line,
+			column: column,
+			start: start,
+			end: state.offset,
+		}),
+	}
+}
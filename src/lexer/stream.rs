@@ -0,0 +1,178 @@
+use super::scanner::{Scanner, ScanState, Step};
+use super::{RegularRule, Token, LexError};
+use std::io::{self, BufRead};
+
+/*
+ * Tokenizer pulling its input from a reader, or from text fed by hand.
+ * Tokens come out as soon as they can not grow any more, the text before
+ * them is dropped, so the input is never lexed twice.
+ * Without a reader, the lexer waits for feed() when it runs out of input, and
+ * close() tells it that nothing more will come.
+ */
+pub struct Lexer<R: BufRead = io::Empty> {
+	scanner: Scanner,
+	reader: Option<R>,
+	// input from offset base on
+	buffer: String,
+	base: usize,
+	state: ScanState,
+	closed: bool,
+}
+
+impl Lexer<io::Empty> {
+	pub fn new(rules: &[RegularRule]) -> Self {
+		Lexer {
+			scanner: Scanner::new(rules),
+			reader: None,
+			buffer: String::new(),
+			base: 0,
+			state: ScanState::default(),
+			closed: false,
+		}
+	}
+}
+
+impl<R: BufRead> Lexer<R> {
+	pub fn from_reader(rules: &[RegularRule], reader: R) -> Self {
+		Lexer {
+			scanner: Scanner::new(rules),
+			reader: Some(reader),
+			buffer: String::new(),
+			base: 0,
+			state: ScanState::default(),
+			closed: false,
+		}
+	}
+
+	pub fn feed(&mut self, text: &str) {
+		self.buffer.push_str(text);
+	}
+
+	pub fn close(&mut self) {
+		self.closed = true;
+	}
+
+	// line and column of the next char to scan
+	pub fn position(&self) -> (usize, usize) {
+		(self.state.line, self.state.column)
+	}
+
+	fn compact(&mut self) {
+		let keep = self.state.keep_from();
+		if keep > self.base {
+			self.buffer.drain(..keep - self.base);
+			self.base = keep;
+		}
+	}
+}
+
+impl<R: BufRead> Iterator for Lexer<R> {
+	type Item = Result<Token, LexError>;
+
+	/*
+	 * None when the input is exhausted: at the end of the reader or after
+	 * close(), or until more text is fed otherwise.
+	 */
+	fn next(&mut self) -> Option<Self::Item> {
+		loop {
+			match self.scanner.next_step(&mut self.state, &self.buffer, self.base, self.closed) {
+				Step::Token(token) => {
+					self.compact();
+					return Some(Ok(token));
+				},
+				Step::Error(e) => {
+					self.compact();
+					return Some(Err(e));
+				},
+				Step::Skipped => self.compact(),
+				Step::NeedInput => {
+					let reader = self.reader.as_mut()?;
+					match reader.read_line(&mut self.buffer) {
+						Ok(0) => self.closed = true,
+						Ok(_) => (),
+						Err(e) => {
+							self.closed = true;
+							return Some(Err(LexError::from(e)));
+						}
+					}
+				},
+				Step::End => return None,
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::lexer::{read_config, tokenize};
+	use crate::utils::read_file;
+	use std::io::BufReader;
+
+	fn cool() -> Vec<RegularRule> {
+		read_config("examples/cool/cool.lex").unwrap().0
+	}
+
+	fn types<R: BufRead>(lexer: &mut Lexer<R>) -> Vec<String> {
+		lexer.by_ref().map(|x| x.unwrap().type_).collect()
+	}
+
+	#[test]
+	fn token_across_feeds() {
+		let mut lexer = Lexer::new(&cool());
+		lexer.feed("cla");
+		assert!(lexer.next().is_none());
+		lexer.feed("ss Main");
+		let token = lexer.next().unwrap().unwrap();
+		assert_eq!((token.type_.as_str(), token.value_.as_str()), ("CLASS", "class"));
+		// Main may go on
+		assert!(lexer.next().is_none());
+		lexer.close();
+		assert_eq!(types(&mut lexer), vec!["TYPEID"]);
+	}
+
+	#[test]
+	fn lookahead_waits() {
+		let mut lexer = Lexer::new(&cool());
+		lexer.feed("x <");
+		assert_eq!(types(&mut lexer), vec!["OBJECTID"]);
+		// < may be the start of <- or <=
+		assert!(lexer.next().is_none());
+		lexer.feed("- 1;");
+		assert_eq!(types(&mut lexer), vec!["ASSIGN", "INT_CONST", "DELIMITER"]);
+		lexer.feed("y <");
+		lexer.close();
+		assert_eq!(types(&mut lexer), vec!["OBJECTID", "OP_LT"]);
+	}
+
+	#[test]
+	fn eof_after_close() {
+		let mut lexer = Lexer::new(&cool());
+		lexer.feed("\"abc");
+		assert!(lexer.next().is_none());
+		lexer.feed("def");
+		assert!(lexer.next().is_none());
+		lexer.close();
+		assert_eq!(types(&mut lexer), vec!["EOF_IN_STRING"]);
+		assert!(lexer.next().is_none());
+	}
+
+	#[test]
+	fn same_as_tokenize() {
+		let rules = cool();
+		let src = read_file("examples/cool/test.cl").unwrap();
+		let expected = tokenize(&src, &rules).unwrap();
+		let from_reader: Vec<Token> = Lexer::from_reader(&rules, BufReader::with_capacity(4, src.as_bytes())).map(|x| x.unwrap()).collect();
+		assert_eq!(from_reader, expected);
+		let mut lexer = Lexer::new(&rules);
+		let mut fed = Vec::new();
+		let chars: Vec<char> = src.chars().collect();
+		for chunk in chars.chunks(3) {
+			lexer.feed(&chunk.iter().collect::<String>());
+			fed.extend(lexer.by_ref().map(|x| x.unwrap()));
+		}
+		lexer.close();
+		fed.extend(lexer.map(|x| x.unwrap()));
+		assert_eq!(fed, expected);
+	}
+}
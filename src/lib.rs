@@ -5,6 +5,8 @@ extern crate serde;
 extern crate serde_derive;
 
 extern crate serde_yaml;
+extern crate bincode;
+extern crate serde_json;
 
 pub static mut DEBUG: bool = false;
 pub static mut VERBOSE: bool = false;
@@ -3,17 +3,22 @@ mod prelude;
 pub mod llparser;
 pub mod lrparser;
 pub mod glrparser;
+pub mod earleyparser;
+pub mod sppf;
 pub mod rdparser;
 pub mod grammar;
-mod derivations;
 
 pub mod transform;
 pub mod functor;
 
 use crate::lexer::Token;
 use crate::utils::tree;
+use crate::utils::span::Span;
+use crate::utils::{FINISH_TOKEN, ERROR_TOKEN};
 use self::grammar::*;
 
+use std::fmt;
+
 
 pub type Node = tree::Node<NodeType>;
 
@@ -27,6 +32,9 @@ pub struct NonTerminal {
     pub type_: String,
     pub value_: String,
     pub rule_: Production,
+    // from the first to the last token under this node
+    #[serde(default)]
+    pub span_: Span,
 }
 
 impl<'a> From<&'a Production> for NonTerminal {
@@ -34,7 +42,127 @@ impl<'a> From<&'a Production> for NonTerminal {
         NonTerminal {
             type_: rule.name.clone(),
             value_: rule.label.clone(),
-            rule_: rule.clone()
+            rule_: rule.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+/*
+ * Source spans of the nodes.
+ * A node without any located token under it (epsilon, FINISH, tokens loaded
+ * from an old model) has no span.
+ */
+impl Node {
+    pub fn span(&self) -> Option<Span> {
+        match &self.value {
+            NodeType::Terminal(Token { span_, .. }) | NodeType::NonTerminal(NonTerminal { span_, .. }) => if span_.is_empty() { None } else { Some(*span_) },
+            _ => self.childs_span(),
+        }
+    }
+
+    fn childs_span(&self) -> Option<Span> {
+        let first = self.childs.iter().filter_map(|x| x.span()).next()?;
+        let last = self.childs.iter().rev().filter_map(|x| x.span()).next()?;
+        Some(first.to(&last))
+    }
+
+    // Refresh the span of a non terminal from its (already located) childs
+    pub fn update_span(&mut self) {
+        let span = self.childs_span();
+        if let NodeType::NonTerminal(nt) = &mut self.value {
+            nt.span_ = span.unwrap_or_default();
+        }
+    }
+
+    pub fn compute_spans(&mut self) {
+        for child in self.childs.iter_mut() {
+            child.compute_spans();
+        }
+        self.update_span();
+    }
+}
+
+// the span of a node, empty if it has none
+impl<'a> From<&'a Node> for Span {
+    fn from(node: &'a Node) -> Self {
+        node.span().unwrap_or_default()
+    }
+}
+
+/*
+ * A syntax error of any of the parsers, at a token index of the input.
+ * expected holds the terminals that would have been accepted there, they are
+ * shown by the names the grammar gives them (see Grammar::term_name), fix
+ * tells how the parser went on if it did.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub msg: String,
+    pub index: usize,
+    pub expected: Vec<Term>,
+    pub fix: Option<Fix>,
+}
+
+// how a parser went on after an error
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fix {
+    Inserted(Term),
+    Skipped,
+}
+
+impl ParseError {
+    pub fn new<S: Into<String>>(msg: S, index: usize) -> Self {
+        ParseError { msg: msg.into(), index: index, expected: Vec::new(), fix: None }
+    }
+
+    // an unexpected token at index of src, past its end the end of input
+    pub fn unexpected(src: &[Token], index: usize) -> Self {
+        match src.get(index) {
+            Some(token) if token.type_ != FINISH_TOKEN => ParseError::new(format!("Syntax error at or near {:} = {:}", token.type_, token.value_), index),
+            _ => ParseError::new("Syntax error at end of input", index),
+        }
+    }
+
+    // add terminals to the expected ones, the error token is never expected
+    pub fn expecting<I: IntoIterator<Item = Term>>(mut self, terms: I) -> Self {
+        for term in terms {
+            if let Term::Terminal { ref type_, .. } = term {
+                if type_ != ERROR_TOKEN && !self.expected.contains(&term) { self.expected.push(term); }
+            }
+        }
+        self.expected.sort_by_key(|x| x.to_string());
+        self
+    }
+
+    pub fn fixed(mut self, fix: Fix) -> Self {
+        self.fix = Some(fix);
+        self
+    }
+
+    // the message and the expected terminals named after grammar
+    pub fn describe(&self, grammar: &Grammar) -> String {
+        let mut names: Vec<String> = self.expected.iter().map(|x| grammar.term_name(x)).collect();
+        names.sort();
+        match names.len() {
+            0 => self.msg.clone(),
+            1 => format!("{:}, expected {:}", self.msg, names[0]),
+            _ => format!("{:}, expected one of {:}", self.msg, names.join(", ")),
+        }
+    }
+
+    pub fn describe_fix(&self, grammar: &Grammar) -> Option<String> {
+        match self.fix.as_ref()? {
+            Fix::Inserted(term) => Some(format!("inserted {:}", grammar.term_name(term))),
+            Fix::Skipped => Some(String::from("skipped it")),
         }
     }
 }
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:}", self.describe(&Grammar::default()))?;
+        if let Some(fix) = self.describe_fix(&Grammar::default()) { write!(f, " ({:})", fix)?; }
+        Ok(())
+    }
+}
@@ -0,0 +1,203 @@
+use super::prelude::*;
+use super::sppf::Forest;
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+/*
+ * Earley parser, for any context free grammar as it is written: left
+ * recursive, ambiguous or with empty rules, without transformation or table.
+ * The set after each token holds the items (rule, dot, origin) alive there:
+ *     the predictor adds the rules of the non terminal after a dot, and
+ *     moves over it at once if it is nullable (Aycock and Horspool)
+ *     the scanner moves over the next token into the next set
+ *     the completer moves the items waiting since origin over a non terminal
+ *     done here
+ * Once the input is recognized, the forest of its derivations is built back
+ * from the sets and the tree is the one precedence chooses in it.
+ */
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Item {
+    rule: usize,
+    dot: usize,
+    origin: usize,
+}
+
+impl Item {
+    fn advance(&self) -> Item {
+        Item { dot: self.dot + 1, ..*self }
+    }
+}
+
+// the non terminals deriving the empty string
+fn nullable(grammar: &Grammar) -> HashSet<String> {
+    let mut ret = HashSet::new();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for p in &grammar.productions {
+            if !ret.contains(&p.name) && p.expr.terms.iter().all(|t| if let Term::NonTerminal { name, .. } = t { ret.contains(name) } else { false }) {
+                ret.insert(p.name.clone());
+                changed = true;
+            }
+        }
+    }
+    ret
+}
+
+struct Chart<'a> {
+    src: &'a [Token],
+    grammar: &'a Grammar,
+    sets: Vec<Vec<Item>>,
+    seen: Vec<HashSet<Item>>,
+}
+
+impl<'a> Chart<'a> {
+    fn add(&mut self, k: usize, item: Item) {
+        if self.seen[k].insert(item) { self.sets[k].push(item); }
+    }
+
+    fn next_term(&self, item: &Item) -> Option<&'a Term> {
+        self.grammar.productions[item.rule].expr.terms.get(item.dot)
+    }
+
+    // the terminals after a dot in set k, and the end of input if all of src up to k is done
+    fn expected(&self, k: usize) -> Vec<Term> {
+        let mut ret: Vec<Term> = self.sets[k].iter().filter_map(|x| match self.next_term(x) { Some(t @ Term::Terminal { .. }) => Some(t.clone()), _ => None }).collect();
+        if self.done(&self.grammar.start_symbol, 0, k) { ret.push(Term::terminal(FINISH_TOKEN)); }
+        ret
+    }
+
+    // whether name derives the tokens [start, end)
+    fn done(&self, name: &str, start: usize, end: usize) -> bool {
+        self.sets[end].iter().any(|x| x.origin == start && self.next_term(x).is_none() && self.grammar.productions[x.rule].name == name)
+    }
+
+    fn recognize(src: &'a [Token], grammar: &'a Grammar) -> Result<Self, ParseError> {
+        let mut chart = Chart { src: src, grammar: grammar, sets: vec![Vec::new(); src.len() + 1], seen: vec![HashSet::new(); src.len() + 1] };
+        let nullable = nullable(grammar);
+        let mut rules: HashMap<&str, Vec<usize>> = HashMap::new();
+        for (i, p) in grammar.productions.iter().enumerate() {
+            rules.entry(p.name.as_str()).or_insert_with(Vec::new).push(i);
+        }
+        for &r in rules.get(grammar.start_symbol.as_str()).unwrap_or(&Vec::new()) {
+            chart.add(0, Item { rule: r, dot: 0, origin: 0 });
+        }
+
+        for k in 0..src.len() + 1 {
+            let mut i = 0;
+            while i < chart.sets[k].len() {
+                let item = chart.sets[k][i];
+                i += 1;
+                match chart.next_term(&item) {
+                    // Complete
+                    None => {
+                        let name = &grammar.productions[item.rule].name;
+                        let waiting: Vec<Item> = chart.sets[item.origin].iter()
+                            .filter(|x| if let Some(Term::NonTerminal { name: n, .. }) = chart.next_term(x) { n == name } else { false })
+                            .map(|x| x.advance()).collect();
+                        for x in waiting { chart.add(k, x); }
+                    },
+                    // Predict
+                    Some(Term::NonTerminal { name, .. }) => {
+                        for &r in rules.get(name.as_str()).unwrap_or(&Vec::new()) {
+                            chart.add(k, Item { rule: r, dot: 0, origin: k });
+                        }
+                        if nullable.contains(name) { chart.add(k, item.advance()); }
+                    },
+                    // Scan
+                    Some(t @ Term::Terminal { .. }) => {
+                        if k < src.len() && t.match_token(&src[k]) { chart.add(k + 1, item.advance()); }
+                    },
+                    Some(t) => panic!("Earley parser needs a formal grammar, found {:}", t),
+                }
+            }
+            if DEBUG!() { println!("#{:} {:} items", k, chart.sets[k].len()); }
+            if k < src.len() && chart.sets[k + 1].is_empty() {
+                return Err(ParseError::unexpected(src, k).expecting(chart.expected(k)));
+            }
+        }
+        if !chart.done(&grammar.start_symbol, 0, src.len()) {
+            return Err(ParseError::unexpected(src, src.len()).expecting(chart.expected(src.len())));
+        }
+        Ok(chart)
+    }
+}
+
+/*
+ * Builds the forest back from a chart: a symbol over [start, end) packs a
+ * derivation for each of its rules done there and each way the right-hand
+ * side splits over the range.
+ */
+struct Builder<'a> {
+    chart: Chart<'a>,
+    forest: Forest,
+    built: HashSet<usize>,
+}
+
+impl<'a> Builder<'a> {
+    fn symbol(&mut self, name: &str, start: usize, end: usize) -> usize {
+        let id = self.forest.symbol(name, start, end);
+        // a symbol met again while built (cyclic grammar) only refers to it
+        if !self.built.insert(id) { return id; }
+        let rules: Vec<usize> = self.chart.sets[end].iter()
+            .filter(|x| x.origin == start && self.chart.next_term(x).is_none() && self.chart.grammar.productions[x.rule].name == name)
+            .map(|x| x.rule).collect();
+        for rule in rules {
+            let len = self.chart.grammar.productions[rule].expr.terms.len();
+            for childs in self.splits(rule, len, start, end) {
+                let rule = self.chart.grammar.productions[rule].clone();
+                self.forest.pack(id, &rule, childs);
+            }
+        }
+        id
+    }
+
+    // the forest nodes of the first dot terms of rule, for each way they span [start, end)
+    fn splits(&mut self, rule: usize, dot: usize, start: usize, end: usize) -> Vec<Vec<usize>> {
+        if dot == 0 { return if start == end { vec![Vec::new()] } else { Vec::new() }; }
+        let before = Item { rule: rule, dot: dot - 1, origin: start };
+        let mut ret = Vec::new();
+        match &self.chart.grammar.productions[rule].expr.terms[dot - 1] {
+            t @ Term::Terminal { .. } => {
+                if end > start && t.match_token(&self.chart.src[end - 1]) && self.chart.seen[end - 1].contains(&before) {
+                    let leaf = self.forest.terminal(end - 1, &self.chart.src[end - 1]);
+                    for mut childs in self.splits(rule, dot - 1, start, end - 1) {
+                        childs.push(leaf);
+                        ret.push(childs);
+                    }
+                }
+            },
+            Term::NonTerminal { name, .. } => {
+                for mid in start..end + 1 {
+                    if self.chart.seen[mid].contains(&before) && self.chart.done(name, mid, end) {
+                        let child = self.symbol(name, mid, end);
+                        for mut childs in self.splits(rule, dot - 1, start, mid) {
+                            childs.push(child);
+                            ret.push(childs);
+                        }
+                    }
+                }
+            },
+            _ => {}
+        }
+        ret
+    }
+}
+
+// the forest of all the derivations of src
+pub fn parse_forest(src: &[Token], grammar: &Grammar) -> Result<Forest, ParseError> {
+    let chart = Chart::recognize(src, grammar)?;
+    let mut builder = Builder { chart: chart, forest: Forest::new(), built: HashSet::new() };
+    let root = builder.symbol(&grammar.start_symbol, 0, src.len());
+    builder.forest.root = Some(root);
+    if DEBUG!() { println!("Result : {:} derivations in {:} forest nodes", builder.forest.count(), builder.forest.nodes.len()); }
+    Ok(builder.forest)
+}
+
+// the tree of src, ambiguities resolved by precedence (see Forest::disambiguate)
+pub fn parse(src: &[Token], grammar: &Grammar) -> Result<Node, ParseError> {
+    let forest = parse_forest(src, grammar)?;
+    forest.disambiguate().ok_or_else(|| ParseError::new("No tree without a cycle in the forest", 0))
+}
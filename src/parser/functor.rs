@@ -2,7 +2,8 @@ use super::*;
 use crate::lexer::Token;
 use crate::lexer;
 use super::utils::*;
-use crate::lexer::re::{SingleToken, RegularExpression};
+use crate::utils::model::{self, ModelKind};
+use crate::lexer::re::{SingleToken, RegularExpression, ReError, CharRange, subtract_ranges, parse_repetition};
 use super::lrparser;
 use super::transform;
 use std::env;
@@ -28,23 +29,28 @@ impl REParser {
         } else { t1 }
     }
 
-    pub fn parse_from_str(s: &str) -> Result<RegularExpression, ()> {
+    pub fn parse_from_str(s: &str) -> Result<RegularExpression, ReError> {
         let lexer_input_model = get_env_var("RE_LEXMODEL", "examples/re/re.lexmodel");
         let input_model = get_env_var("RE_LRTABLE", "examples/re/re.lrtable");
 
         let input_tokens: Vec<Token> = {
-            let input_lexer_rules = serde_yaml::from_str(&read_file(lexer_input_model.as_str()).expect(&format!("Cannot open file: {:} as RE_LEXMODEL", lexer_input_model))).expect("Deserialize error");
-            lexer::tokenize(s, &input_lexer_rules).unwrap()
+            let input_lexer_rules = model::load(&lexer_input_model, ModelKind::Lexer, None).unwrap_or_else(|e| panic!("{:}: {:} (as RE_LEXMODEL)", lexer_input_model, e));
+            lexer::tokenize(s, &input_lexer_rules).map_err(|e| ReError::new(e.msg, e.column - 1))?
         };
 
 
-        let table = serde_yaml::from_str(&read_file(input_model.as_str()).expect(&format!("Cannot open file: {:} as RE_LRTABLE", input_model))).expect("Deserialize error");
-        let n = lrparser::parse_with_table(&input_tokens, &table).unwrap();
+        let table = model::load(&input_model, ModelKind::LRTable, None).unwrap_or_else(|e| panic!("{:}: {:} (as RE_LRTABLE)", input_model, e));
+        let n = lrparser::parse_with_table(&input_tokens, &table).map_err(|mut errs| {
+            let e = errs.remove(0);
+            let pos = if let Some(t) = input_tokens.get(e.index) { t.span_.column - 1 } else { s.chars().count() };
+            ReError::new(e.to_string(), pos)
+        })?;
         let n = transform::retrieve_unwrap(n);
         REParser::parse(&n)
     }
 
-    pub fn parse(node: &Node) -> Result<RegularExpression, ()> {
+    pub fn parse(node: &Node) -> Result<RegularExpression, ReError> {
+        let error = |msg: &str, node: &Node| ReError::new(msg, node.span().map(|span| span.column - 1).unwrap_or(0));
         match &node.value {
             NodeType::Terminal(Token { type_, value_, ..}) => {
                 let c = REParser::parse_char(&value_);
@@ -59,8 +65,8 @@ impl REParser {
                             operands: node.childs
                                 .iter()
                                 .filter_map(|x| if let NodeType::NonTerminal(_) = x.value { Some(x) } else { None })
-                                .map(|x| REParser::parse(x).unwrap())
-                                .collect()
+                                .map(|x| REParser::parse(x))
+                                .collect::<Result<_, _>>()?
                         };
                         return Ok(ret);
                     },
@@ -69,25 +75,33 @@ impl REParser {
                             operands: node.childs
                                 .iter()
                                 .filter_map(|x| if let NodeType::NonTerminal(_) = x.value { Some(x) } else { None })
-                                .map(|x| REParser::parse(x).unwrap())
-                                .collect()
+                                .map(|x| REParser::parse(x))
+                                .collect::<Result<_, _>>()?
                         };
                         return Ok(ret);
                     },
                     "Kleen" => {
-                        let ret = if node.childs.len() > 1 {
-                            RegularExpression::Iteration {
-                                operand: Box::new(REParser::parse(&node.childs[0]).unwrap())
-                            }
-                        } else {
-                            REParser::parse(&node.childs[0])?
-                        };
+                        let mut ret = REParser::parse(&node.childs[0])?;
+                        for postfix in &node.childs[1..] {
+                            let operand = Box::new(ret);
+                            ret = match &postfix.value {
+                                NodeType::NonTerminal(NonTerminal { value_, .. }) if value_ == "kleen" => RegularExpression::Iteration { operand },
+                                NodeType::NonTerminal(NonTerminal { value_, .. }) if value_ == "optional" => RegularExpression::Optional { operand },
+                                NodeType::NonTerminal(NonTerminal { value_, .. }) if value_ == "plus" => RegularExpression::OneOrMore { operand },
+                                NodeType::NonTerminal(NonTerminal { value_, .. }) if value_ == "repeat" => {
+                                    let body = if let NodeType::Terminal(Token { value_, .. }) = &postfix.childs[0].value { &value_[1..value_.len()-1] } else { return Err(error("Expected repetition", postfix)) };
+                                    let (min, max) = parse_repetition(body).map_err(|e| error(e, postfix))?;
+                                    RegularExpression::Repetition { operand, min, max }
+                                },
+                                _ => return Err(error("Invalid postfix operator", postfix))
+                            };
+                        }
                         return Ok(ret);
                     },
                     "Term" => match value_.as_str() {
                         "match" => {
                             let ret = RegularExpression::Match {
-                                operand: Box::new(REParser::parse(&node.childs[1]).unwrap())
+                                operand: Box::new(REParser::parse(&node.childs[1])?)
                             };
                             return Ok(ret);
                         },
@@ -96,7 +110,7 @@ impl REParser {
                         },
                         "alias" => {
                             let ret = RegularExpression::Alias {
-                                id: if let NodeType::Terminal(Token { value_, .. }) = &node.childs[0].value { value_[1..value_.len()-1].to_string() } else { return Err(()) }
+                                id: if let NodeType::Terminal(Token { value_, .. }) = &node.childs[0].value { value_[1..value_.len()-1].to_string() } else { return Err(error("Expected alias", node)) }
                             };
                             return Ok(ret);                            
                         },
@@ -104,49 +118,30 @@ impl REParser {
                             return REParser::parse(&node.childs[0]);
                         },
                         "chargroup" => {
-                            let mut set: Vec<SingleToken> = Vec::new();
+                            let mut set: Vec<CharRange> = Vec::new();
+                            let mut neg_set: Vec<CharRange> = Vec::new();
                             let mut flag_neg = false;
                             for n in &node.childs {
                                 if let NodeType::NonTerminal(NonTerminal { value_, .. }) = &n.value {
-                                    if flag_neg {
-                                        if value_ == "char" {
-                                            if let NodeType::Terminal(Token { value_, .. }) = &n.childs[0].value {
-                                                if let Some(p) = set.iter().position(|x| x == &REParser::parse_char(&value_)) {
-                                                    set.remove(p);
-                                                }
-                                            }
-                                        } else if value_ == "charset" {
-                                            let mut neg_set = Vec::new();
-                                            let mut curr = if let NodeType::Terminal(Token { value_, .. }) = &n.childs[0].value { REParser::parse_char(&value_) } else { return Err(()) };
-                                            let end = if let NodeType::Terminal(Token { value_, .. }) = &n.childs[2].value { REParser::parse_char(&value_) } else { return Err(()) };
-                                            while curr <= end {
-                                                neg_set.push(curr);
-                                                curr = (curr as u8 + 1) as char;
-                                            }
-                                            set = set.into_iter().filter(|x| !neg_set.contains(x)).collect();
-                                        }
-                                    } else {
-                                        if value_ == "char" {
-                                            if let NodeType::Terminal(Token { value_, .. }) = &n.childs[0].value {
-                                                set.push(REParser::parse_char(&value_));
-                                            }
-                                        } else if value_ == "charset" {
-                                            let mut curr = if let NodeType::Terminal(Token { value_, .. }) = &n.childs[0].value { REParser::parse_char(&value_) } else { return Err(()) };
-                                            let end = if let NodeType::Terminal(Token { value_, .. }) = &n.childs[2].value { REParser::parse_char(&value_) } else { return Err(()) };
-                                            while curr <= end {
-                                                set.push(curr);
-                                                curr = (curr as u8 + 1) as char;
-                                            }
-                                        }
-                                    }
+                                    let range = if value_ == "char" {
+                                        if let NodeType::Terminal(Token { value_, .. }) = &n.childs[0].value { CharRange::single(REParser::parse_char(&value_)) } else { return Err(error("Expected char", n)) }
+                                    } else if value_ == "charset" {
+                                        let start = if let NodeType::Terminal(Token { value_, .. }) = &n.childs[0].value { REParser::parse_char(&value_) } else { return Err(error("Expected char", n)) };
+                                        let end = if let NodeType::Terminal(Token { value_, .. }) = &n.childs[2].value { REParser::parse_char(&value_) } else { return Err(error("Expected char", n)) };
+                                        if start > end { return Err(error("Invalid char range", n)) }
+                                        CharRange::new(start, end)
+                                    } else { continue };
+                                    if flag_neg { neg_set.push(range); } else { set.push(range); }
                                 } else if let NodeType::Terminal(Token { type_, .. }) = &n.value {
                                     if type_ == "CharNeg" {
                                         flag_neg = true;
                                     }
                                 }
                             }
-                            let ret = RegularExpression::Union {
-                                operands: set.into_iter().map(|x| RegularExpression::Atomic { id: x}).collect()
+                            // a leading '^' negates against the whole code-point space
+                            if flag_neg && set.is_empty() { set.push(CharRange::full()); }
+                            let ret = RegularExpression::Set {
+                                ranges: subtract_ranges(&set, &neg_set)
                             };
                             return Ok(ret);
                         },
@@ -157,6 +152,6 @@ impl REParser {
             },
             _ => ()
         }
-        Err(())
+        Err(error("Invalid expression", node))
     }
 }
@@ -1,136 +1,152 @@
 // use super::grammar::{Grammar, Production, Expression, Term, Associativity};
 // use super::{NodeType, Node, NonTerminal};
 use super::prelude::*;
-use super::derivations;
+use super::sppf::Forest;
 use crate::lexer::re::StateTransferGraph;
 
 use std::collections::HashMap;
-use std::collections::HashSet;
 
-use super::lrparser::{LRItem, LRItems, LRAction};
-
-
-#[derive(Debug, Clone, PartialEq)]
-pub struct ParseError {
-    pub msg: String,
-    pub index: usize,
+use super::lrparser::{self, LRItems};
+
+
+/*
+ * Graph-structured stack of the GLR parser (Tomita).
+ * The stacks of all the parses alive share their nodes: a node is a state at
+ * a level (the tokens read), there is at most one node per state and level,
+ * and an edge goes down to a node below with the forest node of the symbol
+ * between them. Stacks splitting on a conflict and merging again only add
+ * edges, instead of copying whole stacks.
+ */
+#[derive(Debug, Clone, Default)]
+struct Gss {
+    // (state, level) of the nodes
+    nodes: Vec<(usize, usize)>,
+    // (node below, forest node) of the edges of each node
+    edges: Vec<Vec<(usize, usize)>>,
 }
 
-
-pub fn parse_with_graph(src: &[Token], graph: &StateTransferGraph<LRItems, Term>) -> Result<Node, ParseError> {
-    if DEBUG!() && VERBOSE!() { println!("{:}", graph); }
-    
-    #[derive(Debug, Clone, PartialEq)]
-    enum StackItem {
-        State(usize), NonTerminal(String), Terminal(Token)
+impl Gss {
+    fn add_node(&mut self, state: usize, level: usize) -> usize {
+        self.nodes.push((state, level));
+        self.edges.push(Vec::new());
+        self.nodes.len() - 1
     }
 
-    type MultiStack = Vec<(Vec<StackItem>, Vec<Production>)>;
-
-    let mut last_stack = MultiStack::new();
-    last_stack.push((vec![StackItem::State(graph.start)], Vec::new()));
-
-    let mut next = 0;
-    'outer: loop {
-        let token = if next < src.len() { src[next].clone() } else { Token {
-            type_ : FINISH_TOKEN.to_string(),
-            value_: FINISH_TOKEN.to_string(),
-            line_: 0,
-        } };
-        
-        let mut curr_stack = MultiStack::new();
-
-        // add all shift
-        for (stack, derivations) in &last_stack {
-            let curr_state = if let Some(&StackItem::State(s)) = stack.last() { s } else { panic!("State not on top of stack !") };
-            // println!("Curr_state: {:?}, term: {:}, tr: {:?}", stack, token, graph.get_transition(curr_state, Term::from(&token)));
+    // false if the edge was there
+    fn add_edge(&mut self, from: usize, to: usize, symbol: usize) -> bool {
+        if self.edges[from].contains(&(to, symbol)) { return false; }
+        self.edges[from].push((to, symbol));
+        true
+    }
 
-            if let Some(next_state) = graph.get_transition(curr_state, Term::from(&token)).or(graph.get_transition(curr_state, Term::terminal(token.type_.clone()))) {
-                let mut stack = stack.clone();
-                stack.push(StackItem::Terminal(token.clone()));
-                stack.push(StackItem::State(next_state));
-                curr_stack.push((stack, derivations.clone()));
+    // the nodes len edges down from node, with the forest nodes along the way in input order
+    fn paths(&self, node: usize, len: usize) -> Vec<(usize, Vec<usize>)> {
+        if len == 0 { return vec![(node, Vec::new())]; }
+        let mut ret = Vec::new();
+        for &(below, symbol) in &self.edges[node] {
+            for (end, mut symbols) in self.paths(below, len - 1) {
+                symbols.push(symbol);
+                ret.push((end, symbols));
             }
         }
+        ret
+    }
+}
 
+/*
+ * Parse src into the forest of all its derivations.
+ * For each token, the reductions of the nodes of the level are done until
+ * none adds to the stack (a new edge to an old node can open paths for the
+ * reductions already done, so all are tried again and the forest drops what
+ * it has), then every node with a transition on the token shifts it.
+ */
+pub fn parse_forest(src: &[Token], graph: &StateTransferGraph<LRItems, Term>) -> Result<Forest, ParseError> {
+    if DEBUG!() && VERBOSE!() { println!("{:}", graph); }
 
-        // handle all reduce
+    let mut gss = Gss::default();
+    let mut forest = Forest::new();
+    // the nodes of the current level by state
+    let mut level: HashMap<usize, usize> = HashMap::new();
+    level.insert(graph.start, gss.add_node(graph.start, 0));
 
-        if next < src.len() { next += 1; }
+    for next in 0..src.len() + 1 {
         let token = if next < src.len() { src[next].clone() } else { Token {
             type_ : FINISH_TOKEN.to_string(),
             value_: FINISH_TOKEN.to_string(),
-            line_: 0,
+            ..Default::default()
         } };
-
-        if DEBUG!() { 
-            if VERBOSE!() {
-                println!("#{:} States: {:?}, token: {:}", next - 1, curr_stack, token);
-            } else {
-                println!("#{:} State sum: {:?}, token: {:}", next - 1, last_stack.len(), token);                
-            }
-        }
-
-        let mut state = 0;
-        while state < curr_stack.len() {
-            let (stack, derivations) = curr_stack.get(state).unwrap().clone();
-            // if DEBUG!() && VERBOSE!() { println!("Search for reduced: States: {:?}, token: {:}", stack, token); }
-
-            let curr_state = if let Some(&StackItem::State(s)) = stack.last() { s } else { panic!("State not on top of stack !") };
-
-            for (item, ahead) in &*graph.vertices[curr_state].data {
-                if item.pos == item.rule.expr.terms.len() {
-                    // Check lookahead
-                    // println!("Item: {:}, ahead: {:?}, token: {:}", item, ahead, token);
-                    if ahead.len() > 0 && !ahead.contains(&Term::from(&token)) && !ahead.contains(&Term::terminal(token.type_.clone()))  { continue; }
-        
-
-                    let mut stack = stack.clone();
-                    let mut derivations = derivations.clone();
-
-                    for _ in 0..item.pos {
-                        stack.pop();
-                        stack.pop();
-                    }
-
-                    let curr_state = if let Some(&StackItem::State(s)) = stack.last() { s } else { panic!("Reduce: State not on top of stack !") };
-                    stack.push(StackItem::NonTerminal(item.rule.name.clone()));
-
-
-                    // if DEBUG!() && VERBOSE!() { println!("Reduced: Stack: {:?}", stack); }
-                    if curr_state == graph.start && item.rule.name == FINISH_TOKEN.to_string() {
-                        // Accept
-                        // Assume the grammar is unambigious, so exit
-
-                        last_stack = curr_stack;
-                        break 'outer;
-                    }
-                    if let Some(next_state) = graph.get_transition(curr_state, Term::nonterminal(item.rule.name.as_str())) {
-                        // goto
-                        stack.push(StackItem::State(next_state));
-                        // store derivation rule for this reduce
-                        derivations.push(item.rule.clone());
-
-                        if !curr_stack.iter().any(|(s, d)| s == &stack) { curr_stack.push((stack, derivations)); }
-                    } else {
-                        // Drop this
-                        if DEBUG!() { println!("Dropped: {:}, {:}", curr_state, item.rule); }
+        let matches = |ahead: &lrparser::LRAhead| ahead.len() == 0 || ahead.contains(&Term::from(&token)) || ahead.contains(&Term::terminal(token.type_.clone()));
+
+        // Reduce
+        let mut changed = true;
+        while changed {
+            changed = false;
+            let mut nodes: Vec<usize> = level.values().cloned().collect();
+            nodes.sort();
+            for node in nodes {
+                let state = gss.nodes[node].0;
+                let rules: Vec<Production> = graph.vertices[state].data.iter()
+                    .filter(|(item, ahead)| item.pos == item.rule.expr.terms.len() && matches(ahead))
+                    .map(|(item, _)| item.rule.clone()).collect();
+                for rule in rules {
+                    for (below, childs) in gss.paths(node, rule.expr.terms.len()) {
+                        let (below_state, start) = gss.nodes[below];
+                        if below_state == graph.start && rule.name == FINISH_TOKEN {
+                            // Accept
+                            if next == src.len() { forest.root = childs.first().cloned(); }
+                            continue;
+                        }
+                        let symbol = forest.symbol(&rule.name, start, next);
+                        forest.pack(symbol, &rule, childs);
+                        if let Some(next_state) = graph.get_transition(below_state, Term::nonterminal(rule.name.as_str())) {
+                            let target = match level.get(&next_state) {
+                                Some(&target) => target,
+                                None => {
+                                    changed = true;
+                                    let target = gss.add_node(next_state, next);
+                                    level.insert(next_state, target);
+                                    target
+                                }
+                            };
+                            changed |= gss.add_edge(target, below, symbol);
+                        }
                     }
                 }
             }
-            state += 1;
         }
-        last_stack = curr_stack;
-        // println!("next: {:}", next);
-        if last_stack.is_empty() {
-            return Err(ParseError {
-                msg: format!("All cases failed."),
-                index: next
-            });
+        if DEBUG!() { println!("#{:} States: {:?}, token: {:}", next, level.keys().collect::<Vec<_>>(), token); }
+
+        if next == src.len() { break; }
+
+        // Shift
+        let mut shifted: HashMap<usize, usize> = HashMap::new();
+        let mut nodes: Vec<usize> = level.values().cloned().collect();
+        nodes.sort();
+        for node in nodes {
+            let state = gss.nodes[node].0;
+            if let Some(next_state) = graph.get_transition(state, Term::from(&token)).or(graph.get_transition(state, Term::terminal(token.type_.clone()))) {
+                let leaf = forest.terminal(next, &token);
+                let target = *shifted.entry(next_state).or_insert_with(|| gss.add_node(next_state, next + 1));
+                gss.add_edge(target, node, leaf);
+            }
+        }
+        if shifted.is_empty() {
+            let expected: Vec<Term> = level.keys().flat_map(|&s| lrparser::expected_terminals(graph, s)).collect();
+            return Err(ParseError::unexpected(src, next).expecting(expected));
         }
+        level = shifted;
+    }
+
+    if forest.root.is_none() {
+        let expected: Vec<Term> = level.keys().flat_map(|&s| lrparser::expected_terminals(graph, s)).collect();
+        return Err(ParseError::unexpected(src, src.len()).expecting(expected));
     }
-    let (_, mut derivations) = last_stack.pop().unwrap();
-    derivations.reverse();
-    if DEBUG!() { println!("Result : \n{:}", derivations.iter().map(|x| x.dump()).collect::<Vec<String>>().join("\n")); }
-    Ok(derivations::build_rightmost(&derivations, src))
-}
\ No newline at end of file
+    if DEBUG!() { println!("Result : {:} derivations in {:} forest nodes", forest.count(), forest.nodes.len()); }
+    Ok(forest)
+}
+
+// the tree of src, ambiguities resolved by precedence (see Forest::disambiguate)
+pub fn parse_with_graph(src: &[Token], graph: &StateTransferGraph<LRItems, Term>) -> Result<Node, ParseError> {
+    let forest = parse_forest(src, graph)?;
+    forest.disambiguate().ok_or_else(|| ParseError::new("No tree without a cycle in the forest", 0))
+}
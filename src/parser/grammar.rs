@@ -1,5 +1,8 @@
 use crate::lexer::Token;
+use crate::utils::{ERROR_TOKEN, FINISH_TOKEN};
 use super::transform::Substitution;
+use super::ParseError;
+use std::collections::BTreeMap;
 use std::cmp::Eq;
 use std::fmt;
 use std::convert::From;
@@ -16,6 +19,9 @@ pub struct Grammar {
     pub productions: Vec<Production>,
     pub transforms: Vec<Substitution>,
     pub start_symbol: String,
+    // names of terminal types in messages, declared as "TYPE" = ?name? .
+    #[serde(default)]
+    pub names: BTreeMap<String, String>,
 }
 
 #[derive(Debug, Clone, PartialEq, Default, Eq, Hash, Serialize, Deserialize)]
@@ -54,33 +60,26 @@ pub enum Term {
         expr: Box<Expression>,
         unwrap: bool,
     },
+    // & and ! of parsing expression grammars, they look ahead without consuming anything
+    And {
+        expr: Box<Expression>,
+    },
+    Not {
+        expr: Box<Expression>,
+    },
 }
 
 macro_rules! parse_step {
     ($src: expr, $next: expr, $type: expr) => {
-        if let Some(&Token{ ref type_, ref value_, .. }) = $src.get(*$next) {
-            if type_ == &$type.to_string() {
+        if let Some(token) = $src.get(*$next) {
+            if token.type_ == $type.to_string() {
                 *$next += 1;
-                Ok(value_.clone())
-            } else { Err(ParseError {
-                msg: format!("Expected {:?} but found {:?}", $type, $src.get(*$next)),
-                index: *$next
-            })}
-        } else { Err(ParseError {
-                msg: format!("Cannot read: Expected {:?} but found {:?}", $type, $src.get(*$next)),
-                index: *$next
-            })
-        }
+                Ok(token.text().to_string())
+            } else { Err(ParseError::new(format!("Expected {:?} but found {:?}", $type, $src.get(*$next)), *$next)) }
+        } else { Err(ParseError::new(format!("Cannot read: Expected {:?} but found {:?}", $type, $src.get(*$next)), *$next)) }
     };
 }
 
-
-#[derive(Debug, Clone, PartialEq)]
-pub struct ParseError {
-    msg: String,
-    index: usize,
-}
-
 impl Default for Associativity {
     fn default() -> Self {
         Associativity::Left
@@ -121,6 +120,11 @@ impl Grammar {
             .collect()
     }
 
+    // whether a rule looks ahead, only the recursive descent parsers can
+    pub fn has_predicates(&self) -> bool {
+        self.productions.iter().any(|p| p.expr.terms.iter().any(|t| t.is_predicate()))
+    }
+
     pub fn get_productions(&self, nt: &String) -> Vec<Production> {
         self.productions.iter().filter(|x| &x.name == nt).map(|x| x.clone()).collect()
     }
@@ -129,10 +133,27 @@ impl Grammar {
         self.transforms.push(tf);
     }
 
+    // a terminal as shown to users, by its declared name if any
+    pub fn term_name(&self, term: &Term) -> String {
+        match term {
+            Term::Terminal { type_, .. } if type_ == FINISH_TOKEN => String::from("end of input"),
+            Term::Terminal { type_, value: None } => self.names.get(type_).cloned().unwrap_or_else(|| term.to_string()),
+            _ => term.to_string(),
+        }
+    }
+
     pub fn parse(src: &[Token]) -> Result<Self, ParseError> {
         let mut ret = Grammar::default();
         let ref mut next = 0;
-        while let Ok(name) = parse_step!(src, next, "ProductionName") {
+        loop {
+            if let Ok(type_) = parse_step!(src, next, "Token") {
+                parse_step!(src, next, "Assign")?;
+                let name = parse_step!(src, next, "SpecialSequence")?;
+                parse_step!(src, next, "Terminator")?;
+                ret.names.insert(type_, name);
+                continue;
+            }
+            let name = if let Ok(name) = parse_step!(src, next, "ProductionName") { name } else { break };
             let mut i = 0;
             parse_step!(src, next, "Assign")?;
             {
@@ -285,6 +306,8 @@ impl Term {
                 }
                 format!("{:} {:} {:}", lstr, expr.dump(), rstr)
             },
+            Term::And { ref expr } => format!("& {:}", expr.dump()),
+            Term::Not { ref expr } => format!("! {:}", expr.dump()),
         }
     }
 
@@ -292,6 +315,8 @@ impl Term {
         let unwrap = parse_step!(src, next, "LeftUnwrap").is_ok();
         if let Ok(pn) = parse_step!(src, next, "ProductionName") {
             if unwrap { parse_step!(src, next, "RightUnwrap")?; }
+            // the error token of yacc, for the LR parsers to recover at
+            if pn == ERROR_TOKEN && !unwrap { return Ok(Term::terminal(ERROR_TOKEN)); }
             Ok(Term::NonTerminal{ name: pn, unwrap: unwrap })
         } else if let Ok(token) = parse_step!(src, next, "Token") {
             let value = if let Ok(_) = parse_step!(src, next, "TokenValue") {
@@ -299,10 +324,7 @@ impl Term {
                 Some(val)
             } else { None };
             if unwrap {
-                Err(ParseError {
-                    msg: String::from("Token cannot be unwrap!"),
-                    index: *next
-                })
+                Err(ParseError::new("Token cannot be unwrap!", *next))
             } else {
                 Ok(Term::Terminal {
                     type_: token,
@@ -333,11 +355,18 @@ impl Term {
                 expr: Box::new(expr),
                 unwrap: !unwrap,
             })
-        } else {
-            Err(ParseError {
-                msg: String::from("Unknown type of Term!"),
-                index: *next
+        } else if let Ok(_) = parse_step!(src, next, "AndPredicate") {
+            if unwrap { return Err(ParseError::new("Predicate cannot be unwrap!", *next)); }
+            Ok(Term::And {
+                expr: Box::new(Expression { terms: vec![Term::parse_inner(src, next)?] }),
+            })
+        } else if let Ok(_) = parse_step!(src, next, "NotPredicate") {
+            if unwrap { return Err(ParseError::new("Predicate cannot be unwrap!", *next)); }
+            Ok(Term::Not {
+                expr: Box::new(Expression { terms: vec![Term::parse_inner(src, next)?] }),
             })
+        } else {
+            Err(ParseError::new("Unknown type of Term!", *next))
         }
     }
 
@@ -358,6 +387,16 @@ impl Term {
             Term::Repetition { ref unwrap, .. } => {
                 *unwrap
             },
+            Term::And { .. } | Term::Not { .. } => {
+                false
+            },
+        }
+    }
+
+    pub fn is_predicate(&self) -> bool {
+        match self {
+            Term::And { .. } | Term::Not { .. } => true,
+            _ => false,
         }
     }
 
@@ -392,6 +431,7 @@ impl Term {
 
 /* 
  * We don't care the metadata (unwrap etc.)
+ * Predicates stay in formal grammars, over a single non terminal.
  */
 
 impl PartialEq for Term {
@@ -406,7 +446,13 @@ impl PartialEq for Term {
                 if let Term::Terminal { type_: rhs, value: rhsvalue } = other {
                     type_ == rhs && value == rhsvalue
                 } else { false }
-            },    
+            },
+            Term::And { expr } => {
+                if let Term::And { expr: rhs } = other { expr == rhs } else { false }
+            },
+            Term::Not { expr } => {
+                if let Term::Not { expr: rhs } = other { expr == rhs } else { false }
+            },
             _ => panic!("Cannot compare two informal Term !")        
         }
     }
@@ -425,7 +471,15 @@ impl hash::Hash for Term {
             Term::Terminal { type_, .. } => {
                 type_.hash(state);
                 "T".hash(state);
-            },    
+            },
+            Term::And { expr } => {
+                expr.hash(state);
+                "&".hash(state);
+            },
+            Term::Not { expr } => {
+                expr.hash(state);
+                "!".hash(state);
+            },
             _ => panic!("Cannot hash informal Term !") 
         }
     }
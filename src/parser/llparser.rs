@@ -160,13 +160,6 @@ pub fn generate_table(grammar: &Grammar) -> LLTable {
 }
 
 
-#[derive(Debug, Clone, PartialEq)]
-pub struct ParseError {
-    pub msg: String,
-    pub index: usize,
-}
-
-
 pub fn parse(src: &[Token], grammar: &Grammar) -> Result<Node, Vec<ParseError>> {
     let table = generate_table(&grammar);
     let mut tree = Node {
@@ -192,6 +185,7 @@ pub fn parse(src: &[Token], grammar: &Grammar) -> Result<Node, Vec<ParseError>>
                             type_: name.clone(),
                             value_: p.label.clone(),
                             rule_: p.clone(),
+                            ..Default::default()
                         }),
                         childs: Vec::new(),
                         index: next,
@@ -215,16 +209,13 @@ pub fn parse(src: &[Token], grammar: &Grammar) -> Result<Node, Vec<ParseError>>
 
                     stack.extend(p.expr.terms.clone().into_iter().rev());
                 } else {
-                    errs.push(ParseError{
-                        msg: format!("No rule found for {:} : {:}, Expected {:?}", name, if next == src.len() { FINISH_TOKEN.to_string() } else { format!("{:?}", src[next]) }, table.get(&name).unwrap().keys()),
-                        index: next
-                    });
+                    errs.push(ParseError::unexpected(src, next).expecting(table.get(&name).unwrap().keys().cloned()));
                 }
             },
             Term::Terminal { type_: ref name, .. } => {
                 if next == src.len() && FINISH_TOKEN == name {
 
-                } else if term.match_token(&src[next]) {
+                } else if next < src.len() && term.match_token(&src[next]) {
                     let node = Node {
                         value: NodeType::Terminal(src[next].clone()),
                         childs: Vec::new(),
@@ -245,24 +236,20 @@ pub fn parse(src: &[Token], grammar: &Grammar) -> Result<Node, Vec<ParseError>>
 
                     next += 1;
                 } else {
-                    errs.push(ParseError{
-                        msg: format!("Expected {:} but found {:}", name, if next == src.len() { FINISH_TOKEN.to_string() } else { format!("{:?}", src[next]) }),
-                        index: next
-                    });
+                    errs.push(ParseError::unexpected(src, next).expecting(Some(term.clone())));
                 }
             },
             _ => panic!()
         }
     }
     if next == src.len() && errs.is_empty() {
-        Ok(tree.finish().remove(0))
+        let mut tree = tree.finish().remove(0);
+        tree.compute_spans();
+        Ok(tree)
     } else if next == src.len() || !errs.is_empty() {
         Err(errs)
     } else {
-        errs.push(ParseError {
-            msg: "Program Too Long".to_string(),
-            index: next
-        });
+        errs.push(ParseError::unexpected(src, next).expecting(Some(Term::terminal(FINISH_TOKEN))));
         Err(errs)
     }
 }
\ No newline at end of file
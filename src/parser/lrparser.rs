@@ -1,5 +1,7 @@
 use super::prelude::*;
 use crate::lexer::re::StateTransferGraph;
+use crate::lexer::dot::{DotVertex, DotEdge};
+use crate::utils::span::Span;
 
 use std::fmt;
 use std::hash;
@@ -8,6 +10,9 @@ use std::ops::Deref;
 use std::ops::DerefMut;
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 
 
 
@@ -44,6 +49,15 @@ impl hash::Hash for LRItem {
     }
 }
 
+impl LRItem {
+    // the rule with a dot at pos
+    pub fn dump(&self) -> String {
+        let mut terms: Vec<String> = self.rule.expr.terms.iter().map(|x| x.to_string()).collect();
+        terms.insert(self.pos, String::from("."));
+        format!("{:} -> {:}", self.rule.name, terms.join(" "))
+    }
+}
+
 impl fmt::Display for LRItem {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         if self.pos == self.rule.expr.terms.len() {
@@ -93,6 +107,29 @@ impl fmt::Display for LRItems {
     }
 }
 
+impl DotVertex for LRItems {
+    fn dot_label(&self, index: usize) -> String {
+        let mut items: Vec<String> = self.items.iter().map(|(item, ahead)| {
+            let mut ahead: Vec<String> = ahead.iter().map(|x| x.to_string()).collect();
+            ahead.sort();
+            if ahead.is_empty() { item.dump() }
+            else { format!("{:} , {:}", item.dump(), ahead.join(" ")) }
+        }).collect();
+        items.sort();
+        format!("I{:}\n{:}\n", index, items.join("\n"))
+    }
+
+    fn dot_shape() -> &'static str {
+        "box"
+    }
+}
+
+impl DotEdge for Term {
+    fn dot_label(costs: &[&Self]) -> String {
+        costs.iter().map(|x| x.to_string()).collect::<Vec<String>>().join(", ")
+    }
+}
+
 impl PartialEq for LRItems {
     fn eq(&self, rhs: &Self) -> bool {
         self.keys().collect::<HashSet<_>>() == rhs.keys().collect::<HashSet<_>>()
@@ -233,27 +270,24 @@ pub fn construct_lr_0(grammar: &Grammar) -> StateTransferGraph<LRItems, Term> {
 }
 
 
-pub fn construct_lalr_1(grammar: &Grammar) -> StateTransferGraph<LRItems, Term> {
-    // Initialize first , follow
-    let mut first: HashMap<Term, TerminalSet> = HashMap::new();
-    let mut follow: HashMap<String, TerminalSet> = HashMap::new();
-
-    let get_first = |terms: &[Term], first: &HashMap<Term, TerminalSet>| {
-        let mut tset = TerminalSet::default();
-        let mut flag = true;
-        for term in terms {
-            flag = false;
-            let t = first.get(term).expect(&format!("Term not found : {:} from {:?}", term, terms));
-            tset.append(t);
-            tset.remove_epsilon();
-            if !t.contains_epsilon() { break; }
-            flag = true;
-        }
-        if flag { tset.append_epsilon(); }
-        tset
-    };
+// first set of a sequence of terms, with epsilon if they can all vanish
+fn first_of(terms: &[Term], first: &HashMap<Term, TerminalSet>) -> TerminalSet {
+    let mut tset = TerminalSet::default();
+    let mut flag = true;
+    for term in terms {
+        flag = false;
+        let t = first.get(term).expect(&format!("Term not found : {:} from {:?}", term, terms));
+        tset.append(t);
+        tset.remove_epsilon();
+        if !t.contains_epsilon() { break; }
+        flag = true;
+    }
+    if flag { tset.append_epsilon(); }
+    tset
+}
 
-    // Compute first
+fn first_sets(grammar: &Grammar) -> HashMap<Term, TerminalSet> {
+    let mut first: HashMap<Term, TerminalSet> = HashMap::new();
     // println!("ts:{:?}", grammar.terminals());
     for terminal in grammar.terminals() {
         let mut tset = TerminalSet::default();
@@ -274,11 +308,69 @@ pub fn construct_lalr_1(grammar: &Grammar) -> StateTransferGraph<LRItems, Term>
     loop {
         let mut flag_move = false;
         for p in &grammar.productions {
-            let tset = get_first(&p.expr.terms[..], &first);
+            let tset = first_of(&p.expr.terms[..], &first);
             flag_move |= first.get_mut(&Term::nonterminal(p.name.as_str())).expect("2").append(&tset);
         }
         if !flag_move { break; }
     }
+    first
+}
+
+/*
+ * How the LR(1) construction merges a new state into an existing one:
+ *     Canonical   only if they are the same, lookaheads included
+ *     Minimal     if they have the same core and are weakly compatible
+ *                 (Pager), which never adds a reduce-reduce conflict
+ *     LALR        if they have the same core
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LRMerge {
+    Canonical, Minimal, LALR
+}
+
+/*
+ * Pager's weak compatibility of two states of the same core: merging them
+ * joins the lookaheads of two items only if they already overlap in one of
+ * the states, so a conflict of the merged state is one of a state alone.
+ */
+fn weakly_compatible(a: &LRItems, b: &LRItems) -> bool {
+    let items: Vec<&LRItem> = a.keys().collect();
+    for i in 0..items.len() {
+        for j in i+1..items.len() {
+            let (ai, aj, bi, bj) = (&a[items[i]], &a[items[j]], &b[items[i]], &b[items[j]]);
+            if (!ai.is_disjoint(bj) || !bi.is_disjoint(aj)) && ai.is_disjoint(aj) && bi.is_disjoint(bj) {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+// hash of the core of a state, the same whatever the order of the items
+fn core_hash(s: &LRItems) -> u64 {
+    s.keys().fold(0, |h: u64, item| {
+        let mut hasher = DefaultHasher::new();
+        item.hash(&mut hasher);
+        h.wrapping_add(hasher.finish())
+    })
+}
+
+pub fn construct_lalr_1(grammar: &Grammar) -> StateTransferGraph<LRItems, Term> {
+    construct_lr_1_merging(grammar, LRMerge::LALR)
+}
+
+pub fn construct_lr_1(grammar: &Grammar) -> StateTransferGraph<LRItems, Term> {
+    construct_lr_1_merging(grammar, LRMerge::Canonical)
+}
+
+pub fn construct_minimal_lr_1(grammar: &Grammar) -> StateTransferGraph<LRItems, Term> {
+    construct_lr_1_merging(grammar, LRMerge::Minimal)
+}
+
+pub fn construct_lr_1_merging(grammar: &Grammar, merge: LRMerge) -> StateTransferGraph<LRItems, Term> {
+    // Initialize first , follow
+    let first = first_sets(grammar);
+    let mut follow: HashMap<String, TerminalSet> = HashMap::new();
 
     // Compute follow
     for nonterminal in grammar.non_terminals() {
@@ -291,7 +383,7 @@ pub fn construct_lalr_1(grammar: &Grammar) -> StateTransferGraph<LRItems, Term>
         for p in &grammar.productions {
             for i in 0..p.expr.terms.len() {
                 if let Term::NonTerminal { name: t, .. } = &p.expr.terms[i] {
-                    let mut tset = get_first(&p.expr.terms[i+1..p.expr.terms.len()], &first);
+                    let mut tset = first_of(&p.expr.terms[i+1..p.expr.terms.len()], &first);
                     let fep = tset.contains_epsilon();
                     tset.remove_epsilon();
                     flag_move |= follow.get_mut(t).expect(&format!("Follow not found : {:}", t)).append(&tset);
@@ -319,9 +411,9 @@ pub fn construct_lalr_1(grammar: &Grammar) -> StateTransferGraph<LRItems, Term>
                     if let Term::NonTerminal { name, .. } = &rule.expr.terms[*pos] {
                         for ahead in ahead {
                             let f = if *pos == rule.expr.terms.len() - 1 {
-                                get_first(&[ahead.clone()], &first)
+                                first_of(&[ahead.clone()], &first)
                             } else {
-                                get_first(&[&rule.expr.terms[*pos+1..], &[ahead.clone()]].concat(), &first)
+                                first_of(&[&rule.expr.terms[*pos+1..], &[ahead.clone()]].concat(), &first)
                             };
                             for p in &grammar.productions {
                                 if &p.name != name { continue; }
@@ -365,8 +457,13 @@ pub fn construct_lalr_1(grammar: &Grammar) -> StateTransferGraph<LRItems, Term>
         pos: 0,
     } => set![Term::terminal(FINISH_TOKEN)]] };
 
-    let start = graph.add_state_with_data(closure(&start));
+    let start = closure(&start);
+    // states by the hash of their core
+    let mut cores: HashMap<u64, Vec<usize>> = HashMap::new();
+    cores.insert(core_hash(&start), vec![0]);
+    let start = graph.add_state_with_data(start);
     graph.mark_as_start(start);
+    let symbols = grammar.symbols();
     // let mut state = 0;
     let mut stack = Vec::new();
     stack.push(0);
@@ -380,14 +477,25 @@ pub fn construct_lalr_1(grammar: &Grammar) -> StateTransferGraph<LRItems, Term>
                 }
             }
         }
-        for t in grammar.symbols() {
+        for t in &symbols {
             let g = goto(&graph.vertices[state].data, t.clone());
             if !g.is_empty() {
-                let p = if let Some(p) = graph.vertices.iter().position(|x| x.data == g) { 
+                // lookaheads added to a state go on to the state it already goes to
+                let found = graph.get_transition(state, t.clone()).or_else(|| cores.get(&core_hash(&g))?.iter().cloned().find(|&p| {
+                    let x = &graph.vertices[p].data;
+                    match merge {
+                        LRMerge::Canonical => x.items == g.items,
+                        LRMerge::Minimal => *x == g && weakly_compatible(x, &g),
+                        LRMerge::LALR => *x == g,
+                    }
+                }));
+                let p = if let Some(p) = found {
                     if graph.vertices[p].data.extend_all(g) { if !stack.contains(&p) { stack.push(p); } }
                     p 
                 } else {
+                    let hash = core_hash(&g);
                     let p = graph.add_state_with_data(g);
+                    cores.entry(hash).or_insert_with(Vec::new).push(p);
                     if !stack.contains(&p) { stack.push(p); }
                     p
                 };
@@ -402,270 +510,412 @@ pub fn construct_lalr_1(grammar: &Grammar) -> StateTransferGraph<LRItems, Term>
     graph
 }
 
-
-#[derive(Debug, Clone, PartialEq)]
-pub struct ParseError {
-    pub msg: String,
-    pub index: usize,
-}
-
-pub fn parse(src: &[Token], grammar: &Grammar) -> Result<Node, ParseError> {
+pub fn parse(src: &[Token], grammar: &Grammar) -> Result<Node, Vec<ParseError>> {
     let graph = construct_lalr_1(grammar);
     let table = construct_table(&graph).unwrap();
     parse_with_table(src, &table)
 }
 
+/*
+ * What the driver needs of an LR automaton: a table, or a graph resolving its
+ * conflicts by precedence while parsing.
+ */
+trait Automaton {
+    fn start(&self) -> usize;
+    fn action(&self, state: usize, token: &Token) -> Option<LRAction>;
+    fn goto(&self, state: usize, name: &str) -> Option<usize>;
+    // the terminals with an action in state
+    fn terminals(&self, state: usize) -> Vec<Term>;
+}
 
-pub fn parse_with_graph(src: &[Token], graph: &StateTransferGraph<LRItems, Term>) -> Result<Node, ParseError> {
-    if DEBUG!() && VERBOSE!() { println!("{:}", graph); }
-    
-    #[derive(Debug)]
-    enum StackItem {
-        State(usize), NonTerminal(String), Terminal(Token)
+impl Automaton for LRTable {
+    fn start(&self) -> usize {
+        0
     }
-    let mut stack = Vec::<StackItem>::new();
-    stack.push(StackItem::State(graph.start));
 
-    let mut stack_out = Vec::new();
+    fn action(&self, state: usize, token: &Token) -> Option<LRAction> {
+        self[state].get(&Term::from(token)).or(self[state].get(&Term::terminal(token.type_.as_str()))).cloned()
+    }
 
-    let mut next = 0;
-    'outer: loop {
-        let token = if next < src.len() { src[next].clone() } else { Token {
-            type_ : FINISH_TOKEN.to_string(),
-            value_: FINISH_TOKEN.to_string(),
-            line_: 0,
-        } };
-        let curr_state = if let Some(&StackItem::State(s)) = stack.last() { s } else { panic!("State not on top of stack !") };
-
-        if DEBUG!() { println!("#{:} Step: [{:}, {:}]", next, curr_state, token); }
-        // Shift
-        if let Some(next_state) = graph.get_transition(curr_state, Term::from(token.clone())).or(graph.get_transition(curr_state, Term::terminal(token.type_.clone()))) {
-            let mut conflict = false;
-            // Check shift-reduce conflict
-            let mut shift_precedence = None;
-            for (item, _) in &*graph.vertices[curr_state].data {
-                if item.pos < item.rule.expr.terms.len() && item.rule.expr.terms[item.pos].match_token(&token) {
-                    if shift_precedence == None || shift_precedence.unwrap() > item.rule.precedence { shift_precedence = Some(item.rule.precedence); }
-                }
-            }
-            let shift_precedence = shift_precedence.unwrap();
+    fn goto(&self, state: usize, name: &str) -> Option<usize> {
+        if let Some(LRAction::Shift(next)) = self[state].get(&Term::nonterminal(name)) { Some(*next) } else { None }
+    }
 
-            for (item, ahead) in &*graph.vertices[curr_state].data {
-                if item.pos == item.rule.expr.terms.len() {
-                    // Check lookahead
-                    if ahead.len() > 0 && !ahead.contains(&Term::from(&token)) && !ahead.contains(&Term::terminal(token.type_.clone())) { continue; }
+    fn terminals(&self, state: usize) -> Vec<Term> {
+        self[state].keys().filter(|x| if let Term::Terminal { .. } = x { true } else { false }).cloned().collect()
+    }
+}
 
-                    if DEBUG!() && VERBOSE!() { println!("SHIFT_REDUCE CONFLICT"); }
-                    if item.rule.precedence < shift_precedence || (item.rule.precedence == shift_precedence && item.rule.associativity == Associativity::Left) {
-                        conflict = true;
-                        break
-                    }
-                }
-            }
+impl Automaton for StateTransferGraph<LRItems, Term> {
+    fn start(&self) -> usize {
+        self.start
+    }
 
-            if !conflict {
-                stack.push(StackItem::Terminal(token.clone()));
-                stack.push(StackItem::State(next_state));
-                stack_out.push(Node {
-                    value: NodeType::Terminal(token.clone()),
-                    childs: Vec::new(),
-                    index: next
-                });
-                next += 1;
-                if DEBUG!() { 
-                    if VERBOSE!() { println!("Shifted: {:?}", stack); }
-                    else { println!("Shifted to {:}", next_state); }
-                }
-                continue 'outer;
+    fn action(&self, state: usize, token: &Token) -> Option<LRAction> {
+        let data = &*self.vertices[state].data;
+        let complete = |item: &LRItem, ahead: &LRAhead| item.pos == item.rule.expr.terms.len()
+            && (ahead.len() == 0 || ahead.contains(&Term::from(token)) || ahead.contains(&Term::terminal(token.type_.clone())));
+        // Shift, unless a reduce of lower precedence (or left associative) is possible
+        if let Some(next_state) = self.get_transition(state, Term::from(token.clone())).or(self.get_transition(state, Term::terminal(token.type_.clone()))) {
+            let shift_precedence = data.iter()
+                .filter(|(item, _)| match item.rule.expr.terms.get(item.pos) { Some(t @ Term::Terminal { .. }) => t.match_token(token), _ => false })
+                .map(|(item, _)| item.rule.precedence)
+                .min().unwrap_or(0);
+            let conflict = data.iter().any(|(item, ahead)| complete(item, ahead)
+                && (item.rule.precedence < shift_precedence || (item.rule.precedence == shift_precedence && item.rule.associativity == Associativity::Left)));
+            if DEBUG!() && VERBOSE!() && conflict { println!("SHIFT_REDUCE CONFLICT"); }
+            if !conflict { return Some(LRAction::Shift(next_state)); }
+        }
+        // Reduce by the rule of lowest precedence
+        let mut reduce_item: Option<&LRItem> = None;
+        for (item, ahead) in data {
+            if complete(item, ahead) && (reduce_item == None || reduce_item.unwrap().rule.precedence > item.rule.precedence) {
+                if DEBUG!() && VERBOSE!() && reduce_item != None { println!("REDUCE_REDUCE CONFLICT"); }
+                reduce_item = Some(item);
             }
         }
+        reduce_item.map(|item| LRAction::Reduce(item.rule.clone()))
+    }
 
-        // Reduce
-        let mut reduce_item: Option<LRItem> = None;
+    fn goto(&self, state: usize, name: &str) -> Option<usize> {
+        self.get_transition(state, Term::nonterminal(name))
+    }
 
-        for (item, ahead) in &*graph.vertices[curr_state].data {
+    fn terminals(&self, state: usize) -> Vec<Term> {
+        let mut ret: Vec<Term> = self.vertices[state].out_edges.iter().map(|&e| self.edges[e].cost.clone()).filter(|x| if let Term::Terminal { .. } = x { true } else { false }).collect();
+        for (item, ahead) in &*self.vertices[state].data {
             if item.pos == item.rule.expr.terms.len() {
-                // Check lookahead
-                if ahead.len() > 0 && !ahead.contains(&Term::from(&token)) && !ahead.contains(&Term::terminal(token.type_.clone())) { continue; }
-
-                if reduce_item == None || reduce_item.as_ref().unwrap().rule.precedence > item.rule.precedence { 
-                    if DEBUG!() && VERBOSE!() && reduce_item != None { println!("REDUCE_REDUCE CONFLICT"); }
-                    reduce_item = Some(item.clone());
-                }
+                ret.extend(ahead.iter().filter(|x| !ret.contains(x)).cloned().collect::<Vec<_>>());
             }
         }
+        ret
+    }
+}
 
-        if let Some(item) = reduce_item {
-
-            if DEBUG!() { println!("Reduce by: {:}", item.rule); }
-            let mut node = Node {
-                value: NodeType::NonTerminal(NonTerminal {
-                    type_: item.rule.name.clone(),
-                    value_: item.rule.label.clone(),
-                    rule_: item.rule.clone(),
-                }),
-                childs: Vec::new(),
-                index: next
-            };
-            for _ in 0..item.pos {
-                stack.pop();
-                stack.pop();
-
-                let n = stack_out.pop().expect("Not enough node to reduce");
-                node.index = n.index;
-                node.childs.insert(0, n);
-            }
+// the terminals with an action in a state of the automaton
+pub fn expected_terminals(graph: &StateTransferGraph<LRItems, Term>, state: usize) -> Vec<Term> {
+    graph.terminals(state)
+}
 
-            stack_out.push(node);
+// the token at index, past the end of src the end of input
+fn lookahead(src: &[Token], index: usize) -> Token {
+    src.get(index).cloned().unwrap_or_else(|| Token {
+        type_ : FINISH_TOKEN.to_string(),
+        value_: FINISH_TOKEN.to_string(),
+        span_: src.last().map(|x| Span::new(x.span_.end, x.span_.end, x.span_.line, x.span_.column)).unwrap_or_default(),
+        ..Default::default()
+    })
+}
 
-            let curr_state = if let Some(&StackItem::State(s)) = stack.last() { s } else { panic!("Reduce: State not on top of stack !") };
-            stack.push(StackItem::NonTerminal(item.rule.name.clone()));
-            if DEBUG!() && VERBOSE!() { println!("Reduced: Stack: {:?}", stack); }
-            if curr_state == graph.start && item.rule.name == FINISH_TOKEN.to_string() {
-                break 'outer;
-            }
-            if let Some(next_state) = graph.get_transition(curr_state, Term::nonterminal(item.rule.name.as_str())) {
-                stack.push(StackItem::State(next_state));
-            } else {
-                panic!("No transition found");
+// whether the automaton reads all the tokens from the states without an error
+fn viable<A: Automaton>(automaton: &A, states: &[usize], tokens: &[Token]) -> bool {
+    let mut states = states.to_vec();
+    for token in tokens {
+        loop {
+            match automaton.action(*states.last().unwrap(), token) {
+                Some(LRAction::Shift(next_state)) => { states.push(next_state); break; },
+                Some(LRAction::Reduce(rule)) => {
+                    states.truncate(states.len() - rule.expr.terms.len());
+                    if rule.name == FINISH_TOKEN { return true; }
+                    match automaton.goto(*states.last().unwrap(), &rule.name) {
+                        Some(next_state) => states.push(next_state),
+                        None => return false,
+                    }
+                },
+                None => return false,
             }
-
-        } else {
-            panic!("No action")
         }
     }
-
-    if DEBUG!() { println!("Result : {:?}", stack); }
-    Ok(stack_out.pop().unwrap().childs.remove(0))
+    true
 }
 
-
-pub fn parse_with_table(src: &[Token], table: &LRTable) -> Result<Node, ParseError> {
-    // if DEBUG!() { println!("{:}", graph); }
-    
-    #[derive(Debug)]
-    enum StackItem {
-        State(usize), NonTerminal(String), Terminal(Token)
-    }
-    let mut stack = Vec::<StackItem>::new();
-    stack.push(StackItem::State(0));
-
-    let mut stack_out = Vec::new();
+/*
+ * Runs the automaton over src, recovering from syntax errors the way yacc
+ * does: states are popped until one shifts the error token of the grammar,
+ * then tokens are discarded until one has an action. Until 3 tokens are
+ * shifted again, errors are not reported. Where no error rule applies, a
+ * phrase level fix is tried instead: a missing token inserted or the
+ * unexpected one skipped, when the next tokens can then be read.
+ * All the errors are returned, the tree only if there was none.
+ */
+fn drive<A: Automaton>(src: &[Token], automaton: &A) -> Result<Node, Vec<ParseError>> {
+    let mut states = vec![automaton.start()];
+    // the nodes of the symbols between the states
+    let mut nodes: Vec<Node> = Vec::new();
+    let mut errors = Vec::new();
+    let mut recovering = 0;
+    // a token inserted by a phrase level fix, read before src[next]
+    let mut inserted: Option<Token> = None;
 
     let mut next = 0;
-    'outer: loop {
-        let token = if next < src.len() { src[next].clone() } else { Token {
-            type_ : FINISH_TOKEN.to_string(),
-            value_: FINISH_TOKEN.to_string(),
-            line_: 0,
-        } };
-        let curr_state = if let Some(&StackItem::State(s)) = stack.last() { s } else { panic!("State not on top of stack !") };
+    loop {
+        let token = inserted.clone().unwrap_or_else(|| lookahead(src, next));
+        let curr_state = *states.last().unwrap();
 
         if DEBUG!() { println!("#{:} Step: [{:}, {:}]", next, curr_state, token.type_); }
-        // Shift
-
-        match table[curr_state].get(&Term::from(&token)).or(table[curr_state].get(&Term::terminal(token.type_.as_str()))) {
+        match automaton.action(curr_state, &token) {
             Some(LRAction::Shift(next_state)) => {
-                stack.push(StackItem::Terminal(token.clone()));
-                stack.push(StackItem::State(*next_state));
-                stack_out.push(Node {
-                    value: NodeType::Terminal(token.clone()),
+                states.push(next_state);
+                nodes.push(Node {
+                    value: NodeType::Terminal(token),
                     childs: Vec::new(),
                     index: next
                 });
-                next += 1;
-                if DEBUG!() { 
-                    if VERBOSE!() { println!("Shifted: {:?}", stack); }
+                if inserted.take().is_none() { next += 1; }
+                recovering = recovering.max(1) - 1;
+                if DEBUG!() {
+                    if VERBOSE!() { println!("Shifted: {:?}", states); }
                     else { println!("Shifted to {:}", next_state); }
                 }
             },
             Some(LRAction::Reduce(rule)) => {
                 if DEBUG!() { println!("Reduce by: {:}", rule); }
+                let len = rule.expr.terms.len();
                 let mut node = Node {
                     value: NodeType::NonTerminal(NonTerminal {
                         type_: rule.name.clone(),
                         value_: rule.label.clone(),
                         rule_: rule.clone(),
+                        ..Default::default()
                     }),
-                    childs: Vec::new(),
+                    childs: nodes.split_off(nodes.len() - len),
                     index: next
                 };
-                for _ in 0..rule.expr.terms.len() {
-                    stack.pop();
-                    stack.pop();
-
-                    let n = stack_out.pop().expect("Not enough node to reduce");
-                    node.index = n.index;
-                    node.childs.insert(0, n);
+                if let Some(n) = node.childs.first() { node.index = n.index; }
+                node.update_span();
+                states.truncate(states.len() - len);
+
+                let curr_state = *states.last().unwrap();
+                if DEBUG!() && VERBOSE!() { println!("Reduced: Stack: {:?}", states); }
+                if curr_state == automaton.start() && rule.name == FINISH_TOKEN {
+                    nodes.push(node);
+                    break;
                 }
+                match automaton.goto(curr_state, &rule.name) {
+                    Some(next_state) => {
+                        states.push(next_state);
+                        nodes.push(node);
+                    },
+                    None => {
+                        errors.push(ParseError::new(format!("No transition found after reduced: {:}", rule), next));
+                        return Err(errors);
+                    }
+                }
+            },
+            None if recovering == 3 => {
+                // just after the error token, skip the token into it
+                if token.type_ == FINISH_TOKEN { return Err(errors); }
+                if let Some(Node { value: NodeType::Terminal(error), .. }) = nodes.last_mut() { error.span_ = error.span_.to(&token.span_); }
+                if inserted.take().is_none() { next += 1; }
+            },
+            None => {
+                if DEBUG!() { println!("No action for {:} in state {:}", token, curr_state); }
+                // the terminals that could be read instead, as tokens where the unexpected one is
+                let mut candidates: Vec<(Term, Token)> = automaton.terminals(curr_state).into_iter().filter_map(|t| match &t {
+                    Term::Terminal { type_, value } if type_ != ERROR_TOKEN => {
+                        let candidate = Token {
+                            type_: type_.clone(),
+                            value_: value.clone().unwrap_or(type_.clone()),
+                            line_: token.line_,
+                            span_: Span::new(token.span_.start, token.span_.start, token.span_.line, token.span_.column),
+                            ..Default::default()
+                        };
+                        Some((t, candidate))
+                    },
+                    _ => None,
+                }).filter(|(_, t)| viable(automaton, &states, &[t.clone()])).collect();
+                candidates.sort_by_key(|(t, _)| t.to_string());
+                let err = ParseError::unexpected(src, next).expecting(candidates.iter().map(|(t, _)| t.clone()));
+                let error = Token {
+                    type_: ERROR_TOKEN.to_string(),
+                    value_: ERROR_TOKEN.to_string(),
+                    line_: token.line_,
+                    span_: token.span_,
+                    ..Default::default()
+                };
+                let shifts_error = |state: &usize| if let Some(LRAction::Shift(_)) = automaton.action(*state, &error) { true } else { false };
+                if let Some(depth) = states.iter().rposition(shifts_error) {
+                    // panic mode
+                    states.truncate(depth + 1);
+                    nodes.truncate(depth);
+                    if let Some(LRAction::Shift(next_state)) = automaton.action(states[depth], &error) { states.push(next_state); }
+                    nodes.push(Node {
+                        value: NodeType::Terminal(error),
+                        childs: Vec::new(),
+                        index: next
+                    });
+                    if recovering == 0 { errors.push(err); }
+                    recovering = 3;
+                    continue;
+                }
+                if recovering > 0 { return Err(errors); }
 
-                stack_out.push(node);
-
-                let curr_state = if let Some(&StackItem::State(s)) = stack.last() { s } else { panic!("Reduce: State not on top of stack !") };
-                stack.push(StackItem::NonTerminal(rule.name.clone()));
-                if DEBUG!() && VERBOSE!() { println!("Reduced: Stack: {:?}", stack); }
-                if curr_state == 0 && rule.name == FINISH_TOKEN.to_string() {
-                    break 'outer;
+                // phrase level, the fix has to read the next tokens
+                let mut ahead: Vec<Token> = vec![token.clone()];
+                while ahead.len() < 3 && ahead.last().unwrap().type_ != FINISH_TOKEN {
+                    ahead.push(lookahead(src, next + ahead.len() - if inserted.is_some() { 1 } else { 0 }));
                 }
-                if let Some(LRAction::Shift(next_state)) = table[curr_state].get(&Term::nonterminal(rule.name.as_str())) {
-                    stack.push(StackItem::State(*next_state));
+                let missing = candidates.into_iter().filter(|(_, t)| t.type_ != FINISH_TOKEN)
+                    .find(|(_, t)| viable(automaton, &states, &Some(t.clone()).into_iter().chain(ahead.iter().cloned()).collect::<Vec<_>>()));
+                if let Some((term, t)) = missing {
+                    errors.push(err.fixed(Fix::Inserted(term)));
+                    inserted = Some(t);
+                } else if token.type_ != FINISH_TOKEN && viable(automaton, &states, &ahead[1..]) {
+                    errors.push(err.fixed(Fix::Skipped));
+                    if inserted.take().is_none() { next += 1; }
                 } else {
-                    return Err(ParseError {
-                        msg: format!("No transition found after reduced: {:}", rule),
-                        index: next,
-                    });
+                    errors.push(err);
+                    return Err(errors);
                 }
-            },
-            None => {
-                return Err(ParseError {
-                    msg: format!("No action found for token: {:} when state: {:}", token, curr_state),
-                    index: next,
-                });
             }
         }
     }
 
-    if DEBUG!() { println!("Result : {:?}", stack); }
-    Ok(stack_out.pop().unwrap().childs.remove(0))
+    if DEBUG!() { println!("Result : {:?}", states); }
+    if errors.is_empty() { Ok(nodes.pop().unwrap().childs.remove(0)) } else { Err(errors) }
+}
+
+pub fn parse_with_graph(src: &[Token], graph: &StateTransferGraph<LRItems, Term>) -> Result<Node, Vec<ParseError>> {
+    if DEBUG!() && VERBOSE!() { println!("{:}", graph); }
+    drive(src, graph)
+}
+
+pub fn parse_with_table(src: &[Token], table: &LRTable) -> Result<Node, Vec<ParseError>> {
+    drive(src, table)
 }
 
 
+/*
+ * A state of the LR automaton with more than one action on a lookahead.
+ * The lower precedence number wins, at equal precedence the reduce rule
+ * decides: left associative reduces, right associative shifts.
+ * Rules without a declared precedence (0) leave the conflict unresolved, the
+ * action taken is then only the default one.
+ */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Conflict {
+    pub state: usize,
+    pub lookahead: Term,
+    // the competing actions, the one taken first
+    pub actions: Vec<LRAction>,
+    // the items of the state the actions come from
+    pub items: Vec<LRItem>,
+    // the symbols leading from the start state to the state
+    pub prefix: Vec<Term>,
+    pub resolved: bool,
+}
+
+impl Conflict {
+    pub fn is_shift_reduce(&self) -> bool {
+        self.actions.iter().any(|x| if let LRAction::Shift(_) = x { true } else { false })
+    }
+
+    pub fn kind(&self) -> &'static str {
+        if self.is_shift_reduce() { "shift/reduce" } else { "reduce/reduce" }
+    }
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "State {:}: {:} conflict on {:}{:}, {:} {:}", self.state, self.kind(), self.lookahead,
+            if self.prefix.is_empty() { String::new() } else { format!(" after {:}", self.prefix.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(" ")) },
+            if self.resolved { "resolved as" } else { "taking" }, self.actions[0])
+    }
+}
+
+// the shortest sequence of symbols reaching each state
+fn prefixes(graph: &StateTransferGraph<LRItems, Term>) -> Vec<Vec<Term>> {
+    let mut ret: Vec<Option<Vec<Term>>> = vec![None; graph.vertices.len()];
+    let mut queue = VecDeque::new();
+    ret[graph.start] = Some(Vec::new());
+    queue.push_back(graph.start);
+    while let Some(s) = queue.pop_front() {
+        for &e in &graph.vertices[s].out_edges {
+            let edge = &graph.edges[e];
+            if ret[edge.out_vertex].is_none() {
+                let mut prefix = ret[s].clone().unwrap();
+                prefix.push(edge.cost.clone());
+                ret[edge.out_vertex] = Some(prefix);
+                queue.push_back(edge.out_vertex);
+            }
+        }
+    }
+    ret.into_iter().map(|x| x.unwrap_or_default()).collect()
+}
+
 pub fn construct_table(graph: &StateTransferGraph<LRItems, Term>) -> Result<LRTable, String> {
+    Ok(construct_table_with_conflicts(graph).0)
+}
+
+// the table, or the conflicts not resolved by precedence
+pub fn construct_table_strict(graph: &StateTransferGraph<LRItems, Term>) -> Result<LRTable, Vec<Conflict>> {
+    let (table, conflicts) = construct_table_with_conflicts(graph);
+    let unresolved: Vec<Conflict> = conflicts.into_iter().filter(|x| !x.resolved).collect();
+    if unresolved.is_empty() { Ok(table) } else { Err(unresolved) }
+}
+
+pub fn construct_table_with_conflicts(graph: &StateTransferGraph<LRItems, Term>) -> (LRTable, Vec<Conflict>) {
     if DEBUG!() && VERBOSE!() { println!("{:}", graph); }
+    let prefixes = prefixes(graph);
+    let declared = |item: &LRItem| item.rule.precedence > 0;
+    // the item of the highest precedence first, then in a stable order
+    let by_precedence = |items: &mut Vec<&LRItem>| items.sort_by_key(|x| (x.rule.precedence, x.dump()));
     let mut table = LRTable::default();
+    let mut conflicts = Vec::new();
     for s in 0..graph.vertices.len() {
-        let mut map = HashMap::<Term, (Production, LRAction)>::new();
+        let mut shifts = HashMap::<Term, Vec<&LRItem>>::new();
+        let mut reduces = HashMap::<Term, Vec<&LRItem>>::new();
         for (item, ahead) in &*graph.vertices[s].data {
             if item.pos < item.rule.expr.terms.len() {
-                let f = match map.get(&item.rule.expr.terms[item.pos]) {
-                    Some((rule, action @ LRAction::Reduce(..))) => {
-                        if DEBUG!() { println!("DETECTED CONFLICT on {:} {:} : {:} {:}", s, item.rule.expr.terms[item.pos], LRAction::Shift(graph.get_transition(s, item.rule.expr.terms[item.pos].clone()).expect("No transition for item")), action); }
-                        item.rule.precedence < rule.precedence || (item.rule.precedence == rule.precedence && item.rule.associativity == Associativity::Right)
-                    },
-                    Some((rule, _)) => {
-                        item.rule.precedence < rule.precedence || (item.rule.precedence == rule.precedence && item.rule.associativity == Associativity::Right)
-                    },
-                    None => true
-                };
-                if f {
-                    map.insert(item.rule.expr.terms[item.pos].clone(), (item.rule.clone(), LRAction::Shift(graph.get_transition(s, item.rule.expr.terms[item.pos].clone()).expect("No transition for item"))));
-                }
+                shifts.entry(item.rule.expr.terms[item.pos].clone()).or_insert_with(Vec::new).push(item);
             } else {
                 for ahead in ahead {
-                    let f = match map.get(ahead) {
-                        Some((rule, action)) => {
-                            if DEBUG!() { println!("DETECTED CONFLICT on {:} {:} : {:} {:}", s, ahead, LRAction::Reduce(item.rule.clone()), action); }
-                            item.rule.precedence < rule.precedence || (item.rule.precedence == rule.precedence && item.rule.associativity == Associativity::Left)
-                        },
-                        None => true
-                    };
-                    if f {
-                        map.insert(ahead.clone(), (item.rule.clone(), LRAction::Reduce(item.rule.clone())));
-                    }
+                    reduces.entry(ahead.clone()).or_insert_with(Vec::new).push(item);
                 }
             }
         }
-        table.push(map.into_iter().map(|(t, (_, a))| (t, a)).collect());
+        let mut map = HashMap::<Term, LRAction>::new();
+        for (t, _) in &shifts {
+            map.insert(t.clone(), LRAction::Shift(graph.get_transition(s, t.clone()).expect("No transition for item")));
+        }
+        let mut lookaheads: Vec<(Term, Vec<&LRItem>)> = reduces.into_iter().collect();
+        lookaheads.sort_by_key(|(t, _)| t.to_string());
+        for (t, mut items) in lookaheads {
+            by_precedence(&mut items);
+            let reduce = LRAction::Reduce(items[0].rule.clone());
+            let mut resolved = items.iter().all(|x| declared(x)) && items.get(1).map_or(true, |x| x.rule.precedence > items[0].rule.precedence);
+            let mut actions: Vec<LRAction> = items.iter().map(|x| LRAction::Reduce(x.rule.clone())).collect();
+            let mut involved = items.clone();
+            if let Some(shifting) = shifts.get(&t) {
+                let mut shifting = shifting.clone();
+                by_precedence(&mut shifting);
+                let (r, sh) = (&items[0].rule, &shifting[0].rule);
+                resolved &= declared(shifting[0]);
+                let shift = map[&t].clone();
+                if r.precedence < sh.precedence || (r.precedence == sh.precedence && r.associativity == Associativity::Left) {
+                    actions.insert(1, shift);
+                    map.insert(t.clone(), reduce);
+                } else {
+                    actions.insert(0, shift);
+                }
+                involved.extend(shifting);
+            } else {
+                map.insert(t.clone(), reduce);
+            }
+            if actions.len() > 1 {
+                if DEBUG!() { println!("DETECTED CONFLICT on {:} {:} : {:}", s, t, actions.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(", ")); }
+                involved.sort_by_key(|x| x.dump());
+                conflicts.push(Conflict {
+                    state: s,
+                    lookahead: t,
+                    actions: actions,
+                    items: involved.into_iter().cloned().collect(),
+                    prefix: prefixes[s].clone(),
+                    resolved: resolved,
+                });
+            }
+        }
+        table.push(map);
     }
     if DEBUG!() {        
         for (i, map) in table.iter().enumerate() {
@@ -675,5 +925,78 @@ pub fn construct_table(graph: &StateTransferGraph<LRItems, Term>) -> Result<LRTa
             }
         }
     }
-    Ok(table)
-}
\ No newline at end of file
+    (table, conflicts)
+}
+
+/*
+ * A report of the automaton in the way of bison's .output: the conflicts,
+ * the numbered rules, then each state with its items, its actions and the
+ * actions its conflicts discarded, in brackets.
+ */
+pub fn output_report(graph: &StateTransferGraph<LRItems, Term>, table: &LRTable, conflicts: &[Conflict]) -> String {
+    let mut s = String::new();
+    let mut by_state: Vec<Vec<&Conflict>> = vec![Vec::new(); graph.vertices.len()];
+    for c in conflicts {
+        by_state[c.state].push(c);
+    }
+    for (state, cs) in by_state.iter().enumerate() {
+        if cs.is_empty() { continue; }
+        let sr = cs.iter().filter(|x| x.is_shift_reduce()).count();
+        let counts: Vec<String> = vec![(sr, "shift/reduce"), (cs.len() - sr, "reduce/reduce")].into_iter().filter(|x| x.0 > 0).map(|(n, k)| format!("{:} {:}", n, k)).collect();
+        s += &format!("State {:} conflicts: {:}\n", state, counts.join(", "));
+    }
+
+    // rules numbered as they first appear in the states
+    let mut rules: Vec<Production> = Vec::new();
+    for v in &graph.vertices {
+        let mut items: Vec<&LRItem> = v.data.keys().collect();
+        items.sort_by_key(|x| x.dump());
+        for item in items {
+            if !rules.contains(&item.rule) { rules.push(item.rule.clone()); }
+        }
+    }
+    let rule_text = |rule: &Production| format!("{:} -> {:}", rule.name, if rule.expr.terms.is_empty() { String::from("%empty") } else { rule.expr.to_string() });
+    let number = |rule: &Production| rules.iter().position(|x| x == rule).unwrap_or(0);
+    let action_text = |action: &LRAction| match action {
+        LRAction::Shift(next) => format!("shift, and go to state {:}", next),
+        LRAction::Reduce(rule) => format!("reduce using rule {:} ({:})", number(rule), rule.name),
+    };
+    if !s.is_empty() { s += "\n\n"; }
+    s += "Grammar\n\n";
+    for (i, rule) in rules.iter().enumerate() {
+        s += &format!("{:5} {:}\n", i, rule_text(rule));
+    }
+
+    for (state, v) in graph.vertices.iter().enumerate() {
+        s += &format!("\n\nState {:}\n\n", state);
+        let mut items: Vec<String> = v.data.iter().map(|(item, ahead)| {
+            let mut ahead: Vec<String> = ahead.iter().map(|x| x.to_string()).collect();
+            ahead.sort();
+            if ahead.is_empty() { item.dump() } else { format!("{:}  [{:}]", item.dump(), ahead.join(", ")) }
+        }).collect();
+        items.sort();
+        for item in items {
+            s += &format!("    {:}\n", item);
+        }
+        s += "\n";
+        let mut actions: Vec<(&Term, &LRAction)> = table.get(state).map_or(Vec::new(), |x| x.iter().collect());
+        // the terminals first, then the gotos of the non terminals
+        actions.sort_by_key(|(t, _)| (if let Term::NonTerminal { .. } = t { 1 } else { 0 }, t.to_string()));
+        for (t, action) in actions {
+            match (t, action) {
+                (Term::NonTerminal { .. }, LRAction::Shift(next)) => s += &format!("    {:<16} go to state {:}\n", t.to_string(), next),
+                _ => s += &format!("    {:<16} {:}\n", t.to_string(), action_text(action)),
+            }
+        }
+        for c in &by_state[state] {
+            for action in &c.actions[1..] {
+                s += &format!("    {:<16} [{:}]\n", c.lookahead.to_string(), action_text(action));
+            }
+        }
+        if !by_state[state].is_empty() { s += "\n"; }
+        for c in &by_state[state] {
+            s += &format!("    {:} conflict on {:} {:} {:}\n", c.kind(), c.lookahead, if c.resolved { "resolved by precedence as" } else { "not resolved, default" }, action_text(&c.actions[0]));
+        }
+    }
+    s
+}
@@ -1,4 +1,4 @@
-pub use super::{NodeType, Node, NonTerminal};
+pub use super::{NodeType, Node, NonTerminal, ParseError, Fix};
 pub use super::grammar::{Grammar, Production, Expression, Term, Associativity};
 pub use crate::utils::tree::TreeNode;
 pub use crate::lexer::Token;
@@ -1,16 +1,13 @@
 use super::prelude::*;
 
-
-#[derive(Debug, Clone, PartialEq)]
-pub struct ParseError {
-    msg: String,
-    index: usize,
-}
+use std::collections::HashMap;
+use std::collections::HashSet;
 
 #[derive(Debug, Clone)]
 pub struct MetaData {
-    n: usize,
+    // the furthest index a term failed at, and the terminals tried there
     err: usize,
+    expected: Vec<Term>,
     indent: usize,
 }
 
@@ -25,18 +22,21 @@ pub trait Parser {
 impl Parser for Production {
     fn parse(&self, src: &[Token], grammar: &Grammar) -> Result<Node, ParseError> {
         let mut next = 0;
-        let mut n = self.expr.parse(src, &mut next, grammar, &mut MetaData {
-            n: 0,
+        let mut data = MetaData {
             err: 0,
+            expected: Vec::new(),
             indent: 0,
-        })?;
+        };
+        let mut n = self.expr.parse(src, &mut next, grammar, &mut data)
+            .map_err(|_| ParseError::unexpected(src, data.err).expecting(data.expected.clone()))?;
         if let NodeType::InnerNode = n.value {
             n.value = NodeType::NonTerminal(NonTerminal {
                 type_: self.name.clone(),
                 value_: self.label.clone(),
                 rule_: self.clone(),
                 ..Default::default()
-            })
+            });
+            n.update_span();
         } else {
             panic!("Unreached");
         }
@@ -55,7 +55,8 @@ impl ParserInner for Production {
                 value_: self.label.clone(),
                 rule_: self.clone(),
                 ..Default::default()
-            })
+            });
+            n.update_span();
         } else {
             panic!("Unreached");
         }
@@ -97,7 +98,7 @@ impl ParserInner for Term {
                 for r in &grammar.productions {
                     *next = curr;
                     if &r.name == name {
-                        match (r as &ParserInner).parse(src, next, grammar, data) {
+                        match (r as &dyn ParserInner).parse(src, next, grammar, data) {
                             Ok(ret) => return Ok(ret),
                             Err(ParseError { index, .. }) => {
                                 if index > data.err {
@@ -107,25 +108,19 @@ impl ParserInner for Term {
                         }
                     }
                 }
-                Err(ParseError {
-                    msg: format!("No rule matched for {:?}", name),
-                    index: data.err
-                })
+                Err(ParseError::unexpected(src, data.err).expecting(data.expected.clone()))
             },
-            Term::Terminal { type_: ref ty, value: ref _val, .. } => {
-                if let Some(token) = src.get(*next) {
-                    if self.match_token(token) {
-                        *next += 1;
-                        Ok(Node { value: NodeType::Terminal(src.get(*next - 1).unwrap().clone()), childs: Vec::default(), index: *next-1 })
-                    } else { Err(ParseError {
-                        msg: format!("Expected {:?} but found {:?}", ty, src.get(*next)),
-                        index: *next
-                    }) }
-                } else { Err(ParseError {
-                        msg: format!("Cannot read: Expected {:?} but found {:?}", ty, src.get(*next)),
-                        index: *next
-                    })
+            Term::Terminal { .. } => {
+                if let Some(token) = src.get(*next).filter(|x| self.match_token(x)) {
+                    *next += 1;
+                    return Ok(Node { value: NodeType::Terminal(token.clone()), childs: Vec::default(), index: *next-1 });
                 }
+                if *next > data.err {
+                    data.err = *next;
+                    data.expected.clear();
+                }
+                if *next == data.err { data.expected.push(self.clone()); }
+                Err(ParseError::unexpected(src, *next).expecting(Some(self.clone())))
             },
             Term::Group { ref expr, .. } => {
                 let mut ret = Node {
@@ -174,6 +169,218 @@ impl ParserInner for Term {
                 *next = curr;
                 Ok(ret)
             },
+            Term::And { ref expr } | Term::Not { ref expr } => {
+                let curr = *next;
+                let matched = expr.parse(src, next, grammar, data).is_ok();
+                *next = curr;
+                if matched == (if let Term::And { .. } = self { true } else { false }) {
+                    Ok(Node { value: NodeType::InnerNode, childs: Vec::new(), index: curr })
+                } else {
+                    Err(ParseError::unexpected(src, curr))
+                }
+            },
+        }
+    }
+}
+
+/*
+ * Packrat mode: the grammar read as a parsing expression grammar.
+ * The alternatives of a non terminal are an ordered choice, the first one
+ * matching wins and is never reconsidered, and what a non terminal gives at
+ * an index is memoized: each is parsed once there, so the parse stays linear
+ * in the input however much it backtracks. & and ! look ahead without
+ * consuming anything.
+ * A directly left recursive non terminal is climbed: the first of its other
+ * alternatives matching is the start, then it grows by its left recursive
+ * alternatives as long as one matches further. The left recursion is kept in
+ * the tree, no rewriting is needed. Where the non terminal is an operand of
+ * one of its own rules, precedence and associativity bound what it may grow
+ * into, the same way an LR parser resolves its conflicts (see Operand).
+ */
+pub struct Packrat<'a> {
+    src: &'a [Token],
+    rules: HashMap<&'a str, Vec<&'a Production>>,
+    start: &'a str,
+    left_recursive: HashSet<&'a str>,
+    // the node of a non terminal at an index and the index after it, None if it fails there
+    memo: HashMap<(&'a str, usize, Operand), Option<(Node, usize)>>,
+    // the furthest index a terminal failed at, and the terminals tried there
+    err: usize,
+    expected: Vec<Term>,
+    // inside predicates, failures are not what is expected
+    quiet: usize,
+}
+
+/*
+ * Where a non terminal stands in a rule of its own, what it may be.
+ * A rule nested in the first operand of a rule of lower precedence (a higher
+ * number) is reduced first by an LR parser, and so is it at equal precedence
+ * when left associative. In the last operand it is the other way round: it is
+ * shifted when of lower number, or equal and the outer rule right associative.
+ * Only rules overlapping the outer one there are bounded: a rule nested first
+ * if it ends with the non terminal, last if it starts with it.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Operand {
+    Free,
+    Leading(usize),
+    // the precedence of the outer rule and whether it is right associative
+    Trailing(usize, bool),
+}
+
+impl Operand {
+    fn allows(&self, rule: &Production) -> bool {
+        let own = |t: Option<&Term>| if let Some(Term::NonTerminal { name, .. }) = t { name == &rule.name } else { false };
+        match *self {
+            Operand::Free => true,
+            Operand::Leading(p) => !own(rule.expr.terms.last()) || rule.precedence < p
+                || (rule.precedence == p && rule.associativity == Associativity::Left),
+            Operand::Trailing(p, right) => !own(rule.expr.terms.first()) || rule.precedence < p || (rule.precedence == p && right),
+        }
+    }
+}
+
+fn left_recursive(rule: &Production) -> bool {
+    if let Some(Term::NonTerminal { name, .. }) = rule.expr.terms.first() { name == &rule.name } else { false }
+}
+
+impl<'a> Packrat<'a> {
+    // the grammar must be formal (see transform::convert_to_formal_grammar), left recursion and all
+    pub fn new(src: &'a [Token], grammar: &'a Grammar) -> Self {
+        let mut rules: HashMap<&str, Vec<&Production>> = HashMap::new();
+        for p in &grammar.productions {
+            rules.entry(p.name.as_str()).or_insert_with(Vec::new).push(p);
+        }
+        let left_recursive = grammar.productions.iter().filter(|p| left_recursive(p)).map(|p| p.name.as_str()).collect();
+        Packrat {
+            src: src,
+            rules: rules,
+            start: grammar.start_symbol.as_str(),
+            left_recursive: left_recursive,
+            memo: HashMap::new(),
+            err: 0,
+            expected: Vec::new(),
+            quiet: 0,
+        }
+    }
+
+    pub fn parse(&mut self) -> Result<Node, ParseError> {
+        match self.apply(self.start, 0, Operand::Free) {
+            Some((n, end)) if end == self.src.len() => Ok(n),
+            Some((_, end)) => {
+                self.fail(end, Some(Term::terminal(FINISH_TOKEN)));
+                Err(ParseError::unexpected(self.src, self.err).expecting(self.expected.clone()))
+            },
+            None => Err(ParseError::unexpected(self.src, self.err).expecting(self.expected.clone())),
+        }
+    }
+
+    // a failure at index, of a terminal or of a predicate
+    fn fail(&mut self, index: usize, term: Option<Term>) {
+        if self.quiet > 0 { return; }
+        if index > self.err {
+            self.err = index;
+            self.expected.clear();
+        }
+        if index == self.err { self.expected.extend(term); }
+    }
+
+    fn apply(&mut self, name: &'a str, index: usize, operand: Operand) -> Option<(Node, usize)> {
+        let recursive = self.left_recursive.contains(name);
+        let key = (name, index, if recursive { operand } else { Operand::Free });
+        if let Some(ret) = self.memo.get(&key) { return ret.clone(); }
+        // a left recursion not through the first term of a rule fails
+        self.memo.insert(key, None);
+        let ret = if recursive { self.climb(name, index, operand) } else { self.choice(name, index).map(|(_, n, end)| (n, end)) };
+        self.memo.insert(key, ret.clone());
+        if DEBUG!() { println!("Packrat #{:}-{:?} {:}", index, ret.as_ref().map(|x| x.1), name); }
+        ret
+    }
+
+    // the first alternative of name matching at index, but the left recursive ones
+    fn choice(&mut self, name: &'a str, index: usize) -> Option<(&'a Production, Node, usize)> {
+        let rules = self.rules.get(name).cloned().unwrap_or_default();
+        for rule in rules.into_iter().filter(|x| !left_recursive(x)) {
+            if let Some((childs, end)) = self.sequence(&rule.expr.terms, Some(rule), index) {
+                return Some((rule, node(rule, childs, index), end));
+            }
         }
+        None
     }
-}
\ No newline at end of file
+
+    fn climb(&mut self, name: &'a str, index: usize, operand: Operand) -> Option<(Node, usize)> {
+        let (mut rule, mut n, mut end) = self.choice(name, index)?;
+        let rules = self.rules.get(name).cloned().unwrap_or_default();
+        'grow: loop {
+            for &r in rules.iter().filter(|x| left_recursive(x) && operand.allows(x)) {
+                if !Operand::Leading(r.precedence).allows(rule) { continue; }
+                if let Some((rest, after)) = self.sequence(&r.expr.terms[1..], Some(r), end) {
+                    let mut childs = Vec::new();
+                    if r.expr.terms[0].is_unwrap() { childs.extend(n.childs); } else { childs.push(n); }
+                    childs.extend(rest);
+                    rule = r;
+                    n = node(r, childs, index);
+                    end = after;
+                    continue 'grow;
+                }
+            }
+            return Some((n, end));
+        }
+    }
+
+    // the terms of rule from index, the last one bounded if it is the non terminal of rule
+    fn sequence(&mut self, terms: &'a [Term], rule: Option<&'a Production>, index: usize) -> Option<(Vec<Node>, usize)> {
+        let mut childs = Vec::new();
+        let mut next = index;
+        for (i, term) in terms.iter().enumerate() {
+            match term {
+                Term::NonTerminal { name, unwrap } => {
+                    let operand = match rule {
+                        Some(r) if i + 1 == terms.len() && name == &r.name => Operand::Trailing(r.precedence, r.associativity == Associativity::Right),
+                        _ => Operand::Free,
+                    };
+                    let (n, end) = self.apply(name, next, operand)?;
+                    if *unwrap { childs.extend(n.childs); } else { childs.push(n); }
+                    next = end;
+                },
+                Term::Terminal { .. } => {
+                    match self.src.get(next).filter(|x| term.match_token(x)) {
+                        Some(token) => {
+                            childs.push(Node { value: NodeType::Terminal(token.clone()), childs: Vec::new(), index: next });
+                            next += 1;
+                        },
+                        None => {
+                            self.fail(next, Some(term.clone()));
+                            return None;
+                        },
+                    }
+                },
+                Term::And { expr } | Term::Not { expr } => {
+                    if self.lookahead(expr, next) != (if let Term::And { .. } = term { true } else { false }) {
+                        self.fail(next, None);
+                        return None;
+                    }
+                },
+                _ => panic!("Packrat parser needs a formal grammar, found {:}", term),
+            }
+        }
+        Some((childs, next))
+    }
+
+    fn lookahead(&mut self, expr: &'a Expression, index: usize) -> bool {
+        self.quiet += 1;
+        let matched = self.sequence(&expr.terms, None, index).is_some();
+        self.quiet -= 1;
+        matched
+    }
+}
+
+fn node(rule: &Production, childs: Vec<Node>, index: usize) -> Node {
+    let mut n = Node {
+        value: NodeType::NonTerminal(NonTerminal::from(rule)),
+        childs: childs,
+        index: index
+    };
+    n.update_span();
+    n
+}
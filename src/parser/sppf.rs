@@ -0,0 +1,245 @@
+use super::prelude::*;
+
+use std::collections::HashMap;
+
+/*
+ * Shared packed parse forest, what the GLR and Earley parsers build of an input.
+ * A symbol node stands for a non terminal over a range of tokens [start, end),
+ * all its derivations share it: each alternative is packed in it as the rule
+ * used and the forest nodes of its right-hand side. Terminal nodes are the
+ * tokens themselves.
+ * An unambiguous input has one alternative in each node, an ambiguous one
+ * stays polynomial in size however many trees it has.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum ForestNode {
+    Terminal {
+        index: usize,
+        token: Token,
+    },
+    Symbol {
+        name: String,
+        start: usize,
+        end: usize,
+        alternatives: Vec<Packed>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Packed {
+    pub rule: Production,
+    pub childs: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Forest {
+    pub nodes: Vec<ForestNode>,
+    pub root: Option<usize>,
+    terminals: HashMap<usize, usize>,
+    symbols: HashMap<(String, usize, usize), usize>,
+}
+
+impl ForestNode {
+    // the tokens [start, end) under the node
+    pub fn range(&self) -> (usize, usize) {
+        match self {
+            ForestNode::Terminal { index, .. } => (*index, *index + 1),
+            ForestNode::Symbol { start, end, .. } => (*start, *end),
+        }
+    }
+}
+
+impl Forest {
+    pub fn new() -> Self {
+        Forest::default()
+    }
+
+    // the node of the token at index, made once
+    pub fn terminal(&mut self, index: usize, token: &Token) -> usize {
+        if let Some(&id) = self.terminals.get(&index) { return id; }
+        self.nodes.push(ForestNode::Terminal { index: index, token: token.clone() });
+        self.terminals.insert(index, self.nodes.len() - 1);
+        self.nodes.len() - 1
+    }
+
+    // the node of name over [start, end), made once
+    pub fn symbol(&mut self, name: &str, start: usize, end: usize) -> usize {
+        let key = (name.to_string(), start, end);
+        if let Some(&id) = self.symbols.get(&key) { return id; }
+        self.nodes.push(ForestNode::Symbol { name: key.0.clone(), start: start, end: end, alternatives: Vec::new() });
+        self.symbols.insert(key, self.nodes.len() - 1);
+        self.nodes.len() - 1
+    }
+
+    // pack a derivation in a symbol node, false if it was there
+    pub fn pack(&mut self, id: usize, rule: &Production, childs: Vec<usize>) -> bool {
+        if let ForestNode::Symbol { alternatives, .. } = &mut self.nodes[id] {
+            if alternatives.iter().any(|x| &x.rule == rule && x.childs == childs) { return false; }
+            alternatives.push(Packed { rule: rule.clone(), childs: childs });
+            true
+        } else { panic!("Cannot pack a derivation in a terminal node !") }
+    }
+
+    /*
+     * The number of trees of the forest, saturating. A cyclic grammar has
+     * infinitely many, only the derivations without a cycle are counted.
+     */
+    pub fn count(&self) -> u128 {
+        fn count(forest: &Forest, id: usize, memo: &mut HashMap<usize, u128>, visiting: &mut Vec<usize>) -> (u128, Option<usize>) {
+            if let Some(&n) = memo.get(&id) { return (n, None); }
+            if let Some(depth) = visiting.iter().position(|&x| x == id) { return (0, Some(depth)); }
+            let (n, cut) = match &forest.nodes[id] {
+                ForestNode::Terminal { .. } => (1, None),
+                ForestNode::Symbol { alternatives, .. } => {
+                    let depth = visiting.len();
+                    visiting.push(id);
+                    let mut cut = None;
+                    let mut n = 0u128;
+                    for packed in alternatives {
+                        let mut product = 1u128;
+                        for &child in &packed.childs {
+                            let (m, c) = count(forest, child, memo, visiting);
+                            cut = cut.into_iter().chain(c).min();
+                            product = product.saturating_mul(m);
+                        }
+                        n = n.saturating_add(product);
+                    }
+                    visiting.pop();
+                    (n, uncut(cut, depth))
+                },
+            };
+            if cut.is_none() { memo.insert(id, n); }
+            (n, cut)
+        }
+        self.root.map_or(0, |root| count(self, root, &mut HashMap::new(), &mut Vec::new()).0)
+    }
+
+    pub fn is_ambiguous(&self) -> bool {
+        self.count() > 1
+    }
+
+    // at most limit of the trees of the forest
+    pub fn trees(&self, limit: usize) -> Vec<Node> {
+        fn trees(forest: &Forest, id: usize, limit: usize, memo: &mut HashMap<usize, Vec<Node>>, visiting: &mut Vec<usize>) -> (Vec<Node>, Option<usize>) {
+            if let Some(ret) = memo.get(&id) { return (ret.clone(), None); }
+            if let Some(depth) = visiting.iter().position(|&x| x == id) { return (Vec::new(), Some(depth)); }
+            let (ret, cut) = match &forest.nodes[id] {
+                ForestNode::Terminal { index, token } => (vec![Node { value: NodeType::Terminal(token.clone()), childs: Vec::new(), index: *index }], None),
+                ForestNode::Symbol { start, alternatives, .. } => {
+                    let depth = visiting.len();
+                    visiting.push(id);
+                    let mut cut = None;
+                    let mut ret = Vec::new();
+                    for packed in alternatives {
+                        // every combination of the trees of the childs
+                        let mut partial: Vec<Vec<Node>> = vec![Vec::new()];
+                        for &child in &packed.childs {
+                            let (subtrees, c) = trees(forest, child, limit, memo, visiting);
+                            cut = cut.into_iter().chain(c).min();
+                            partial = partial.iter().flat_map(|p| subtrees.iter().map(move |t| { let mut p = p.clone(); p.push(t.clone()); p })).take(limit).collect();
+                        }
+                        ret.extend(partial.into_iter().map(|childs| symbol_node(&packed.rule, *start, childs)));
+                        if ret.len() >= limit { break; }
+                    }
+                    ret.truncate(limit);
+                    visiting.pop();
+                    (ret, uncut(cut, depth))
+                },
+            };
+            if cut.is_none() { memo.insert(id, ret.clone()); }
+            (ret, cut)
+        }
+        self.root.map_or(Vec::new(), |root| trees(self, root, limit, &mut HashMap::new(), &mut Vec::new()).0)
+    }
+
+    /*
+     * The tree the precedence and associativity of the rules choose, as an
+     * LR parser resolving its conflicts would build it:
+     *     the rule of the highest precedence number binds loosest, so it is
+     *     kept at the top, rules without precedence (0) lose
+     *     at equal precedence, left associative keeps the alternative whose
+     *     last child starts the furthest, right associative the nearest
+     * A tie keeps the first alternative found, an alternative only leading
+     * into a cycle is passed over.
+     */
+    pub fn disambiguate(&self) -> Option<Node> {
+        fn choose(forest: &Forest, id: usize, visiting: &mut Vec<usize>) -> Option<Node> {
+            match &forest.nodes[id] {
+                ForestNode::Terminal { index, token } => Some(Node { value: NodeType::Terminal(token.clone()), childs: Vec::new(), index: *index }),
+                ForestNode::Symbol { start, alternatives, .. } => {
+                    if visiting.contains(&id) { return None; }
+                    visiting.push(id);
+                    let split = |packed: &Packed| packed.childs.last().map_or(*start, |&x| forest.nodes[x].range().0) as isize;
+                    let mut ranked: Vec<&Packed> = alternatives.iter().collect();
+                    ranked.sort_by_key(|x| std::cmp::Reverse((x.rule.precedence, if x.rule.associativity == Associativity::Left { split(x) } else { -split(x) })));
+                    let ret = ranked.into_iter().filter_map(|packed| {
+                        let childs = packed.childs.iter().map(|&x| choose(forest, x, visiting)).collect::<Option<Vec<Node>>>()?;
+                        Some(symbol_node(&packed.rule, *start, childs))
+                    }).next();
+                    visiting.pop();
+                    ret
+                },
+            }
+        }
+        choose(self, self.root?, &mut Vec::new())
+    }
+}
+
+/*
+ * Where a cycle was cut below a node, as the depth in the visiting stack of
+ * the node it led back to. A cycle back to the node itself only leaves out
+ * derivations it has no other way to have, a cycle back to a node above
+ * leaves out some it would have elsewhere: its result is not to be memoized.
+ */
+fn uncut(cut: Option<usize>, depth: usize) -> Option<usize> {
+    cut.filter(|&x| x < depth)
+}
+
+fn symbol_node(rule: &Production, start: usize, childs: Vec<Node>) -> Node {
+    let mut node = Node {
+        value: NodeType::NonTerminal(NonTerminal::from(rule)),
+        childs: childs,
+        index: start
+    };
+    node.update_span();
+    node
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer;
+    use super::super::{earleyparser, transform};
+
+    fn grammar(src: &str) -> Grammar {
+        let (rules, _) = lexer::read_config("examples/ebnf/ebnf.lex").unwrap();
+        transform::convert_to_formal_grammar(Grammar::parse(&lexer::tokenize(src, &rules).unwrap()).unwrap())
+    }
+
+    fn tokens(types: &[&str]) -> Vec<Token> {
+        types.iter().map(|&x| Token { type_: x.to_string(), value_: x.to_string(), ..Default::default() }).collect()
+    }
+
+    #[test]
+    fn ambiguous() {
+        let grammar = grammar("E = E \"plus\" E | \"n\" .");
+        let forest = earleyparser::parse_forest(&tokens(&["n", "plus", "n", "plus", "n"]), &grammar).unwrap();
+        assert_eq!(forest.count(), 2);
+        let trees = forest.trees(10);
+        assert_eq!(trees.len(), 2);
+        assert_ne!(trees[0], trees[1]);
+        // (n plus n) plus n
+        let left = trees.iter().find(|x| x.childs[0].childs.len() == 3).unwrap();
+        assert_eq!(forest.disambiguate().as_ref(), Some(left));
+    }
+
+    #[test]
+    fn cycle_not_memoized() {
+        // A inside S is cut where it leads back to S, not where R reaches it
+        let grammar = grammar("R = S | A . S = A | \"n\" . A = S .");
+        let forest = earleyparser::parse_forest(&tokens(&["n"]), &grammar).unwrap();
+        assert_eq!(forest.count(), 2);
+        assert_eq!(forest.trees(10).len(), 2);
+    }
+}
@@ -81,6 +81,7 @@ impl Substitution {
                 };
                 new_node.childs = node.childs.drain(self.pos..self.pos + self.rule.expr.terms.len()).collect();
                 new_node.index = new_node.childs[0].index;
+                new_node.update_span();
                 node.childs.insert(self.pos, new_node);
                 *rule_ = self.source.clone();
                 // println!("Now: {:?}", self.pos..self.pos + rule_.expr.alters[0].terms.len());
@@ -186,6 +187,31 @@ pub fn convert_to_formal_grammar(mut grammar: Grammar) -> Grammar {
                     unwrap: true
                 }
             },
+            // what a predicate looks for becomes a rule of its own
+            Term::And { expr } => {
+                stack.push(Production {
+                    name: name.clone() + "$lookahead#" + &i.to_string(),
+                    label: "#0".to_string(),
+                    expr: *expr,
+                    associativity: pp.associativity,
+                    precedence: pp.precedence
+                });
+                Term::And {
+                    expr: Box::new(Expression { terms: vec![Term::nonterminal(name.clone() + "$lookahead#" + &i.to_string())] })
+                }
+            },
+            Term::Not { expr } => {
+                stack.push(Production {
+                    name: name.clone() + "$lookahead#" + &i.to_string(),
+                    label: "#0".to_string(),
+                    expr: *expr,
+                    associativity: pp.associativity,
+                    precedence: pp.precedence
+                });
+                Term::Not {
+                    expr: Box::new(Expression { terms: vec![Term::nonterminal(name.clone() + "$lookahead#" + &i.to_string())] })
+                }
+            },
             _ => y
         }).collect();
 
@@ -351,6 +377,7 @@ pub fn retrieve_left_recursion(node: Node) -> Node {
             }
             let mut node = out_stack.pop().unwrap().unwrap();
             node.childs = set;
+            node.update_span();
             out_stack.push(Some(node));
         }
     }
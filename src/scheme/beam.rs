@@ -6,6 +6,8 @@ use std::str::FromStr;
 use std::rc::{Rc, Weak};
 use std::cell::RefCell;
 use std::fmt;
+use crate::utils::span::Span;
+use crate::utils::diagnostic::Diagnostic;
 use std::fs::File;
 use std::io::{Write, Read, BufRead};
 
@@ -14,7 +16,9 @@ pub type ValueRef = Weak<RefCell<Datum>>;
 
 #[derive(Debug)]
 pub struct RuntimeError {
-    msg: String
+    pub msg: String,
+    // the source of the expression being evaluated when it failed
+    pub span: Option<Span>,
 }
 
 macro_rules! error {
@@ -26,7 +30,27 @@ macro_rules! error {
 impl RuntimeError {
     pub fn new(s: impl Into<String>) -> Self {
         RuntimeError {
-            msg: s.into()
+            msg: s.into(),
+            span: None,
+        }
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        Diagnostic::error(self.msg.clone()).primary(self.span.unwrap_or_default(), "")
+    }
+
+    // locate the error, unless an inner expression already did
+    pub fn at(mut self, span: Option<Span>) -> Self {
+        if self.span.is_none() { self.span = span; }
+        self
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.span {
+            Some(span) => write!(f, "{:}: {:}", span, self.msg),
+            None => write!(f, "{:}", self.msg),
         }
     }
 }
@@ -44,7 +68,8 @@ pub enum Datum {
     Nil,
     Unspecified,
 
-    Pair(Value, Value),
+    // a pair read from source knows where its list is
+    Pair(Value, Value, Option<Span>),
 
     Vector(Vec<Value>),
 
@@ -91,7 +116,7 @@ impl fmt::Debug for Datum {
             Datum::Nil => write!(f, "()"),
             Datum::Unspecified => write!(f, "<Unspecified>"),
             Datum::Vector(ref v) => write!(f, "#({})", v.iter().fold(String::new(), |s, x| format!("{:}{:?} ", s, x.borrow()))),
-            Datum::Pair(ref a, ref b) => write!(f, "({:?}{:})", a.borrow(), List::from(b.clone()).fold(String::new(), |s, x| format!("{:} {:}", s, match x {
+            Datum::Pair(ref a, ref b, _) => write!(f, "({:?}{:})", a.borrow(), List::from(b.clone()).fold(String::new(), |s, x| format!("{:} {:}", s, match x {
                 ListItem::Item(x) => format!("{:?}", x.borrow()),
                 ListItem::Ellipsis(x) => format!(". {:?}", x.borrow())
             }))),
@@ -120,7 +145,7 @@ impl fmt::Display for Datum {
             Datum::Nil => write!(f, "()"),
             Datum::Unspecified => write!(f, "<Unspecified>"),
             Datum::Vector(ref v) => write!(f, "#({})", v.iter().fold(String::new(), |s, x| format!("{:}{:?} ", s, x.borrow()))),
-            Datum::Pair(ref a, ref b) => write!(f, "({:}{:})", a.borrow(), List::from(b.clone()).fold(String::new(), |s, x| format!("{:} {:}", s, match x {
+            Datum::Pair(ref a, ref b, _) => write!(f, "({:}{:})", a.borrow(), List::from(b.clone()).fold(String::new(), |s, x| format!("{:} {:}", s, match x {
                 ListItem::Item(x) => format!("{:}", x.borrow()),
                 ListItem::Ellipsis(x) => format!(". {:}", x.borrow())
             }))),
@@ -185,21 +210,21 @@ impl Datum {
     }
 
     pub fn is_pair(&self) -> bool {
-        if let Datum::Pair(_, _) = self { true } else { false }
+        if let Datum::Pair(_, _, _) = self { true } else { false }
     }
 
     pub fn as_pair(&self) -> Result<(Value, Value), RuntimeError> {
-        if let Datum::Pair(ref car, ref cdr) = self { 
+        if let Datum::Pair(ref car, ref cdr, _) = self { 
             Ok((car.clone(), cdr.clone())) } else { Err(RuntimeError::new(format!("Expected pair: {:?}", self))) }
     }
 
     pub fn into_pair(self) -> Result<(Value, Value), RuntimeError> {
-        if let Datum::Pair(car, cdr) = self { 
+        if let Datum::Pair(car, cdr, _) = self { 
             Ok((car, cdr)) } else { Err(RuntimeError::new(format!("Expected pair: {:?}", self))) }
     }
 
     pub fn is_list(&self) -> bool {
-        if let Datum::Pair(ref a, ref d) = self {
+        if let Datum::Pair(ref a, ref d, _) = self {
             if d.borrow().is_nil() { true } 
             else {
                 if let ListItem::Ellipsis(_) = List::from(d.clone()).last().unwrap() {
@@ -265,7 +290,7 @@ impl Datum {
     }
 
     pub fn car(&self) -> Result<Value, RuntimeError> {
-        if let Datum::Pair(a, d) = self {
+        if let Datum::Pair(a, d, _) = self {
             Ok(a.clone())
         } else {
             Err(RuntimeError::new(format!("ice: car on non-list : {:?}", self)))
@@ -273,7 +298,7 @@ impl Datum {
     }
 
     pub fn set_car(&mut self, rhs: Value) -> Result<Value, RuntimeError> {
-        if let Datum::Pair(ref mut a, _) = self {
+        if let Datum::Pair(ref mut a, _, _) = self {
             *a = rhs.clone();
             Ok(SymbolTable::unspecified())
         } else {
@@ -282,7 +307,7 @@ impl Datum {
     }
 
     pub fn set_cdr(&mut self, rhs: Value) -> Result<Value, RuntimeError> {
-        if let Datum::Pair(_, ref mut d) = self {
+        if let Datum::Pair(_, ref mut d, _) = self {
             *d = rhs.clone();
             Ok(SymbolTable::unspecified())
         } else {
@@ -291,7 +316,7 @@ impl Datum {
     }
 
     pub fn cdr(&self) -> Result<Value, RuntimeError> {
-        if let Datum::Pair(a, d) = self {
+        if let Datum::Pair(a, d, _) = self {
             Ok(d.clone())
         } else {
             Err(RuntimeError::new(format!("ice: cdr on non-list : {:?}", self)))
@@ -303,7 +328,7 @@ impl Datum {
     }
 
     pub fn len(&self) -> usize {
-        if let Datum::Pair(ref a, ref d) = self {
+        if let Datum::Pair(ref a, ref d, _) = self {
             1 + List::from(d.clone()).collect::<Vec<_>>().len()
         } else {
             0
@@ -366,10 +391,10 @@ impl iter::FromIterator<ListItem> for List {
         while let Some(next) = list.next() {
             if let ListItem::Item(x) = next {
                 if ret.borrow().is_nil() {
-                    ret = Datum::Pair(x, SymbolTable::nil()).wrap();
+                    ret = Datum::Pair(x, SymbolTable::nil(), None).wrap();
                     last = ret.clone();
                 } else {
-                    let d = Datum::Pair(x, SymbolTable::nil()).wrap();
+                    let d = Datum::Pair(x, SymbolTable::nil(), None).wrap();
                     last.borrow_mut().set_cdr(d.clone()).unwrap();
                     last = d;
                 }
@@ -153,7 +153,7 @@ pub fn list(operands: Value) -> Result<Value, RuntimeError> {
 }
 
 pub fn cons(operands: Value) -> Result<Value, RuntimeError> {
-    Ok(Datum::Pair(operands.borrow().car()?, operands.borrow().cadr()?).wrap())
+    Ok(Datum::Pair(operands.borrow().car()?, operands.borrow().cadr()?, None).wrap())
 }
 
 // Primitive type predicate
@@ -4,6 +4,7 @@ use super::symbol::*;
 use std::iter;
 use std::rc::*;
 use crate::utils::*;
+use crate::utils::span::Span;
 
 macro_rules! DATUM {
     ($expr: expr) => {
@@ -17,7 +18,16 @@ macro_rules! CONT {
     };
 }
 
+/*
+ * Errors are located at the last list read from source that was entered,
+ * the innermost expression being evaluated when the error is raised.
+ */
 pub fn eval(src: Value, env: Env) -> Result<Value, RuntimeError> {
+    let mut at = None;
+    run(src, env, &mut at).map_err(|e| e.at(at))
+}
+
+fn run(src: Value, env: Env, at: &mut Option<Span>) -> Result<Value, RuntimeError> {
     let mut cont = Continuation::Return;
     let mut expr = SymbolTable::nil();
     cont = Continuation::EvaluateBegin(src, env.clone(), CONT!(cont));
@@ -38,12 +48,13 @@ pub fn eval(src: Value, env: Env) -> Result<Value, RuntimeError> {
                     Datum::Symbol(ref id) if *level == 0 => {
                         expr = env.borrow().find(id).map_err(|e| if env.borrow().find_syntax(id).is_ok() { RuntimeError::new(format!("Keyword cannot be used as expression : {:?}", id)) } else { e })?;
                     },
-                    Datum::Pair(ref a, ref d) if *level == 0 => {
+                    Datum::Pair(ref a, ref d, span) if *level == 0 => {
+                        if span.is_some() { *at = span; }
                         cont = Continuation::EvaluateList(expr_.clone(), env.clone(), CONT!(cont));
                         cont = Continuation::EvaluateOperator(a.clone(), env.clone(), CONT!(cont));
                         continue 'outer;
                     },
-                    Datum::Pair(ref a, ref d) => {
+                    Datum::Pair(ref a, ref d, _) => {
                         cont = Continuation::QuasiquoteList(List::new().into(), expr_.borrow().cdr()?, env.clone(), *level, CONT!(cont));
                         cont = Continuation::EvaluateExpression(a.clone(), env.clone(), *level, CONT!(cont));
                     },
@@ -58,7 +69,7 @@ pub fn eval(src: Value, env: Env) -> Result<Value, RuntimeError> {
                     Datum::Symbol(ref id) => {
                         expr = env.borrow().find_syntax(id).or(env.borrow().find(id))?;
                     },
-                    Datum::Pair(ref a, ref d) => {
+                    Datum::Pair(ref a, ref d, _) => {
                         cont = Continuation::EvaluateList(expr_.clone(), env.clone(), CONT!(cont));
                         cont = Continuation::EvaluateOperator(a.clone(), env.clone(), CONT!(cont));
                         continue 'outer;
@@ -378,7 +389,7 @@ pub fn eval_pattern_match(pattern: Value, params: Value, env: Env) -> Result<(),
                 env.borrow_mut().put(id.clone(), params.clone());
                 // println!("Env is {:?}", env);
             },
-            Datum::Pair(ref a, ref d) => {
+            Datum::Pair(ref a, ref d, _) => {
                 stack.push((a.clone(), params.borrow().car()?.clone()));
                 stack.push((d.clone(), params.borrow().cdr()?.clone()));
             },
@@ -412,7 +423,7 @@ pub fn check_syntax_rule(pattern: Value, template: Value, literals: Value) -> Re
                     }
                 }
             },
-            Datum::Pair(ref a, ref d) => {
+            Datum::Pair(ref a, ref d, _) => {
                 stack.push((a.clone(), template.borrow().car()?.clone()));
                 stack.push((d.clone(), template.borrow().cdr()?.clone()));
             },
@@ -434,8 +445,8 @@ pub fn eval_template(expr: Value, env:Env) -> Result<Value, RuntimeError> {
             if DEBUG!() { println!("Template matching: Binding {:} to {:?}", id, expr.borrow()); }
             Ok(env.borrow().find(id).unwrap_or(expr.clone()))
         },
-        Datum::Pair(ref a, ref d) => {
-            Ok(Datum::Pair(eval_template(a.clone(), env.clone())?, eval_template(d.clone(), env.clone())?).wrap())
+        Datum::Pair(ref a, ref d, span) => {
+            Ok(Datum::Pair(eval_template(a.clone(), env.clone())?, eval_template(d.clone(), env.clone())?, span).wrap())
         },
         _ => {
             Ok(expr.clone())
@@ -4,6 +4,7 @@ use super::symbol::*;
 use super::number::*;
 use crate::utils::tree;
 use crate::utils::tree::{TreeNode, NodeZipper};
+use crate::utils::span::Span;
 
 use std::rc::Rc;
 use std::cell::RefCell;
@@ -14,7 +15,7 @@ pub fn parse(src: &[Token]) -> Result<Value, RuntimeError> {
     let mut tree = Node::new(Token {
         type_: "LGroup".to_string(),
         value_: "(".to_string(),
-        line_: 0
+        ..Default::default()
     }).zipper();
     for token in src {
         // println!("Parsing : {:}", token);
@@ -39,8 +40,10 @@ pub fn parse(src: &[Token]) -> Result<Value, RuntimeError> {
             },
             "RGroup" => {
                 if tree.check_parent() {
+                    // the list spans to its closing parenthesis
+                    tree.node.value.span_ = tree.node.value.span_.to(&token.span_);
                     tree = tree.parent();
-                } else { error!("Unexpected )")? }
+                } else { Err(RuntimeError::new("Unexpected )").at(Some(token.span_)))? }
             },
             _ => {
                 tree.node.push(Node::new(token.clone()));
@@ -57,7 +60,12 @@ pub fn parse(src: &[Token]) -> Result<Value, RuntimeError> {
     // }
 }
 
+/*
+ * A list read from source carries its span in its first pair only, the
+ * pairs of its tail and those built by the reader for quotes have none.
+ */
 pub fn parse_datum(mut src: Node) -> Result<Value, RuntimeError> {
+    let span = Some(std::mem::replace(&mut src.value.span_, Span::default())).filter(|x| !x.is_empty());
     Ok(match src.value.type_.as_str() {
         // "Eval" => Datum::SpecialForm(SpecialForm::Eval),
         // "Apply" => Datum::SpecialForm(SpecialForm::Apply),
@@ -83,7 +91,7 @@ pub fn parse_datum(mut src: Node) -> Result<Value, RuntimeError> {
                 error!("Error parsing {:?}", src)?
             }
         },
-        "String" => SymbolTable::string(src.value.value_[1..src.value.value_.len()-1].to_string()),
+        "String" => SymbolTable::string(src.value.text().to_string()),
         "Boolean" => SymbolTable::bool(src.value.value_.as_str() == "#t"),
         "Character" => SymbolTable::character(match src.value.value_.as_str() {
             "#\\newline" => '\n',
@@ -93,9 +101,9 @@ pub fn parse_datum(mut src: Node) -> Result<Value, RuntimeError> {
         "LGroup" => if src.len() == 0 {
             SymbolTable::nil()
         } else if src.len() == 2 && src.childs[1].value.type_.as_str() == "Dot" {
-            Datum::Pair(parse_datum(src.childs.remove(0))?, parse_datum(src.childs.remove(0))?).wrap()
+            Datum::Pair(parse_datum(src.childs.remove(0))?, parse_datum(src.childs.remove(0))?, span).wrap()
         } else {
-            Datum::Pair(parse_datum(src.childs.remove(0))?, parse_datum(src)?).wrap()
+            Datum::Pair(parse_datum(src.childs.remove(0))?, parse_datum(src)?, span).wrap()
         },
         "VGroup" => if src.len() == 0 {
             Datum::Vector(vec![]).wrap()
@@ -104,10 +112,10 @@ pub fn parse_datum(mut src: Node) -> Result<Value, RuntimeError> {
         },
         "Dot" => parse_datum(src.childs.remove(0))?,
         // "VGroup" => Datum::Vector(src.childs.into_iter().map(|x| parse_datum(x)).collect()),
-        "Symbolize" => Datum::Pair(SymbolTable::symbol("quote"), List::one(parse_datum(src.clone().childs.into_iter().next().ok_or(()).or(error!("Error parsing quote : {:?}", src))?)?).into()).wrap(),
-        "Template" => Datum::Pair(SymbolTable::symbol("quasiquote"), List::one(parse_datum(src.childs.into_iter().next().ok_or(()).or(error!("Error parsing {:?}", "Quasiquote"))?)?).into()).wrap(),
-        "Comma" => Datum::Pair(SymbolTable::symbol("unquote"), List::one(parse_datum(src.childs.into_iter().next().ok_or(()).or(error!("Error parsing {:?}", "Unquote"))?)?).into()).wrap(),
-        "Comma_Splicing" => Datum::Pair(SymbolTable::symbol("unquote-splicing"), List::one(parse_datum(src.childs.into_iter().next().ok_or(()).or(error!("Error parsing {:?}", "UnquoteSplicing"))?)?).into()).wrap(),
+        "Symbolize" => Datum::Pair(SymbolTable::symbol("quote"), List::one(parse_datum(src.clone().childs.into_iter().next().ok_or(()).or(error!("Error parsing quote : {:?}", src))?)?).into(), None).wrap(),
+        "Template" => Datum::Pair(SymbolTable::symbol("quasiquote"), List::one(parse_datum(src.childs.into_iter().next().ok_or(()).or(error!("Error parsing {:?}", "Quasiquote"))?)?).into(), None).wrap(),
+        "Comma" => Datum::Pair(SymbolTable::symbol("unquote"), List::one(parse_datum(src.childs.into_iter().next().ok_or(()).or(error!("Error parsing {:?}", "Unquote"))?)?).into(), None).wrap(),
+        "Comma_Splicing" => Datum::Pair(SymbolTable::symbol("unquote-splicing"), List::one(parse_datum(src.childs.into_iter().next().ok_or(()).or(error!("Error parsing {:?}", "UnquoteSplicing"))?)?).into(), None).wrap(),
         _ => SymbolTable::symbol(src.value.value_)
     })
 }
\ No newline at end of file
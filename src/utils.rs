@@ -5,6 +5,9 @@ use std::io;
 use std::env;
 
 pub mod tree;
+pub mod model;
+pub mod span;
+pub mod diagnostic;
 
 pub use crate::DEBUG;
 macro_rules! DEBUG {
@@ -91,6 +94,8 @@ macro_rules! check_default {
 
 pub const EPSILON_TOKEN: &'static str = "\0";
 pub const FINISH_TOKEN: &'static str = "$";
+// the terminal of yacc style error rules, written error in a grammar
+pub const ERROR_TOKEN: &'static str = "error";
 
 
 pub fn get_env_var(varname: &str, default: &str) -> String {
@@ -0,0 +1,179 @@
+use std::fmt;
+use super::span::{Span, SourceMap};
+
+/*
+ * Diagnostics of all stages: a message of some severity, labels pointing in
+ * the source and notes. One primary label tells where the problem is, the
+ * secondary ones point at related places.
+ * They are shown either for a terminal:
+ *     error: Multiply definition for class A
+ *      --> test.cl:9:1
+ *       |
+ *     1 | class A {
+ *       | ------- first defined here
+ *     9 | class A {
+ *       | ^^^^^^^
+ *       = note: ...
+ * or as one json object per line for editors.
+ */
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+	Error,
+	Warning,
+	Note,
+}
+
+impl fmt::Display for Severity {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{:}", match self {
+			Severity::Error => "error",
+			Severity::Warning => "warning",
+			Severity::Note => "note",
+		})
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Label {
+	pub span: Span,
+	pub msg: String,
+	pub primary: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Diagnostic {
+	pub severity: Severity,
+	pub msg: String,
+	pub labels: Vec<Label>,
+	pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+	pub fn new<S: Into<String>>(severity: Severity, msg: S) -> Self {
+		Diagnostic { severity: severity, msg: msg.into(), labels: Vec::new(), notes: Vec::new() }
+	}
+
+	pub fn error<S: Into<String>>(msg: S) -> Self {
+		Diagnostic::new(Severity::Error, msg)
+	}
+
+	pub fn warning<S: Into<String>>(msg: S) -> Self {
+		Diagnostic::new(Severity::Warning, msg)
+	}
+
+	// where the problem is, empty spans are left out
+	pub fn primary<S: Into<String>>(self, span: Span, msg: S) -> Self {
+		self.label(span, msg, true)
+	}
+
+	pub fn secondary<S: Into<String>>(self, span: Span, msg: S) -> Self {
+		self.label(span, msg, false)
+	}
+
+	fn label<S: Into<String>>(mut self, span: Span, msg: S, primary: bool) -> Self {
+		if !span.is_empty() {
+			self.labels.push(Label { span: span, msg: msg.into(), primary: primary });
+		}
+		self
+	}
+
+	pub fn note<S: Into<String>>(mut self, note: S) -> Self {
+		self.notes.push(note.into());
+		self
+	}
+
+	pub fn is_error(&self) -> bool {
+		self.severity == Severity::Error
+	}
+
+	// the primary label, else the first one
+	pub fn span(&self) -> Option<Span> {
+		self.labels.iter().find(|x| x.primary).or(self.labels.first()).map(|x| x.span)
+	}
+
+	/*
+	 * For a terminal, the labels are shown under their lines in source order,
+	 * a source without text (e.g. tokens read from a file) only tells lines.
+	 */
+	pub fn render(&self, map: &SourceMap) -> String {
+		let mut s = format!("{:}: {:}", self.severity, self.msg);
+		let mut labels: Vec<&Label> = self.labels.iter().collect();
+		labels.sort_by_key(|x| (x.span.start, !x.primary));
+		if let Some(span) = self.span() {
+			let (line, column) = if map.source().is_empty() { (span.line, span.column) } else { map.locate(span.start) };
+			s += &format!("\n --> {:}:{:}:{:}", map.name(), line, column);
+		}
+		let rows: Vec<_> = labels.iter().filter_map(|x| Some((map.underline(&x.span, if x.primary { '^' } else { '-' })?, &x.msg))).collect();
+		let width = rows.iter().map(|((line, _, _), _)| line.to_string().len()).max().unwrap_or(0);
+		let gutter = " ".repeat(width);
+		if !rows.is_empty() {
+			s += &format!("\n{:} |", gutter);
+			let mut last = 0;
+			for ((line, text, marks), msg) in rows {
+				// a line with several labels is shown once
+				if line != last { s += &format!("\n{:>w$} | {:}", line, text, w = width); }
+				s += &format!("\n{:} | {:}", gutter, marks);
+				if !msg.is_empty() { s += &format!(" {:}", msg); }
+				last = line;
+			}
+		}
+		for note in &self.notes {
+			s += &format!("\n{:} = note: {:}", gutter, note);
+		}
+		s
+	}
+
+	// one line of json, with the file of the source and the spans located in it
+	pub fn to_json(&self, map: &SourceMap) -> String {
+		let mut located = self.clone();
+		if !map.source().is_empty() {
+			for label in located.labels.iter_mut() {
+				label.span = map.span(label.span.start, label.span.end);
+			}
+		}
+		let mut value = serde_json::to_value(&located).unwrap();
+		value["file"] = serde_json::Value::String(map.name().to_string());
+		value.to_string()
+	}
+}
+
+impl fmt::Display for Diagnostic {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self.span() {
+			Some(span) => write!(f, "{:}: {:}: {:}", span, self.severity, self.msg),
+			None => write!(f, "{:}: {:}", self.severity, self.msg),
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagnosticFormat {
+	Human,
+	Json,
+}
+
+/*
+ * Shows the diagnostics of one source in a format and counts the errors.
+ */
+#[derive(Debug, Clone)]
+pub struct Emitter {
+	pub map: SourceMap,
+	pub format: DiagnosticFormat,
+	pub errors: usize,
+}
+
+impl Emitter {
+	pub fn new(map: SourceMap, json: bool) -> Self {
+		Emitter { map: map, format: if json { DiagnosticFormat::Json } else { DiagnosticFormat::Human }, errors: 0 }
+	}
+
+	pub fn emit(&mut self, diagnostic: &Diagnostic) {
+		if diagnostic.is_error() { self.errors += 1; }
+		match self.format {
+			DiagnosticFormat::Human => println!("{:}", diagnostic.render(&self.map)),
+			DiagnosticFormat::Json => println!("{:}", diagnostic.to_json(&self.map)),
+		}
+	}
+}
@@ -0,0 +1,275 @@
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/*
+ * Files of built models: lexer rules, LR tables and LR automata.
+ * A model is saved as yaml or, for paths ending in .bin, in binary. Both start
+ * with a header telling the format version, the kind of model and a hash of
+ * the specs it was built from, so that a stale model is reported as such
+ * instead of failing to deserialize:
+ *     binary  MAGIC, then the header and the model in bincode
+ *     yaml    # coolc <kind> model v<version> <hash>
+ * Loading detects the format from the content, yaml without the header
+ * line is read as is.
+ */
+
+pub const MAGIC: &[u8] = b"COOLCMDL";
+pub const VERSION: u32 = 1;
+const YAML_HEADER: &str = "# coolc ";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ModelKind {
+	// Vec<RegularRule>
+	Lexer,
+	// LRTable
+	LRTable,
+	// StateTransferGraph<LRItems, Term>
+	LRAutomaton,
+}
+
+impl fmt::Display for ModelKind {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{:}", match self {
+			ModelKind::Lexer => "lexer",
+			ModelKind::LRTable => "lrtable",
+			ModelKind::LRAutomaton => "lrautomaton",
+		})
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModelHeader {
+	pub version: u32,
+	pub kind: ModelKind,
+	// hash of the specs, 0 if unknown
+	pub hash: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModelError {
+	pub msg: String,
+}
+
+impl ModelError {
+	fn new<S: Into<String>>(msg: S) -> Self {
+		ModelError { msg: msg.into() }
+	}
+}
+
+impl fmt::Display for ModelError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{:}", self.msg)
+	}
+}
+
+impl From<io::Error> for ModelError {
+	fn from(e: io::Error) -> Self {
+		ModelError::new(e.to_string())
+	}
+}
+
+/*
+ * FNV-1a of the parts, stable across builds unlike the std hasher.
+ */
+pub fn spec_hash(parts: &[&str]) -> u64 {
+	let mut hash: u64 = 0xcbf29ce484222325;
+	for part in parts {
+		for b in part.bytes().chain(Some(0xff)) {
+			hash ^= b as u64;
+			hash = hash.wrapping_mul(0x100000001b3);
+		}
+	}
+	hash
+}
+
+impl ModelHeader {
+	/*
+	 * Check a header read from a model against what the caller wants,
+	 * the hash is only checked when both are known.
+	 */
+	fn check(&self, kind: ModelKind, hash: Option<u64>) -> Result<(), ModelError> {
+		if self.version != VERSION {
+			return Err(ModelError::new(format!("Model format version {:} is not supported by this build (version {:}), rebuild the model", self.version, VERSION)));
+		}
+		if self.kind != kind {
+			return Err(ModelError::new(format!("Model is of kind {:}, expected {:}", self.kind, kind)));
+		}
+		match hash {
+			Some(hash) if self.hash != 0 && self.hash != hash => Err(ModelError::new(format!("Model was built from other specs (hash {:016x}, expected {:016x}), rebuild the model", self.hash, hash))),
+			_ => Ok(())
+		}
+	}
+}
+
+pub fn is_binary_path(path: &str) -> bool {
+	path.ends_with(".bin")
+}
+
+pub fn to_bytes<T: Serialize>(model: &T, kind: ModelKind, hash: u64) -> Result<Vec<u8>, ModelError> {
+	let header = ModelHeader { version: VERSION, kind: kind, hash: hash };
+	let mut bytes = MAGIC.to_vec();
+	bytes.extend(bincode::serialize(&header).map_err(|e| ModelError::new(e.to_string()))?);
+	bytes.extend(bincode::serialize(model).map_err(|e| ModelError::new(e.to_string()))?);
+	Ok(bytes)
+}
+
+pub fn to_yaml<T: Serialize>(model: &T, kind: ModelKind, hash: u64) -> Result<String, ModelError> {
+	let body = serde_yaml::to_string(model).map_err(|e| ModelError::new(e.to_string()))?;
+	Ok(format!("{:}{:} model v{:} {:016x}\n{:}", YAML_HEADER, kind, VERSION, hash, body))
+}
+
+fn yaml_header(line: &str) -> Result<ModelHeader, ModelError> {
+	let malformed = || ModelError::new(format!("Malformed model header {:?}", line.trim_end()));
+	let fields: Vec<&str> = line[YAML_HEADER.len()..].split_whitespace().collect();
+	if fields.len() != 4 || fields[1] != "model" || !fields[2].starts_with('v') { return Err(malformed()); }
+	let kind = match fields[0] {
+		"lexer" => ModelKind::Lexer,
+		"lrtable" => ModelKind::LRTable,
+		"lrautomaton" => ModelKind::LRAutomaton,
+		_ => return Err(malformed())
+	};
+	Ok(ModelHeader {
+		version: fields[2][1..].parse().map_err(|_| malformed())?,
+		kind: kind,
+		hash: u64::from_str_radix(fields[3], 16).map_err(|_| malformed())?,
+	})
+}
+
+pub fn from_bytes<T: DeserializeOwned>(bytes: &[u8], kind: ModelKind, hash: Option<u64>) -> Result<T, ModelError> {
+	if bytes.starts_with(MAGIC) {
+		let mut rest = &bytes[MAGIC.len()..];
+		let header: ModelHeader = bincode::deserialize_from(&mut rest).map_err(|_| ModelError::new("Malformed model header"))?;
+		header.check(kind, hash)?;
+		return bincode::deserialize(rest).map_err(|e| ModelError::new(format!("Invalid {:} model: {:}", kind, e)));
+	}
+	let text = std::str::from_utf8(bytes).map_err(|_| ModelError::new("Not a model file"))?;
+	if text.starts_with(YAML_HEADER) {
+		yaml_header(text.lines().next().unwrap())?.check(kind, hash)?;
+	}
+	serde_yaml::from_str(text).map_err(|e| ModelError::new(format!("Invalid {:} model: {:}", kind, e)))
+}
+
+/*
+ * Save model to path, in binary if is_binary_path(path) else in yaml.
+ */
+pub fn save<T: Serialize>(path: &str, model: &T, kind: ModelKind, hash: u64) -> Result<(), ModelError> {
+	let bytes = if is_binary_path(path) { to_bytes(model, kind, hash)? } else { to_yaml(model, kind, hash)?.into_bytes() };
+	fs::write(path, bytes)?;
+	Ok(())
+}
+
+/*
+ * Load a model of kind from path, in either format. With a hash, a model
+ * built from other specs is an error.
+ */
+pub fn load<T: DeserializeOwned>(path: &str, kind: ModelKind, hash: Option<u64>) -> Result<T, ModelError> {
+	from_bytes(&fs::read(path)?, kind, hash)
+}
+
+/*
+ * Directory of built models, each one in binary under the hash of its specs.
+ * A changed spec has another hash, so its stale model is never looked up.
+ * A model the current build cannot read is built again and replaced.
+ */
+#[derive(Debug, Clone)]
+pub struct ModelCache {
+	dir: PathBuf,
+}
+
+impl ModelCache {
+	pub fn new(dir: &str) -> Self {
+		ModelCache { dir: PathBuf::from(dir) }
+	}
+
+	pub fn path(&self, kind: ModelKind, hash: u64) -> PathBuf {
+		self.dir.join(format!("{:}-{:016x}.bin", kind, hash))
+	}
+
+	pub fn get<T: DeserializeOwned>(&self, kind: ModelKind, hash: u64) -> Option<T> {
+		from_bytes(&fs::read(self.path(kind, hash)).ok()?, kind, Some(hash)).ok()
+	}
+
+	pub fn put<T: Serialize>(&self, model: &T, kind: ModelKind, hash: u64) -> Result<(), ModelError> {
+		fs::create_dir_all(&self.dir)?;
+		// written aside then renamed, a concurrent run never reads half a model
+		let path = self.path(kind, hash);
+		let temp = path.with_extension(format!("{:}.tmp", std::process::id()));
+		fs::write(&temp, to_bytes(model, kind, hash)?)?;
+		fs::rename(&temp, &path)?;
+		Ok(())
+	}
+
+	/*
+	 * The cached model, or the one build returns, which is then cached.
+	 * Failing to cache it is only reported.
+	 */
+	pub fn get_or_build<T: Serialize + DeserializeOwned, F: FnOnce() -> T>(&self, kind: ModelKind, hash: u64, build: F) -> T {
+		if let Some(model) = self.get(kind, hash) { return model; }
+		let model = build();
+		if let Err(e) = self.put(&model, kind, hash) {
+			eprintln!("warning: cannot cache {:} model in {:}: {:}", kind, self.dir.display(), e);
+		}
+		model
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn error<T: std::fmt::Debug>(ret: Result<T, ModelError>) -> String {
+		ret.unwrap_err().msg
+	}
+
+	#[test]
+	fn round_trip() {
+		let model = vec![1u32, 2, 3];
+		let bytes = to_bytes(&model, ModelKind::Lexer, 42).unwrap();
+		assert_eq!(from_bytes::<Vec<u32>>(&bytes, ModelKind::Lexer, Some(42)).unwrap(), model);
+		let yaml = to_yaml(&model, ModelKind::LRTable, 42).unwrap();
+		assert!(yaml.starts_with("# coolc lrtable model v1 000000000000002a\n"));
+		assert_eq!(from_bytes::<Vec<u32>>(yaml.as_bytes(), ModelKind::LRTable, Some(42)).unwrap(), model);
+		// yaml without a header is read as is
+		assert_eq!(from_bytes::<Vec<u32>>(b"- 1\n- 2\n- 3\n", ModelKind::LRTable, Some(42)).unwrap(), model);
+	}
+
+	#[test]
+	fn kind_mismatch() {
+		for bytes in vec![to_bytes(&0u32, ModelKind::LRTable, 1).unwrap(), to_yaml(&0u32, ModelKind::LRTable, 1).unwrap().into_bytes()] {
+			assert_eq!(error(from_bytes::<u32>(&bytes, ModelKind::Lexer, None)), "Model is of kind lrtable, expected lexer");
+		}
+	}
+
+	#[test]
+	fn hash_mismatch() {
+		for bytes in vec![to_bytes(&0u32, ModelKind::Lexer, 1).unwrap(), to_yaml(&0u32, ModelKind::Lexer, 1).unwrap().into_bytes()] {
+			assert_eq!(error(from_bytes::<u32>(&bytes, ModelKind::Lexer, Some(2))),
+				"Model was built from other specs (hash 0000000000000001, expected 0000000000000002), rebuild the model");
+			// an unknown hash on either side is not checked
+			assert!(from_bytes::<u32>(&bytes, ModelKind::Lexer, None).is_ok());
+		}
+		assert!(from_bytes::<u32>(&to_bytes(&0u32, ModelKind::Lexer, 0).unwrap(), ModelKind::Lexer, Some(2)).is_ok());
+	}
+
+	#[test]
+	fn version_mismatch() {
+		let header = ModelHeader { version: VERSION + 1, kind: ModelKind::Lexer, hash: 0 };
+		let mut bytes = MAGIC.to_vec();
+		bytes.extend(bincode::serialize(&header).unwrap());
+		bytes.extend(bincode::serialize(&0u32).unwrap());
+		let expected = format!("Model format version {:} is not supported by this build (version {:}), rebuild the model", VERSION + 1, VERSION);
+		assert_eq!(error(from_bytes::<u32>(&bytes, ModelKind::Lexer, None)), expected);
+		let yaml = format!("# coolc lexer model v{:} 0000000000000000\n0\n", VERSION + 1);
+		assert_eq!(error(from_bytes::<u32>(yaml.as_bytes(), ModelKind::Lexer, None)), expected);
+	}
+
+	#[test]
+	fn malformed_header() {
+		assert_eq!(error(from_bytes::<u32>(b"# coolc parser model v1 0\n0\n", ModelKind::Lexer, None)), "Malformed model header \"# coolc parser model v1 0\"");
+		assert_eq!(error(from_bytes::<u32>(b"COOLCMDL", ModelKind::Lexer, None)), "Malformed model header");
+	}
+}
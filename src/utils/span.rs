@@ -0,0 +1,117 @@
+use std::fmt;
+
+/*
+ * Where something is in a source: byte offsets [start, end) and the line /
+ * column (from 1, column in chars) of start.
+ * Spans come from the tokens of the lexer and grow through the parsers to the
+ * nodes and the values built from them, SourceMap turns one back into the
+ * source line with a caret under it.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub struct Span {
+	pub start: usize,
+	pub end: usize,
+	pub line: usize,
+	pub column: usize,
+}
+
+impl Span {
+	pub fn new(start: usize, end: usize, line: usize, column: usize) -> Self {
+		Span { start: start, end: end, line: line, column: column }
+	}
+
+	// no located text, e.g. the span of an epsilon node
+	pub fn is_empty(&self) -> bool {
+		self.end <= self.start
+	}
+
+	// from the beginning of self to the end of other
+	pub fn to(&self, other: &Span) -> Span {
+		Span { end: other.end, ..*self }
+	}
+}
+
+impl fmt::Display for Span {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{:}:{:}", self.line, self.column)
+	}
+}
+
+/*
+ * A source with the offsets of its lines, to locate offsets and show spans.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+	name: String,
+	src: String,
+	// byte offset of the beginning of each line
+	lines: Vec<usize>,
+}
+
+impl SourceMap {
+	pub fn new<S: Into<String>, T: Into<String>>(name: S, src: T) -> Self {
+		let src = src.into();
+		let lines = Some(0).into_iter().chain(src.match_indices('\n').map(|(i, _)| i + 1)).collect();
+		SourceMap { name: name.into(), src: src, lines: lines }
+	}
+
+	pub fn name(&self) -> &str {
+		&self.name
+	}
+
+	pub fn source(&self) -> &str {
+		&self.src
+	}
+
+	// (line, column) of a byte offset
+	pub fn locate(&self, offset: usize) -> (usize, usize) {
+		let offset = offset.min(self.src.len());
+		let line = match self.lines.binary_search(&offset) { Ok(i) => i, Err(i) => i - 1 };
+		let start = self.lines[line];
+		(line + 1, self.src[start..offset].chars().count() + 1)
+	}
+
+	pub fn span(&self, start: usize, end: usize) -> Span {
+		let (line, column) = self.locate(start);
+		Span::new(start, end, line, column)
+	}
+
+	// text of a line (from 1) without its line break
+	pub fn line(&self, line: usize) -> Option<&str> {
+		let start = *self.lines.get(line.checked_sub(1)?)?;
+		let end = self.lines.get(line).map_or(self.src.len(), |x| x - 1);
+		Some(self.src[start..end].trim_end_matches('\r'))
+	}
+
+	/*
+	 * The first line of span, its text and marks under the spanned text.
+	 * The span is located from its offsets.
+	 */
+	pub fn underline(&self, span: &Span, mark: char) -> Option<(usize, &str, String)> {
+		let (line, column) = self.locate(span.start);
+		let text = self.line(line)?;
+		let skipped: String = text.chars().take(column - 1).map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+		// at least one mark, and none past the end of the line
+		let rest = text.chars().count().saturating_sub(column - 1);
+		let marks = self.src[span.start.min(self.src.len())..span.end.max(span.start).min(self.src.len())].chars().take_while(|c| *c != '\n').count().min(rest).max(1);
+		Some((line, text, skipped + &mark.to_string().repeat(marks)))
+	}
+
+	/*
+	 * The first line of span with carets under the spanned text:
+	 *     3 | let x <- 1 in x
+	 *       |       ^^
+	 */
+	pub fn snippet(&self, span: &Span) -> String {
+		match self.underline(span, '^') {
+			Some((line, text, marks)) => format!("{:} | {:}\n{:} | {:}", line, text, " ".repeat(line.to_string().len()), marks),
+			None => String::new(),
+		}
+	}
+
+	// "name:line:column: msg" followed by the snippet of span
+	pub fn render(&self, span: &Span, msg: &str) -> String {
+		let (line, column) = self.locate(span.start);
+		format!("{:}:{:}:{:}: {:}\n{:}", self.name, line, column, msg, self.snippet(span))
+	}
+}
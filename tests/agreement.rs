@@ -0,0 +1,59 @@
+mod common;
+
+use coolc::parser::*;
+use coolc::parser::grammar::Grammar;
+use coolc::lexer::Token;
+use coolc::utils::read_file;
+use coolc::utils::model::{self, ModelKind};
+
+fn cool() -> (Grammar, Vec<Token>) {
+    let src = read_file("examples/cool/test.cl").unwrap();
+    (common::grammar("examples/cool/cool.ebnf"), common::tokens("examples/cool/cool.lex", &src))
+}
+
+// the tree as the parser binary shows it
+fn tree(n: Node) -> Node {
+    transform::retrieve_left_recursion(transform::retrieve_unwrap(n))
+}
+
+// packrat unwraps while parsing
+fn packrat(tokens: &[Token], grammar: &Grammar) -> Node {
+    transform::retrieve_left_recursion(rdparser::Packrat::new(tokens, grammar).parse().unwrap())
+}
+
+fn lalr(tokens: &[Token]) -> Node {
+    let table = model::load("examples/cool/cool.lrtable", ModelKind::LRTable, None).unwrap();
+    tree(lrparser::parse_with_table(tokens, &table).unwrap())
+}
+
+#[test]
+fn glr_agrees_with_lalr() {
+    let (grammar, tokens) = cool();
+    let forest = glrparser::parse_forest(&tokens, &lrparser::construct_lalr_1(&grammar)).unwrap();
+    assert_eq!(tree(forest.disambiguate().unwrap()), lalr(&tokens));
+}
+
+#[test]
+fn earley_agrees_with_lalr() {
+    let (grammar, tokens) = cool();
+    let forest = earleyparser::parse_forest(&tokens, &grammar).unwrap();
+    // test.cl is ambiguous, its operators are left to precedence
+    assert!(forest.count() > 1);
+    assert_eq!(tree(forest.disambiguate().unwrap()), lalr(&tokens));
+}
+
+#[test]
+fn packrat_agrees_with_lalr() {
+    let (grammar, tokens) = cool();
+    assert_eq!(packrat(&tokens, &grammar), lalr(&tokens));
+}
+
+// 1+2*3-4/5*~6+8=9 of test.cl, by precedence and associativity
+#[test]
+fn packrat_precedence() {
+    let (grammar, _) = cool();
+    let tokens = common::tokens("examples/cool/cool.lex", "class A { f : Bool <- 1+2*3-4/5*~6+8=9; };");
+    let expected = lalr(&tokens);
+    assert_eq!(packrat(&tokens, &grammar), expected);
+    assert_eq!(tree(earleyparser::parse(&tokens, &grammar).unwrap()), expected);
+}
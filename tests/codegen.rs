@@ -0,0 +1,25 @@
+mod common;
+
+#[path = "generated/cool_lexer.rs"]
+mod cool_lexer;
+
+use coolc::lexer::{self, codegen};
+use coolc::utils::read_file;
+
+// the fixture is the output of lexer -c examples/cool/cool.lex -g tests/generated/cool_lexer.rs
+#[test]
+fn fixture_up_to_date() {
+    let (rules, _) = lexer::read_config("examples/cool/cool.lex").unwrap();
+    assert!(codegen::generate(&rules) == read_file("tests/generated/cool_lexer.rs").unwrap(), "regenerate tests/generated/cool_lexer.rs");
+}
+
+#[test]
+fn generated_agrees_with_tokenize() {
+    let src = read_file("examples/cool/test.cl").unwrap();
+    let expected = common::tokens("examples/cool/cool.lex", &src);
+    let generated = cool_lexer::tokenize(&src).unwrap();
+    assert_eq!(generated.len(), 578);
+    assert_eq!(
+        generated.iter().map(|x| (x.type_.to_string(), x.value_.clone(), x.line_)).collect::<Vec<_>>(),
+        expected.iter().map(|x| (x.type_.clone(), x.value_.clone(), x.line_)).collect::<Vec<_>>());
+}
@@ -0,0 +1,23 @@
+// each test crate uses a part of these
+#![allow(dead_code)]
+
+use coolc::lexer::{self, Token};
+use coolc::parser::grammar::Grammar;
+use coolc::parser::transform;
+use coolc::utils::read_file;
+
+// the grammar of an .ebnf file, formal as the parsers take it
+pub fn grammar(path: &str) -> Grammar {
+    grammar_of(&read_file(path).unwrap())
+}
+
+pub fn grammar_of(src: &str) -> Grammar {
+    let (rules, _) = lexer::read_config("examples/ebnf/ebnf.lex").unwrap();
+    let tokens = lexer::tokenize(src, &rules).unwrap();
+    transform::convert_to_formal_grammar(Grammar::parse(&tokens).unwrap())
+}
+
+pub fn tokens(lex: &str, src: &str) -> Vec<Token> {
+    let (rules, _) = lexer::read_config(lex).unwrap();
+    lexer::tokenize(src, &rules).unwrap()
+}
@@ -0,0 +1,77 @@
+mod common;
+
+use coolc::parser::*;
+use coolc::parser::grammar::Grammar;
+use coolc::lexer::Token;
+use coolc::utils::model::{self, ModelKind};
+
+const SOURCE: &str = "class A {
+    f(x : Int) : Int { x + };
+    g() : Int { 1 };
+};
+
+class B inherits {
+    h : Int <- 3
+};
+
+class C {
+    y : Int <- 1 2;
+};
+";
+
+const AFTER_PLUS: &str = "Syntax error at or near RBLOCK = }, expected one of '(', 'case', 'if', 'isvoid', 'let', 'new', 'not', 'while', '{', '~', boolean, integer, object identifier, string";
+
+fn cool() -> (Grammar, Vec<Token>) {
+    (common::grammar("examples/cool/cool.ebnf"), common::tokens("examples/cool/cool.lex", SOURCE))
+}
+
+// line and column of the token of each error
+fn places(tokens: &[Token], errs: &[ParseError]) -> Vec<(usize, usize)> {
+    errs.iter().map(|e| (tokens[e.index].span_.line, tokens[e.index].span_.column)).collect()
+}
+
+#[test]
+fn lr_recovers_at_every_error() {
+    let (grammar, tokens) = cool();
+    let table = model::load("examples/cool/cool.lrtable", ModelKind::LRTable, None).unwrap();
+    let errs = lrparser::parse_with_table(&tokens, &table).unwrap_err();
+    assert_eq!(places(&tokens, &errs), vec![(2, 28), (6, 18), (11, 18)]);
+    assert_eq!(errs[0].describe(&grammar), AFTER_PLUS);
+    assert_eq!(errs[1].describe(&grammar), "Syntax error at or near LBLOCK = {, expected type identifier");
+    assert_eq!(errs[2].describe(&grammar), "Syntax error at or near INT_CONST = 2, expected one of '*', '+', '-', '.', '/', ';', '<', '<=', '=', '@'");
+}
+
+// the automaton is slow to build, both of its parsers are checked on one
+#[test]
+fn lr_automaton_errors() {
+    let (grammar, tokens) = cool();
+    let graph = lrparser::construct_lalr_1(&grammar);
+    let errs = lrparser::parse_with_graph(&tokens, &graph).unwrap_err();
+    assert_eq!(places(&tokens, &errs), vec![(2, 28), (6, 18), (11, 18)]);
+    let err = glrparser::parse_forest(&tokens, &graph).map(|_| ()).unwrap_err();
+    assert_eq!(places(&tokens, &[err.clone()]), vec![(2, 28)]);
+    assert_eq!(err.describe(&grammar), AFTER_PLUS);
+}
+
+#[test]
+fn first_error_agrees() {
+    let (grammar, tokens) = cool();
+    let first = vec![
+        earleyparser::parse_forest(&tokens, &grammar).map(|_| ()).unwrap_err(),
+        rdparser::Packrat::new(&tokens, &grammar).parse().map(|_| ()).unwrap_err(),
+    ];
+    for err in &first {
+        assert_eq!(places(&tokens, &[err.clone()]), vec![(2, 28)]);
+        assert_eq!(err.describe(&grammar), AFTER_PLUS);
+    }
+}
+
+#[test]
+fn ll_expected_set() {
+    let grammar = common::grammar("examples/json/json.ebnf");
+    let tokens = common::tokens("examples/json/json.lex", "{ \"a\": [1, 2,, 3] }");
+    let grammar = transform::left_factor(transform::elimate_left_recursion(grammar));
+    let errs = llparser::parse(&tokens, &grammar).unwrap_err();
+    assert_eq!(places(&tokens, &errs[..1]), vec![(1, 14)]);
+    assert_eq!(errs[0].describe(&grammar), "Syntax error at or near Comma = ,, expected one of '[', 'false', 'null', 'true', '{', integer, number, string");
+}
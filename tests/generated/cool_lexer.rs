@@ -0,0 +1,462 @@
+// Generated lexer, do not edit.
+#![allow(dead_code)]
+
+use std::cmp::Ordering;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub type_: &'static str,
+    pub value_: String,
+    // line of the end of the token
+    pub line_: usize,
+    pub start_: usize,
+    pub end_: usize,
+    pub column_: usize,
+    // value computed by the value actions of the rule
+    pub literal_: Option<Literal>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    Str(String),
+    Int(i64),
+    Float(f64),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LexError {
+    pub msg: String,
+    pub rule: Option<&'static str>,
+    pub text: String,
+    pub line: usize,
+    pub column: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:}:{:}: {:}", self.line, self.column, self.msg)?;
+        if let Some(rule) = self.rule { write!(f, " in rule [{:}]", rule)?; }
+        write!(f, ": {:?}", self.text)
+    }
+}
+
+const NONE: u32 = u32::max_value();
+
+enum Action {
+    Push(usize),
+    Pop,
+    Begin(usize),
+    More,
+    Strip,
+    Unescape,
+    Lower,
+    Int,
+    Float,
+}
+
+struct Rule {
+    name: &'static str,
+    ignore: bool,
+    actions: &'static [Action],
+    nocase: bool,
+    // (word, token type) sorted by word, lowercase words if nocase
+    keywords: &'static [(&'static str, &'static str)],
+}
+
+// transitions hold one row of classes.len() states per state
+struct Mode {
+    name: &'static str,
+    start: usize,
+    classes: &'static [(char, char)],
+    transitions: &'static [u32],
+    accepts: &'static [u32],
+    lives: &'static [u32],
+    eof: u32,
+}
+
+fn some(x: u32) -> Option<usize> {
+    if x == NONE { None } else { Some(x as usize) }
+}
+
+fn unescape(s: &str) -> String {
+    let mut ret = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            ret.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => ret.push('\n'),
+            Some('t') => ret.push('\t'),
+            Some('r') => ret.push('\r'),
+            Some('b') => ret.push('\u{8}'),
+            Some('f') => ret.push('\u{c}'),
+            Some('0') => ret.push('\0'),
+            Some(c) => ret.push(c),
+            None => ret.push('\\'),
+        }
+    }
+    ret
+}
+
+impl Rule {
+    fn token_type(&self, text: &str) -> &'static str {
+        let word = if self.nocase { text.to_lowercase() } else { text.to_string() };
+        match self.keywords.binary_search_by(|&(w, _)| w.cmp(word.as_str())) {
+            Ok(i) => self.keywords[i].1,
+            Err(_) => self.name,
+        }
+    }
+
+    fn literal(&self, text: &str) -> Result<Option<Literal>, String> {
+        let mut value = None;
+        for action in self.actions {
+            let s = if let Some(Literal::Str(s)) = &value { s.as_str() } else { text };
+            value = Some(match action {
+                Action::Strip => {
+                    let mut chars = s.chars();
+                    chars.next();
+                    chars.next_back();
+                    Literal::Str(chars.as_str().to_string())
+                },
+                Action::Unescape => Literal::Str(unescape(s)),
+                Action::Lower => Literal::Str(s.to_lowercase()),
+                Action::Int => Literal::Int(s.parse().map_err(|_| format!("Invalid integer {:?}", s))?),
+                Action::Float => Literal::Float(s.parse().map_err(|_| format!("Invalid float {:?}", s))?),
+                _ => continue
+            });
+        }
+        Ok(value)
+    }
+}
+
+impl Mode {
+    fn step(&self, state: usize, c: char) -> Option<usize> {
+        let class = self.classes.binary_search_by(|&(start, end)| if end < c { Ordering::Less } else if start > c { Ordering::Greater } else { Ordering::Equal }).ok()?;
+        some(self.transitions[state * self.classes.len() + class])
+    }
+
+    // longest match as (rule, length), or the length consumed and the rule still matching
+    fn scan(&self, src: &str) -> Result<(usize, usize), (usize, Option<usize>)> {
+        let mut state = self.start;
+        let mut last = None;
+        let mut consumed = 0;
+        for (i, c) in src.char_indices() {
+            if let Some(next) = self.step(state, c) {
+                state = next;
+                consumed = i + c.len_utf8();
+                if let Some(rule) = some(self.accepts[state]) {
+                    last = Some((rule, consumed));
+                }
+            } else { break; }
+        }
+        last.ok_or((consumed, if consumed > 0 { some(self.lives[state]) } else { None }))
+    }
+}
+
+pub struct Lexer<'a> {
+    src: &'a str,
+    offset: usize,
+    line: usize,
+    column: usize,
+    stack: Vec<usize>,
+    // (start, line, column, rule) of the text kept by more
+    pending: Option<(usize, usize, usize, usize)>,
+    finished: bool,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(src: &'a str) -> Self {
+        Lexer { src: src, offset: 0, line: 1, column: 1, stack: vec![0], pending: None, finished: false }
+    }
+
+    fn advance(&mut self, len: usize) {
+        let text = &self.src[self.offset..self.offset + len];
+        self.offset += len;
+        if let Some(i) = text.rfind('\n') {
+            self.line += text.matches('\n').count();
+            self.column = text[i + 1..].chars().count() + 1;
+        } else {
+            self.column += text.chars().count();
+        }
+    }
+
+    pub fn next_token(&mut self) -> Option<Result<Token, LexError>> {
+        loop {
+            let (start, line, column) = if let Some((start, line, column, _)) = self.pending { (start, line, column) } else {
+                (self.offset, self.line, self.column)
+            };
+            let mode = &MODES[*self.stack.last().unwrap()];
+            let rest = &self.src[self.offset..];
+
+            if rest.is_empty() {
+                if self.finished { return None; }
+                // end of input, in the mode left by the last token
+                self.finished = true;
+                if let Some(rule) = some(mode.eof).map(|r| &RULES[r]) {
+                    self.pending = None;
+                    if rule.ignore { return None; }
+                    return Some(self.token(rule, start, line, column));
+                } else if let Some((_, _, _, rule)) = self.pending.take() {
+                    return Some(Err(LexError { msg: String::from("Unexpected end of input"), rule: Some(RULES[rule].name), text: self.src[start..].to_string(), line: line, column: column, start: start, end: self.offset }));
+                }
+                return None;
+            }
+
+            let rule = match mode.scan(rest) {
+                Ok((rule, len)) => {
+                    self.advance(len);
+                    rule
+                },
+                Err((len, rule)) => {
+                    let len = if len == 0 { rest.chars().next().unwrap().len_utf8() } else { len };
+                    self.advance(len);
+                    self.pending = None;
+                    return Some(Err(LexError {
+                        msg: if mode.name == "INITIAL" { String::from("Unmatched input") } else { format!("Unmatched input in mode {:}", mode.name) },
+                        rule: rule.map(|r| RULES[r].name),
+                        text: self.src[start..self.offset].to_string(),
+                        line: line,
+                        column: column,
+                        start: start,
+                        end: self.offset,
+                    }));
+                }
+            };
+
+            let mut more = false;
+            for action in RULES[rule].actions {
+                match action {
+                    Action::Push(m) => self.stack.push(*m),
+                    Action::Pop => if self.stack.len() > 1 { self.stack.pop(); },
+                    Action::Begin(m) => *self.stack.last_mut().unwrap() = *m,
+                    Action::More => more = true,
+                    _ => (),
+                }
+            }
+            if more {
+                self.pending = Some((start, line, column, rule));
+                continue;
+            }
+            self.pending = None;
+            if RULES[rule].ignore { continue; }
+            return Some(self.token(&RULES[rule], start, line, column));
+        }
+    }
+
+    // token of rule ending at the current offset
+    fn token(&self, rule: &Rule, start: usize, line: usize, column: usize) -> Result<Token, LexError> {
+        let text = self.src[start..self.offset].to_string();
+        match rule.literal(&text) {
+            Ok(literal) => Ok(Token { type_: rule.token_type(&text), value_: text, line_: self.line, start_: start, end_: self.offset, column_: column, literal_: literal }),
+            Err(msg) => Err(LexError { msg: msg, rule: Some(rule.name), text: text, line: line, column: column, start: start, end: self.offset }),
+        }
+    }
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Result<Token, LexError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_token()
+    }
+}
+
+pub fn tokenize(src: &str) -> Result<Vec<Token>, LexError> {
+    Lexer::new(src).collect()
+}
+
+static RULES: [Rule; 34] = [
+    Rule { name: "BOOL_CONST", ignore: false, actions: &[], nocase: false, keywords: &[] },
+    Rule { name: "OBJECTID", ignore: false, actions: &[], nocase: true, keywords: &[("case", "CASE"), ("class", "CLASS"), ("else", "ELSE"), ("esac", "ESAC"), ("fi", "FI"), ("if", "IF"), ("in", "IN"), ("inherits", "INHERITS"), ("isvoid", "ISVOID"), ("let", "LET"), ("loop", "LOOP"), ("new", "NEW"), ("not", "NOT"), ("of", "OF"), ("pool", "POOL"), ("then", "THEN"), ("while", "WHILE")] },
+    Rule { name: "TYPEID", ignore: false, actions: &[], nocase: true, keywords: &[("case", "CASE"), ("class", "CLASS"), ("else", "ELSE"), ("esac", "ESAC"), ("fi", "FI"), ("if", "IF"), ("in", "IN"), ("inherits", "INHERITS"), ("isvoid", "ISVOID"), ("let", "LET"), ("loop", "LOOP"), ("new", "NEW"), ("not", "NOT"), ("of", "OF"), ("pool", "POOL"), ("then", "THEN"), ("while", "WHILE")] },
+    Rule { name: "INT_CONST", ignore: false, actions: &[], nocase: false, keywords: &[] },
+    Rule { name: "STR_CONST", ignore: false, actions: &[Action::Push(2), Action::More], nocase: false, keywords: &[] },
+    Rule { name: "STR_CONST", ignore: false, actions: &[Action::More], nocase: false, keywords: &[] },
+    Rule { name: "STR_CONST", ignore: false, actions: &[Action::Pop], nocase: false, keywords: &[] },
+    Rule { name: "UNTERMINATED_STRING", ignore: false, actions: &[Action::Pop], nocase: false, keywords: &[] },
+    Rule { name: "EOF_IN_STRING", ignore: false, actions: &[], nocase: false, keywords: &[] },
+    Rule { name: "ASSIGN", ignore: false, actions: &[], nocase: false, keywords: &[] },
+    Rule { name: "OP_ADD", ignore: false, actions: &[], nocase: false, keywords: &[] },
+    Rule { name: "OP_SUB", ignore: false, actions: &[], nocase: false, keywords: &[] },
+    Rule { name: "OP_MUL", ignore: false, actions: &[], nocase: false, keywords: &[] },
+    Rule { name: "OP_DIV", ignore: false, actions: &[], nocase: false, keywords: &[] },
+    Rule { name: "OP_NEG", ignore: false, actions: &[], nocase: false, keywords: &[] },
+    Rule { name: "OP_LT", ignore: false, actions: &[], nocase: false, keywords: &[] },
+    Rule { name: "OP_LE", ignore: false, actions: &[], nocase: false, keywords: &[] },
+    Rule { name: "OP_EQ", ignore: false, actions: &[], nocase: false, keywords: &[] },
+    Rule { name: "DELIMITER", ignore: false, actions: &[], nocase: false, keywords: &[] },
+    Rule { name: "COMMA", ignore: false, actions: &[], nocase: false, keywords: &[] },
+    Rule { name: "LTUPLE", ignore: false, actions: &[], nocase: false, keywords: &[] },
+    Rule { name: "RTUPLE", ignore: false, actions: &[], nocase: false, keywords: &[] },
+    Rule { name: "LBLOCK", ignore: false, actions: &[], nocase: false, keywords: &[] },
+    Rule { name: "RBLOCK", ignore: false, actions: &[], nocase: false, keywords: &[] },
+    Rule { name: "DARROW", ignore: false, actions: &[], nocase: false, keywords: &[] },
+    Rule { name: "TYPE_DEC", ignore: false, actions: &[], nocase: false, keywords: &[] },
+    Rule { name: "TYPE_ANN", ignore: false, actions: &[], nocase: false, keywords: &[] },
+    Rule { name: "DISPATCH", ignore: false, actions: &[], nocase: false, keywords: &[] },
+    Rule { name: "COMMENTLINE", ignore: true, actions: &[], nocase: false, keywords: &[] },
+    Rule { name: "COMMENTBLOCK", ignore: true, actions: &[Action::Push(1)], nocase: false, keywords: &[] },
+    Rule { name: "COMMENTBLOCK", ignore: true, actions: &[Action::Pop], nocase: false, keywords: &[] },
+    Rule { name: "COMMENTBLOCK", ignore: true, actions: &[], nocase: false, keywords: &[] },
+    Rule { name: "EOF_IN_COMMENT", ignore: false, actions: &[], nocase: false, keywords: &[] },
+    Rule { name: "WHITESPACE", ignore: true, actions: &[], nocase: false, keywords: &[] },
+];
+
+static MODES: [Mode; 3] = [
+    Mode {
+        name: "INITIAL",
+        start: 0,
+        classes: &[
+            ('\t', '\t'),
+            ('\n', '\n'),
+            ('\r', '\r'),
+            (' ', ' '),
+            ('!', '!'),
+            ('"', '"'),
+            ('#', '\''),
+            ('(', '('),
+            (')', ')'),
+            ('*', '*'),
+            ('+', '+'),
+            (',', ','),
+            ('-', '-'),
+            ('.', '.'),
+            ('/', '/'),
+            ('0', '0'),
+            ('1', '9'),
+            (':', ':'),
+            (';', ';'),
+            ('<', '<'),
+            ('=', '='),
+            ('>', '>'),
+            ('?', '?'),
+            ('@', '@'),
+            ('A', 'A'),
+            ('B', 'D'),
+            ('E', 'E'),
+            ('F', 'K'),
+            ('L', 'L'),
+            ('M', 'Q'),
+            ('R', 'R'),
+            ('S', 'S'),
+            ('T', 'T'),
+            ('U', 'U'),
+            ('V', 'Z'),
+            ('[', '^'),
+            ('_', '_'),
+            ('`', '`'),
+            ('a', 'a'),
+            ('b', 'd'),
+            ('e', 'e'),
+            ('f', 'f'),
+            ('g', 'k'),
+            ('l', 'l'),
+            ('m', 'q'),
+            ('r', 'r'),
+            ('s', 's'),
+            ('t', 't'),
+            ('u', 'u'),
+            ('v', 'z'),
+            ('{', '{'),
+            ('|', '|'),
+            ('}', '}'),
+            ('~', '~'),
+        ],
+        transitions: &[
+            1, 1, 1, 1, NONE, 2, NONE, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, NONE, NONE, 17, 18, 18, 18, 18, 18, 18, 18, 18, 18, 18, 18, NONE, NONE, NONE, 19, 19, 19, 20, 19, 19, 19, 19, 19, 21, 19, 19, 22, NONE, 23, 24,
+            1, 1, 1, 1, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE,
+            NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE,
+            NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, 34, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE,
+            NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE,
+            NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE,
+            NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE,
+            NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE,
+            NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, 33, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE,
+            NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE,
+            NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE,
+            NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE,
+            NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, 12, 12, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE,
+            NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE,
+            NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE,
+            NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, 31, NONE, NONE, NONE, NONE, NONE, NONE, NONE, 32, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE,
+            NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, 30, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE,
+            NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE,
+            NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, 18, 18, NONE, NONE, NONE, NONE, NONE, NONE, NONE, 18, 18, 18, 18, 18, 18, 18, 18, 18, 18, 18, NONE, 18, NONE, 18, 18, 18, 18, 18, 18, 18, 18, 18, 18, 18, 18, NONE, NONE, NONE, NONE,
+            NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, 19, 19, NONE, NONE, NONE, NONE, NONE, NONE, NONE, 19, 19, 19, 19, 19, 19, 19, 19, 19, 19, 19, NONE, 19, NONE, 19, 19, 19, 19, 19, 19, 19, 19, 19, 19, 19, 19, NONE, NONE, NONE, NONE,
+            NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, 19, 19, NONE, NONE, NONE, NONE, NONE, NONE, NONE, 28, 19, 19, 19, 19, 19, 19, 19, 19, 19, 19, NONE, 19, NONE, 28, 19, 19, 19, 19, 19, 19, 19, 19, 19, 19, 19, NONE, NONE, NONE, NONE,
+            NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, 19, 19, NONE, NONE, NONE, NONE, NONE, NONE, NONE, 19, 19, 19, 19, 19, 19, 25, 19, 19, 19, 19, NONE, 19, NONE, 19, 19, 19, 19, 19, 19, 19, 25, 19, 19, 19, 19, NONE, NONE, NONE, NONE,
+            NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE,
+            NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE,
+            NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE,
+            NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, 19, 19, NONE, NONE, NONE, NONE, NONE, NONE, NONE, 19, 19, 19, 19, 19, 19, 19, 19, 19, 26, 19, NONE, 19, NONE, 19, 19, 19, 19, 19, 19, 19, 19, 19, 19, 26, 19, NONE, NONE, NONE, NONE,
+            NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, 19, 19, NONE, NONE, NONE, NONE, NONE, NONE, NONE, 19, 19, 27, 19, 19, 19, 19, 19, 19, 19, 19, NONE, 19, NONE, 19, 19, 27, 19, 19, 19, 19, 19, 19, 19, 19, 19, NONE, NONE, NONE, NONE,
+            NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, 19, 19, NONE, NONE, NONE, NONE, NONE, NONE, NONE, 19, 19, 19, 19, 19, 19, 19, 19, 19, 19, 19, NONE, 19, NONE, 19, 19, 19, 19, 19, 19, 19, 19, 19, 19, 19, 19, NONE, NONE, NONE, NONE,
+            NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, 19, 19, NONE, NONE, NONE, NONE, NONE, NONE, NONE, 19, 19, 19, 19, 29, 19, 19, 19, 19, 19, 19, NONE, 19, NONE, 19, 19, 19, 19, 19, 29, 19, 19, 19, 19, 19, 19, NONE, NONE, NONE, NONE,
+            NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, 19, 19, NONE, NONE, NONE, NONE, NONE, NONE, NONE, 19, 19, 19, 19, 19, 19, 19, 26, 19, 19, 19, NONE, 19, NONE, 19, 19, 19, 19, 19, 19, 19, 19, 26, 19, 19, 19, NONE, NONE, NONE, NONE,
+            NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE,
+            NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE,
+            NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE,
+            33, NONE, NONE, 33, 33, 33, 33, 33, 33, 33, 33, 33, 33, 33, 33, 33, 33, 33, 33, 33, 33, 33, 33, 33, 33, 33, 33, 33, 33, 33, 33, 33, 33, 33, 33, 33, 33, 33, 33, 33, 33, 33, 33, 33, 33, 33, 33, 33, 33, 33, 33, 33, 33, 33,
+            NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE,
+        ],
+        accepts: &[NONE, 33, 4, 20, 21, 12, 10, 19, 11, 27, 13, 3, 3, 25, 18, 15, 17, 26, 2, 1, 1, 1, 22, 23, 14, 1, 1, 0, 1, 1, 24, 9, 16, 28, 29],
+        lives: &[0, 33, 4, 20, 21, 12, 10, 19, 11, 27, 13, 3, 3, 25, 18, 9, 17, 26, 2, 1, 0, 0, 22, 23, 14, 0, 0, 0, 0, 0, 24, 9, 16, 28, 29],
+        eof: NONE,
+    },
+    Mode {
+        name: "COMMENT",
+        start: 0,
+        classes: &[
+            ('\0', '\''),
+            ('(', '('),
+            (')', ')'),
+            ('*', '*'),
+            ('+', '\u{10ffff}'),
+        ],
+        transitions: &[
+            1, 2, 1, 3, 1,
+            NONE, NONE, NONE, NONE, NONE,
+            NONE, NONE, NONE, 5, NONE,
+            NONE, NONE, 4, NONE, NONE,
+            NONE, NONE, NONE, NONE, NONE,
+            NONE, NONE, NONE, NONE, NONE,
+        ],
+        accepts: &[NONE, 31, 31, 31, 30, 29],
+        lives: &[29, 31, 29, 30, 30, 29],
+        eof: 32,
+    },
+    Mode {
+        name: "STRING",
+        start: 0,
+        classes: &[
+            ('\0', '\t'),
+            ('\n', '\n'),
+            ('\u{b}', '\u{c}'),
+            ('\r', '\r'),
+            ('\u{e}', '\u{1f}'),
+            (' ', '!'),
+            ('"', '"'),
+            ('#', '['),
+            ('\\', '\\'),
+            (']', '~'),
+            ('\u{7f}', '\u{10ffff}'),
+        ],
+        transitions: &[
+            1, 2, 1, 3, 1, 1, 4, 1, 5, 1, 1,
+            NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE,
+            NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE,
+            NONE, 2, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE,
+            NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE,
+            NONE, 1, NONE, 6, NONE, 1, 1, 1, 1, 1, NONE,
+            NONE, 1, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE, NONE,
+        ],
+        accepts: &[NONE, 5, 7, 5, 6, NONE, NONE],
+        lives: &[5, 5, 7, 5, 6, 5, 5],
+        eof: 8,
+    },
+];
@@ -0,0 +1,46 @@
+mod common;
+
+use coolc::parser::*;
+use coolc::parser::lrparser::LRMerge;
+use coolc::lexer::Token;
+
+// LR(1) but not LALR(1): the states after A E and B E share a core
+const GRAMMAR: &str = "S = \"A\" E \"C\" | \"A\" F \"D\" | \"B\" F \"C\" | \"B\" E \"D\" . E = \"E\" . F = \"E\" .";
+
+fn tokens(types: &[&str]) -> Vec<Token> {
+    types.iter().map(|&x| Token { type_: x.to_string(), value_: x.to_string(), ..Default::default() }).collect()
+}
+
+fn name(node: &Node) -> &str {
+    match &node.value {
+        NodeType::NonTerminal(x) => &x.type_,
+        NodeType::Terminal(x) => &x.type_,
+        _ => "",
+    }
+}
+
+#[test]
+fn lalr_conflicts() {
+    let graph = lrparser::construct_lr_1_merging(&common::grammar_of(GRAMMAR), LRMerge::LALR);
+    let conflicts = lrparser::construct_table_strict(&graph).unwrap_err();
+    assert!(conflicts.iter().all(|x| x.kind() == "reduce/reduce"));
+}
+
+#[test]
+fn lr1_splits() {
+    let grammar = common::grammar_of(GRAMMAR);
+    for &merge in &[LRMerge::Canonical, LRMerge::Minimal] {
+        let table = lrparser::construct_table_strict(&lrparser::construct_lr_1_merging(&grammar, merge)).unwrap();
+        let tree = lrparser::parse_with_table(&tokens(&["B", "E", "C"]), &table).unwrap();
+        assert_eq!(tree.childs.iter().map(name).collect::<Vec<_>>(), vec!["B", "F", "C"], "{:?}", merge);
+    }
+}
+
+#[test]
+fn minimal_keeps_lalr_states() {
+    let grammar = common::grammar("examples/cool/cool.ebnf");
+    let states = |merge| lrparser::construct_lr_1_merging(&grammar, merge).vertices.len();
+    assert_eq!(states(LRMerge::LALR), 167);
+    assert_eq!(states(LRMerge::Minimal), 167);
+    assert_eq!(states(LRMerge::Canonical), 927);
+}